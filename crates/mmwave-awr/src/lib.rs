@@ -13,16 +13,23 @@ use async_nats::{
 use async_trait::async_trait;
 use connection::Connection;
 use egui::{TextEdit, Ui};
-use futures::StreamExt;
+use futures::{FutureExt, StreamExt};
 use mmwave_core::{
     address::ServerAddress,
+    background::{Background, BackgroundModel, BackgroundParams},
     config::Configuration,
     devices::DeviceDescriptor,
-    message::{Id, Message, Tag, TagsToSubject},
-    nats::get_store,
+    ego::MovingMount,
+    ground::Plane,
+    message::Id,
+    nats::{get_config, get_store},
     point::Point,
     pointcloud::PointCloud,
+    pubsub::{Publisher, Received, Subscription},
+    subject::SubjectFilter,
+    telemetry::{DeviceState, Pose, VitalSigns},
     transform::Transform,
+    wire::Encoding,
 };
 use serde::{Deserialize, Deserializer, Serialize};
 use std::{
@@ -49,7 +56,9 @@ pub struct AwrDescriptor {
     pub model: Model,   // Model of the USB device
     pub config: String, // Configuration string to initialize device
     pub config_path: String,
-    pub transform: Transform, // Transform of this AWR device
+    pub transform: Transform,         // Transform of this AWR device
+    pub encoding: Encoding,           // Encoding of published messages
+    pub background: BackgroundParams, // Static background to subtract
 }
 
 #[derive(Deserialize)]
@@ -59,6 +68,10 @@ struct AwrDescriptorHelper {
     config: Option<String>,
     transform: Transform,
     config_path: Option<String>,
+    #[serde(default)]
+    encoding: Encoding,
+    #[serde(default)]
+    background: BackgroundParams,
 }
 
 impl Eq for AwrDescriptor {}
@@ -116,6 +129,8 @@ impl<'de> Deserialize<'de> for AwrDescriptor {
             config,
             config_path,
             transform: helper.transform,
+            encoding: helper.encoding,
+            background: helper.background,
         })
     }
 }
@@ -165,6 +180,8 @@ impl DeviceDescriptor for AwrDescriptor {
             };
         });
         self.transform.ui(ui);
+        self.encoding.ui(ui);
+        ui.collapsing("background", |ui| self.background.ui(ui));
 
         ui.group(|ui| {
             ui.horizontal(|ui| {
@@ -191,6 +208,10 @@ impl DeviceDescriptor for AwrDescriptor {
         Some(self.transform.clone())
     }
 
+    fn set_transform(&mut self, transform: Transform) {
+        self.transform = transform;
+    }
+
     fn position(&self) -> Option<Point> {
         Some(self.transform.apply([0.0, 0.0, 0.0].into()).into())
     }
@@ -217,7 +238,7 @@ async fn start_awr(
             return Err(String::from("lost connection to nats").into());
         }
 
-        if let Err(e) = run_awr(
+        let result = run_awr(
             &client,
             &store,
             &mut entries,
@@ -226,8 +247,13 @@ async fn start_awr(
             address,
         )
         .await
-        {
+        .map_err(|e| e.to_string());
+        if let Err(e) = result {
             error!(error=%e, "awr stopped running");
+            let publisher = Publisher::new(client.clone(), id);
+            if let Err(e) = publisher.error(descriptor.title(), e).await {
+                warn!(error=%e, "failed to report awr error");
+            }
         }
         interval.tick().await;
     }
@@ -246,41 +272,169 @@ async fn run_awr(
     let mut connection = Connection::try_open(descriptor.serial.clone(), descriptor.model)?;
     connection = connection.send_command(descriptor.config.clone())?;
 
+    let mut publisher = Publisher::new(client.clone(), id).with_encoding(descriptor.encoding);
+    publisher
+        .status(DeviceState::Running, descriptor.title(), None)
+        .await?;
+
+    // Sensor to world, resolved through the configured frame tree
+    let configuration = get_config(store).await?;
+    let mut transform = match &configuration {
+        Some(configuration) => configuration.sensor_to_world_or(id, &descriptor.transform),
+        None => descriptor.transform.clone(),
+    };
+    let mut floor = configuration.as_ref().map(|c| c.floor).unwrap_or_default();
+
+    // On a moving platform the transform instead follows the published poses
+    let pose_source = configuration.as_ref().and_then(|c| c.pose_source(id));
+    let mut mount = configuration
+        .as_ref()
+        .filter(|_| pose_source.is_some())
+        .map(|configuration| moving_mount(configuration, &descriptor, id));
+    let mut poses = match pose_source {
+        Some(source) => {
+            let filter = SubjectFilter::all().id(source);
+            Some(Subscription::<Pose>::new(client, filter).await?)
+        }
+        None => None,
+    };
+
+    let mut background = load_background(&publisher, &descriptor).await?;
+
     loop {
         yield_now().await;
-        select! {
+        let (reload, learnt) = select! {
              Some(config) = entries.next() => {
-                 if let Err(()) = maintain_config(config?, &mut descriptor, id.clone()) {
+                 let config = config?;
+                 let configuration = serde_json::from_slice::<Configuration>(&config.value).ok();
+                 let previous = descriptor.background.clone();
+                 if let Err(()) = maintain_config(config, &mut descriptor, id.clone()) {
                      info!("restarting awr device with new config");
                      return Ok(());
                  }
+                 publisher.set_encoding(descriptor.encoding);
+                 if let Some(configuration) = configuration {
+                     if configuration.pose_source(id) != pose_source {
+                         info!("restarting awr device with new pose source");
+                         return Ok(());
+                     }
+                     transform = configuration.sensor_to_world_or(id, &descriptor.transform);
+                     floor = configuration.floor;
+                     if let Some(mount) = mount.as_mut() {
+                         *mount = MovingMount {
+                             poses: std::mem::take(&mut mount.poses),
+                             ..moving_mount(&configuration, &descriptor, id)
+                         };
+                     }
+                 }
+                 (descriptor.background != previous, None)
+            }
+            Some(received) = next_pose(&mut poses) => {
+                if let Some(mount) = mount.as_mut() {
+                    mount.poses.push(received.content);
+                    // Odometry usually outpaces frames, so catch up on any queued
+                    while let Some(Some(received)) = next_pose(&mut poses).now_or_never() {
+                        mount.poses.push(received.content);
+                    }
+                }
+                (false, None)
             }
-            result = maintain_connection(&mut connection, client, id.clone(), descriptor.transform.clone()) => {
+            result = maintain_connection(&mut connection, &publisher, transform.clone(), mount.as_ref(), &floor, &mut background) => {
                 match result {
-                    Ok(_) => {  },
+                    Ok(learnt) => (false, learnt),
                     Err(e) => {
                         error!("Unable to publish to client");
                         return Err(e);
                     },
                 }
             }
+        };
+        // Awaited outside of select, as its output holds a non Send error
+        if reload {
+            background = load_background(&publisher, &descriptor).await?;
+        }
+        if let Some(learnt) = learnt {
+            save_background(&publisher, &descriptor, &learnt).await?;
+        }
+    }
+}
+
+/// Where a device sits on its moving platform, with no poses yet
+fn moving_mount(
+    configuration: &Configuration,
+    descriptor: &AwrDescriptor,
+    id: Id,
+) -> MovingMount {
+    let parent = match configuration.parent_to_world(id) {
+        Ok(parent) => parent.unwrap_or_default(),
+        Err(e) => {
+            warn!(error=%e, "Unable to resolve the parent frame of the AWR");
+            Transform::default()
+        }
+    };
+    MovingMount::new(parent, descriptor.transform.clone())
+}
+
+/// The next pose of the platform, never if the device does not move
+async fn next_pose(poses: &mut Option<Subscription<Pose>>) -> Option<Received<Pose>> {
+    match poses {
+        Some(poses) => poses.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// The saved background of the device, or a model that learns it. A
+/// background that cannot be loaded is reported and learnt again.
+async fn load_background(
+    publisher: &Publisher,
+    descriptor: &AwrDescriptor,
+) -> Result<BackgroundModel, Box<dyn Error>> {
+    let params = descriptor.background.clone();
+    match BackgroundModel::load(params.clone()) {
+        Ok(model) => Ok(model),
+        Err(e) => {
+            publisher.error(descriptor.title(), e.to_string()).await?;
+            Ok(BackgroundModel::new(params))
         }
     }
 }
 
+async fn save_background(
+    publisher: &Publisher,
+    descriptor: &AwrDescriptor,
+    background: &Background,
+) -> Result<(), Box<dyn Error>> {
+    let detail = format!("Learnt background of {} cells", background.cells.len());
+    info!("{}", detail);
+    if !descriptor.background.path.is_empty() {
+        if let Err(e) = background.save(&descriptor.background.path) {
+            publisher.error(descriptor.title(), e.to_string()).await?;
+        }
+    }
+    publisher
+        .status(DeviceState::Running, descriptor.title(), Some(detail))
+        .await?;
+    Ok(())
+}
+
+/// Publishes the next frame, returning the background if it finished
+/// learning it. On a moving platform frames are placed by the pose at the
+/// time they were captured, and dropped until it is known.
 async fn maintain_connection(
     connection: &mut Connection,
-    client: &Client,
-    id: Id,
+    publisher: &Publisher,
     transform: Transform,
-) -> Result<(), Box<dyn Error>> {
+    mount: Option<&MovingMount>,
+    floor: &Plane,
+    background: &mut BackgroundModel,
+) -> Result<Option<Background>, Box<dyn Error>> {
     yield_now().await;
     let frame = match connection.read_frame() {
         Ok(frame) => frame,
         Err(e) => match e {
             error::RadarReadError::ParseError(e) => {
                 warn!(error=%e, "AWR parse error, this is usually fine");
-                return Ok(());
+                return Ok(None);
             }
             other => {
                 warn!(error=%other, "AWR other error");
@@ -288,29 +442,30 @@ async fn maintain_connection(
             }
         },
     };
-    let mut message = Message {
-        content: mmwave_core::message::MessageContent::PointCloud({
-            let (i1, i2) = Into::<PointCloud>::into(frame)
-                .points
-                .iter_mut()
-                .map(|&mut pt| {
-                    let v = pt.v;
-                    let mut pt: Point = transform.apply(pt.into()).into();
-                    let l = format!("{}", id);
-                    pt.v = v;
-                    (pt, l)
-                })
-                .unzip();
-
-            PointCloud::from((i1, i2))
-        }),
-        tags: Vec::from([Tag::Pointcloud, Tag::FromId(id)]),
-        timestamp: chrono::Utc::now(),
+    // Only the vital signs firmware reports these
+    let targets = frame.vital_signs();
+    // The background is in the sensor frame, so is subtracted before moving
+    let (pointcloud, learnt) = background.apply(frame.into());
+    let mut pointcloud = pointcloud
+        .with_source(publisher.id())
+        .with_origin(Some(publisher.id()));
+    let placed = match mount {
+        Some(mount) => mount.apply(&mut pointcloud),
+        None => {
+            transform.apply_to_cloud(&mut pointcloud);
+            true
+        }
     };
-    let subject = message.tags.clone().to_subject();
-    let payload = bincode::serialize(&message)?.into();
-    client.publish(subject, payload).await?;
-    Ok(())
+    if !targets.is_empty() {
+        let time = pointcloud.time;
+        publisher.publish(VitalSigns { time, targets }).await?;
+    }
+    if !placed {
+        debug!(time=%pointcloud.time, "No pose of the platform for frame, dropping it");
+        return Ok(learnt);
+    }
+    publisher.publish(pointcloud.with_heights(floor)).await?;
+    Ok(learnt)
 }
 
 fn maintain_config(entry: Entry, descriptor: &mut AwrDescriptor, id: Id) -> Result<(), ()> {
@@ -342,6 +497,16 @@ fn maintain_config(entry: Entry, descriptor: &mut AwrDescriptor, id: Id) -> Resu
             descriptor.transform = updated_desc.transform.clone();
         }
 
+        if descriptor.encoding != updated_desc.encoding {
+            info!("Updated AWR descriptor encoding");
+            descriptor.encoding = updated_desc.encoding;
+        }
+
+        if descriptor.background != updated_desc.background {
+            info!("Updated AWR descriptor background");
+            descriptor.background = updated_desc.background.clone();
+        }
+
         if descriptor.config != updated_desc.config {
             info!("Updated AWR descriptor config file");
             debug!(oldConfig=%descriptor.config, newConfig=%updated_desc.config);
@@ -1,5 +1,5 @@
 use super::error::ParseError;
-use mmwave_core::pointcloud::PointCloud;
+use mmwave_core::{pointcloud::PointCloud, telemetry::VitalSign};
 use serde::{Deserialize, Serialize};
 
 pub trait FromBytes
@@ -238,6 +238,7 @@ impl FrameBody {
                     tmp_dig0_sens: u16::from_bytes(&bytes[24..26])?,
                     tmp_dig1_sens: u16::from_bytes(&bytes[26..28])?,
                 },
+                TlvType::VitalSigns => TlvBody::VitalSigns(VitalSignsTlv::from_bytes(bytes)?),
             };
 
             offset += tlv_header.length as usize;
@@ -281,6 +282,74 @@ pub enum TlvBody {
         tmp_dig0_sens: u16,
         tmp_dig1_sens: u16,
     },
+    VitalSigns(VitalSignsTlv),
+}
+
+/// Output of the vital signs firmware for one target, whose phase is filtered
+/// into separate breathing and heart waveforms
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct VitalSignsTlv {
+    pub id: u16,
+    pub range_bin: u16,
+    pub breath_deviation: f32, // 0 when no one is there, small while holding breath
+    pub heart_rate: f32,       // Beats per minute
+    pub breath_rate: f32,      // Breaths per minute
+    pub heart_waveform: [f32; 15],
+    pub breath_waveform: [f32; 15],
+}
+
+impl FromBytes for VitalSignsTlv {
+    fn from_bytes(bytes: &[u8]) -> Result<Self, ParseError> {
+        if bytes.len() != 4 + 33 * 4 {
+            return Err(ParseError::DataLengthMismatch);
+        }
+        Ok(VitalSignsTlv {
+            id: u16::from_bytes(&bytes[0..2])?,
+            range_bin: u16::from_bytes(&bytes[2..4])?,
+            breath_deviation: f32::from_bytes(&bytes[4..8])?,
+            heart_rate: f32::from_bytes(&bytes[8..12])?,
+            breath_rate: f32::from_bytes(&bytes[12..16])?,
+            heart_waveform: <_>::from_bytes(&bytes[16..76])?,
+            breath_waveform: <_>::from_bytes(&bytes[76..136])?,
+        })
+    }
+}
+
+/// Breath deviation above which the firmware's estimates are fully trusted,
+/// TI's demo reports breathing as held below it
+const BREATH_DEVIATION_CLEAR: f32 = 0.02;
+
+/// Heart rates in beats per minute outside of which the firmware has locked
+/// onto something other than a heartbeat
+const HEART_RATE_PLAUSIBLE: std::ops::RangeInclusive<f32> = 40.0..=180.0;
+
+impl VitalSignsTlv {
+    /// The firmware has no confidence of its own for the heart rate, so it is
+    /// only trusted while plausible and backed by a heart waveform that moves
+    fn heart_quality(&self) -> f32 {
+        let moving = self
+            .heart_waveform
+            .iter()
+            .any(|&x| x != self.heart_waveform[0]);
+        if HEART_RATE_PLAUSIBLE.contains(&self.heart_rate) && moving {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+impl From<&VitalSignsTlv> for VitalSign {
+    fn from(tlv: &VitalSignsTlv) -> Self {
+        VitalSign {
+            id: tlv.id as u32,
+            range_bin: tlv.range_bin as u32,
+            breathing_rate: tlv.breath_rate,
+            heart_rate: tlv.heart_rate,
+            breathing_quality: (tlv.breath_deviation / BREATH_DEVIATION_CLEAR).clamp(0.0, 1.0),
+            heart_quality: tlv.heart_quality(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -319,6 +388,7 @@ pub enum TlvType {
     SideInfo = 7,
     AzimuthElevationStaticHeatmap = 8,
     Temperature = 9,
+    VitalSigns = 1040,
 }
 
 impl FromBytes for TlvType {
@@ -340,6 +410,7 @@ impl FromBytes for TlvType {
                 7 => Ok(TlvType::SideInfo),
                 8 => Ok(TlvType::AzimuthElevationStaticHeatmap),
                 9 => Ok(TlvType::Temperature),
+                1040 => Ok(TlvType::VitalSigns),
                 _ => Err(ParseError::MalformedData),
             }
         }
@@ -350,18 +421,101 @@ impl FromBytes for TlvType {
     }
 }
 
+impl Frame {
+    /// Estimates of any targets the vital signs firmware reported, whose
+    /// breath deviation is nonzero
+    pub fn vital_signs(&self) -> Vec<VitalSign> {
+        self.frame_body
+            .tlvs
+            .iter()
+            .filter_map(|tlv| match &tlv.tlv_body {
+                TlvBody::VitalSigns(vitals) if vitals.breath_deviation > 0.0 => Some(vitals.into()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
 impl Into<PointCloud> for Frame {
     fn into(self) -> PointCloud {
         // dbg!(self.frame_header.time);
+        let mut pointcloud = None;
+        let mut side_info = None;
         for tlv in self.frame_body.tlvs {
-            if let TlvBody::PointCloud(pc) = tlv.tlv_body {
-                return PointCloud {
-                    time: chrono::Utc::now(),
-                    points: pc.iter().map(|&p| p.into()).collect(),
-                    ..Default::default()
-                };
+            match tlv.tlv_body {
+                TlvBody::PointCloud(pc) => pointcloud = Some(pc),
+                TlvBody::SideInfo(info) => side_info = Some(info),
+                _ => {}
             }
         }
-        PointCloud::default()
+
+        let Some(pc) = pointcloud else {
+            return PointCloud::default();
+        };
+        let mut pointcloud = PointCloud {
+            time: chrono::Utc::now(),
+            points: pc.iter().map(|&p| p.into()).collect(),
+            ..Default::default()
+        };
+
+        // Side info is reported per detected point in units of 0.1dB
+        if let Some(info) = side_info.filter(|info| info.len() == pc.len()) {
+            let (snr, noise) = info
+                .iter()
+                .map(|&[snr, noise]| (snr as f32 * 0.1, noise as f32 * 0.1))
+                .unzip();
+            pointcloud.attributes.snr = snr;
+            pointcloud.attributes.noise = noise;
+        }
+
+        pointcloud
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FromBytes, VitalSignsTlv};
+    use crate::error::ParseError;
+    use mmwave_core::telemetry::VitalSign;
+
+    #[test]
+    pub fn test_parse_vital_signs() {
+        let mut bytes = Vec::new();
+        bytes.extend(3u16.to_ne_bytes());
+        bytes.extend(17u16.to_ne_bytes());
+        for value in [0.01f32, 72.0, 15.0] {
+            bytes.extend(value.to_ne_bytes());
+        }
+        for i in 0..30 {
+            bytes.extend((i as f32).to_ne_bytes());
+        }
+
+        let tlv = VitalSignsTlv::from_bytes(&bytes).unwrap();
+        assert_eq!((tlv.id, tlv.range_bin), (3, 17));
+        assert_eq!(
+            (tlv.breath_deviation, tlv.heart_rate, tlv.breath_rate),
+            (0.01, 72.0, 15.0)
+        );
+        assert_eq!(tlv.heart_waveform[14], 14.0);
+        assert_eq!(tlv.breath_waveform[0], 15.0);
+
+        // Breathing quality does not carry over to the heart rate
+        let vital = VitalSign::from(&tlv);
+        assert_eq!((vital.breathing_quality, vital.heart_quality), (0.5, 1.0));
+        let flat = VitalSignsTlv {
+            heart_waveform: [0.0; 15],
+            ..tlv.clone()
+        };
+        assert_eq!(VitalSign::from(&flat).heart_quality, 0.0);
+        let implausible = VitalSignsTlv {
+            heart_rate: 300.0,
+            ..tlv
+        };
+        assert_eq!(VitalSign::from(&implausible).heart_quality, 0.0);
+
+        assert!(matches!(
+            VitalSignsTlv::from_bytes(&bytes[..100]),
+            Err(ParseError::DataLengthMismatch)
+        ));
     }
 }
@@ -0,0 +1,105 @@
+use std::{net::IpAddr, path::PathBuf};
+
+use clap::{Parser, Subcommand};
+use mmwave_core::message::Id;
+
+#[derive(Parser, Debug, Clone)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+    /// IP address for server (ipv4)
+    #[arg(short, long)]
+    pub ip: Option<IpAddr>,
+
+    /// Port for server
+    #[arg(short, long, default_value_t = 3000)]
+    pub port: u16,
+
+    /// Enable debug logging
+    #[arg(short, long, default_value_t = false)]
+    pub debug: bool,
+
+    /// Whether to use tracing
+    #[arg(short, long, default_value_t = false)]
+    pub tracing: bool,
+
+    /// File to write the proposed configuration to
+    #[arg(short, long, default_value = "config_calibrated.json")]
+    pub output: PathBuf,
+
+    #[command(subcommand)]
+    pub mode: Mode,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Mode {
+    /// Move a corner reflector around the shared field of view of the radars
+    Reflector {
+        /// Device whose current transform is trusted, the others are solved relative to it
+        #[arg(short, long)]
+        reference: Id,
+
+        /// Seconds to record for
+        #[arg(long, default_value_t = 60)]
+        duration: u64,
+
+        /// Weakest return (snr in dB) that may be the reflector
+        #[arg(long, default_value_t = 15.0)]
+        min_snr: f32,
+
+        /// Largest difference in milliseconds between frames observing the same reflector position
+        #[arg(long, default_value_t = 50)]
+        tolerance: i64,
+
+        /// Refine with ICP against the reference's trajectory, for devices without synchronised clocks
+        #[arg(long, default_value_t = false)]
+        icp: bool,
+    },
+    /// Walk alone around the shared field of view of the radars and a Zed tracking bodies
+    Camera {
+        /// Zed device whose current transform is trusted, the radars are solved relative to it
+        #[arg(short, long)]
+        camera: Id,
+
+        /// Radars to calibrate, defaults to every other device with a transform
+        #[arg(short, long)]
+        radar: Vec<Id>,
+
+        /// Seconds to record for
+        #[arg(long, default_value_t = 60)]
+        duration: u64,
+
+        /// Largest difference in milliseconds between frames observing the same position
+        #[arg(long, default_value_t = 50)]
+        tolerance: i64,
+
+        /// Largest distance in metres between neighbouring radar points on the person
+        #[arg(long, default_value_t = 0.5)]
+        cluster_distance: f32,
+
+        /// Fewest radar points (or skeleton keypoints) that make up the person
+        #[arg(long, default_value_t = 3)]
+        min_points: usize,
+    },
+    /// Record the empty site and level every device onto the floor
+    Floor {
+        /// Device whose current transform is trusted, the floor it sees becomes the site floor
+        #[arg(short, long)]
+        reference: Option<Id>,
+
+        /// Seconds to record for
+        #[arg(long, default_value_t = 30)]
+        duration: u64,
+
+        /// Furthest in metres a point can be from the floor to lie on it
+        #[arg(long, default_value_t = 0.05)]
+        threshold: f32,
+
+        /// Steepest in degrees the floor a device sees may be
+        #[arg(long, default_value_t = 20.0)]
+        max_tilt: f32,
+
+        /// Fastest in m/s a point on the floor may appear to move
+        #[arg(long, default_value_t = 0.05)]
+        max_speed: f32,
+    },
+}
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use mmwave_core::{pointcloud::PointCloud, spatial::KdTree};
+
+use crate::observation::Observation;
+
+/// The centroid of the keypoints of the only body in a Zed cloud. Frames with
+/// no body, or more than one, are ambiguous and give no observation.
+pub fn body_centroid(pointcloud: &PointCloud, min_keypoints: usize) -> Option<Observation> {
+    let mut bodies: HashMap<u32, Vec<[f32; 3]>> = HashMap::new();
+    for (i, point) in pointcloud.points.iter().enumerate() {
+        let position: [f32; 3] = (*point).into();
+        // The Zed reports keypoints it could not place as nan
+        if position.iter().any(|x| !x.is_finite()) {
+            continue;
+        }
+        let body = pointcloud.attributes.track_id.get(i).copied().unwrap_or(0);
+        bodies.entry(body).or_default().push(position);
+    }
+    if bodies.len() != 1 {
+        return None;
+    }
+    let keypoints = bodies.into_values().next()?;
+    (keypoints.len() >= min_keypoints).then(|| Observation {
+        time: pointcloud.time,
+        position: centroid(&keypoints),
+        strength: keypoints.len() as f32,
+    })
+}
+
+/// The centroid of the largest cluster of radar points, presumed to be the
+/// person walking around. Points closer than `max_distance` to any point of a
+/// cluster join it.
+pub fn largest_cluster(
+    pointcloud: &PointCloud,
+    max_distance: f32,
+    min_points: usize,
+) -> Option<Observation> {
+    let points: Vec<[f32; 3]> = pointcloud.points.iter().map(|&p| p.into()).collect();
+    let tree = KdTree::new(&points);
+    let mut cluster_of = vec![usize::MAX; points.len()];
+    let mut clusters: Vec<Vec<usize>> = Vec::new();
+    for start in 0..points.len() {
+        if cluster_of[start] != usize::MAX {
+            continue;
+        }
+        let id = clusters.len();
+        cluster_of[start] = id;
+        let mut members = vec![start];
+        let mut next = 0;
+        while next < members.len() {
+            let current = points[members[next]];
+            next += 1;
+            for i in tree.within(current, max_distance) {
+                if cluster_of[i] == usize::MAX {
+                    cluster_of[i] = id;
+                    members.push(i);
+                }
+            }
+        }
+        clusters.push(members);
+    }
+
+    let largest = clusters.into_iter().max_by_key(|members| members.len())?;
+    (largest.len() >= min_points).then(|| Observation {
+        time: pointcloud.time,
+        position: centroid(&largest.iter().map(|&i| points[i]).collect::<Vec<_>>()),
+        strength: largest.len() as f32,
+    })
+}
+
+fn centroid(points: &[[f32; 3]]) -> [f32; 3] {
+    let n = points.len() as f32;
+    points.iter().fold([0.0; 3], |acc, p| {
+        [acc[0] + p[0] / n, acc[1] + p[1] / n, acc[2] + p[2] / n]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use mmwave_core::pointcloud::PointCloud;
+
+    use super::{body_centroid, largest_cluster};
+
+    #[test]
+    pub fn test_body_centroid() {
+        let mut pointcloud = PointCloud::from(vec![
+            [0.0, 2.0, 1.0, 0.0].into(),
+            [0.0, 2.0, 1.5, 0.0].into(),
+            [f32::NAN, f32::NAN, f32::NAN, 0.0].into(),
+            [0.5, 2.5, 1.0, 0.0].into(),
+        ]);
+        pointcloud.attributes.track_id = vec![0; 4];
+        let observation = body_centroid(&pointcloud, 3).unwrap();
+        assert!((observation.position[2] - 3.5 / 3.0).abs() < 1e-6);
+        assert_eq!(body_centroid(&pointcloud, 4), None);
+
+        pointcloud.attributes.track_id = vec![0, 0, 0, 1];
+        assert_eq!(body_centroid(&pointcloud, 1), None);
+    }
+
+    #[test]
+    pub fn test_largest_cluster() {
+        let pointcloud = PointCloud::from(vec![
+            [0.0, 3.0, 0.0, 0.0].into(),
+            [0.2, 3.1, 0.0, 0.0].into(),
+            [0.4, 3.2, 0.0, 0.0].into(),
+            [5.0, 1.0, 0.0, 0.0].into(),
+            [5.1, 1.0, 0.0, 0.0].into(),
+        ]);
+        let observation = largest_cluster(&pointcloud, 0.3, 3).unwrap();
+        assert!((observation.position[0] - 0.2).abs() < 1e-6);
+        assert_eq!(observation.strength, 3.0);
+        assert_eq!(largest_cluster(&pointcloud, 0.05, 2), None);
+    }
+}
@@ -0,0 +1,123 @@
+use std::fmt::Display;
+
+use mmwave_core::{ground::Plane, pointcloud::PointCloud, transform::Transform};
+
+/// The points of a device's recording that were standing still, in the world
+/// frame. The floor never moves, so this drops people walking over it.
+pub fn static_points(
+    pointclouds: &[PointCloud],
+    sensor_to_world: &Transform,
+    max_speed: f32,
+) -> Vec<[f32; 3]> {
+    let matrix = sensor_to_world.matrix();
+    pointclouds
+        .iter()
+        .flat_map(|pointcloud| pointcloud.points.iter())
+        .filter(|point| point.v.abs() <= max_speed)
+        .map(|&point| matrix.apply(point.into()))
+        .collect()
+}
+
+/// The sensor to world transform that moves the floor a device sees onto the
+/// site floor. The floor is laid onto z = 0 and from there onto the site
+/// floor, turning about the point below the sensor.
+pub fn level(sensor_to_world: &Transform, apparent: &Plane, site: &Plane) -> Transform {
+    let pivot = sensor_to_world.translation;
+    site.leveling(pivot)
+        .inverse()
+        .compose(&apparent.leveling(pivot))
+        .compose(sensor_to_world)
+}
+
+/// How far a device's transform is from agreeing with the floor it sees
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FloorError {
+    pub points: usize,        // Points the device saw on the floor
+    pub assumed_height: f32,  // Height of the sensor above the site floor
+    pub apparent_height: f32, // Height of the sensor above the floor it sees
+    pub pitch: f32,           // Assumed pitch less the pitch the floor implies (radians)
+    pub roll: f32,            // Assumed roll less the roll the floor implies (radians)
+}
+
+impl FloorError {
+    /// The error of a device given the floor it sees, the floor of the site,
+    /// and its transform before and after correcting it
+    pub fn of(
+        apparent: &Plane,
+        site: &Plane,
+        points: usize,
+        previous: &Transform,
+        corrected: &Transform,
+    ) -> Self {
+        let position = previous.translation;
+        Self {
+            points,
+            assumed_height: site.height(position),
+            apparent_height: apparent.height(position),
+            pitch: previous.orientation[1] - corrected.orientation[1],
+            roll: previous.orientation[2] - corrected.orientation[2],
+        }
+    }
+}
+
+impl Display for FloorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "height {:.3} m (assumed {:.3} m, error {:+.3} m), pitch error {:+.2} deg, roll error {:+.2} deg, from {} floor points",
+            self.apparent_height,
+            self.assumed_height,
+            self.assumed_height - self.apparent_height,
+            self.pitch.to_degrees(),
+            self.roll.to_degrees(),
+            self.points
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mmwave_core::{
+        ground::{fit_floor, Plane, RansacParams},
+        pointcloud::PointCloud,
+        transform::Transform,
+    };
+
+    use super::{level, static_points, FloorError};
+
+    #[test]
+    pub fn test_level() {
+        // Mounted higher and pitched further down than measured by hand
+        let actual = Transform {
+            translation: [1.0, 0.0, 2.2],
+            orientation: [0.3, -0.35, 0.02],
+        };
+        let assumed = Transform {
+            translation: [1.0, 0.0, 2.0],
+            orientation: [0.3, -0.25, 0.0],
+        };
+        let mut points = Vec::new();
+        for i in 0..20 {
+            for j in 0..20 {
+                let floor = [i as f32 * 0.3 - 3.0, j as f32 * 0.3 + 1.0, 0.0];
+                let [x, y, z] = actual.unapply(floor);
+                points.push([x, y, z, 0.0].into());
+            }
+        }
+        points.push([0.0, 3.0, 0.0, 1.0].into());
+        let recording = [PointCloud::from(points)];
+
+        let points = static_points(&recording, &assumed, 0.05);
+        assert_eq!(points.len(), 400);
+        let (apparent, inliers) = fit_floor(&points, &RansacParams::default()).unwrap();
+        let site = Plane::default();
+        let corrected = level(&assumed, &apparent, &site);
+        assert!((corrected.translation[2] - 2.2).abs() < 1e-3);
+        assert!((corrected.orientation[1] + 0.35).abs() < 1e-3);
+        assert!((corrected.orientation[2] - 0.02).abs() < 1e-3);
+
+        let error = FloorError::of(&apparent, &site, inliers.len(), &assumed, &corrected);
+        assert!((error.assumed_height - error.apparent_height + 0.2).abs() < 1e-3);
+        assert!((error.pitch - 0.1).abs() < 1e-3);
+    }
+}
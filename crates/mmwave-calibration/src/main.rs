@@ -0,0 +1,343 @@
+mod args;
+mod camera;
+mod floor;
+mod observation;
+mod record;
+mod reflector;
+mod solve;
+
+use std::{collections::HashMap, error::Error, fs::File, io::Write, time::Duration};
+
+use args::{Args, Mode};
+use async_nats::jetstream;
+use clap::Parser;
+use floor::{level, static_points, FloorError};
+use mmwave_core::{
+    address::ServerAddress,
+    config::Configuration,
+    ground::{fit_floor, RansacParams},
+    logging::enable_tracing,
+    message::Id,
+    nats::{get_config, get_store},
+    pointcloud::PointCloud,
+    transform,
+};
+use observation::{associate, Observation};
+use reflector::strongest_return;
+use solve::{icp, rigid_transform_robust, Correspondence, Residuals};
+use tracing::{error, info, warn};
+use transform::Transform;
+
+// Linked so their descriptors can be read from the configuration
+use mmwave_awr as _;
+use mmwave_cluster as _;
+use mmwave_filter as _;
+use mmwave_fusion as _;
+use mmwave_playback as _;
+use mmwave_recorder as _;
+use mmwave_tracker as _;
+use mmwave_occupancy as _;
+use mmwave_zones as _;
+use mmwave_posture as _;
+use mmwave_classification as _;
+use mmwave_zed as _;
+
+/// Pairs further than this many median residuals from their target are dropped
+const OUTLIER_THRESHOLD: f32 = 3.0;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    if args.tracing {
+        enable_tracing(args.debug);
+    }
+
+    let address = ServerAddress::new(args.ip, args.port).await;
+    let client = async_nats::connect(address.address().to_string()).await?;
+    let store = get_store(jetstream::new(client.clone())).await?;
+    let Some(mut configuration) = get_config(&store).await? else {
+        return Err("No configuration to calibrate, apply one from the dashboard first".into());
+    };
+
+    match args.mode {
+        Mode::Reflector {
+            reference,
+            duration,
+            min_snr,
+            tolerance,
+            icp,
+        } => {
+            let recorded =
+                record::record(&client, &configuration, Duration::from_secs(duration)).await?;
+            calibrate_reflector(
+                &mut configuration,
+                recorded,
+                reference,
+                min_snr,
+                chrono::Duration::milliseconds(tolerance),
+                icp,
+            )?;
+        }
+        Mode::Camera {
+            camera,
+            radar,
+            duration,
+            tolerance,
+            cluster_distance,
+            min_points,
+        } => {
+            let recorded =
+                record::record(&client, &configuration, Duration::from_secs(duration)).await?;
+            calibrate_camera(
+                &mut configuration,
+                recorded,
+                camera,
+                radar,
+                chrono::Duration::milliseconds(tolerance),
+                cluster_distance,
+                min_points,
+            )?;
+        }
+        Mode::Floor {
+            reference,
+            duration,
+            threshold,
+            max_tilt,
+            max_speed,
+        } => {
+            let recorded =
+                record::record(&client, &configuration, Duration::from_secs(duration)).await?;
+            let params = RansacParams {
+                threshold,
+                max_tilt: max_tilt.to_radians(),
+                ..Default::default()
+            };
+            calibrate_floor(&mut configuration, recorded, reference, &params, max_speed)?;
+        }
+    }
+
+    let mut file = File::create(&args.output)?;
+    file.write_all(serde_json::to_string_pretty(&configuration)?.as_bytes())?;
+    println!(
+        "Wrote the proposed configuration to {}, review it and load it with the dashboard",
+        args.output.display()
+    );
+    Ok(())
+}
+
+/// Solves for each device's sensor to world transform from the reflector
+/// positions it observed, relative to the reference device
+fn calibrate_reflector(
+    configuration: &mut Configuration,
+    recorded: HashMap<Id, Vec<PointCloud>>,
+    reference: Id,
+    min_snr: f32,
+    tolerance: chrono::Duration,
+    refine: bool,
+) -> Result<(), Box<dyn Error>> {
+    let observations: HashMap<Id, Vec<Observation>> = recorded
+        .into_iter()
+        .map(|(id, pointclouds)| {
+            let mut observations: Vec<_> = pointclouds
+                .iter()
+                .filter_map(|pointcloud| strongest_return(pointcloud, min_snr))
+                .collect();
+            observations.sort_by_key(|observation| observation.time);
+            (id, observations)
+        })
+        .collect();
+
+    let Some(reference_to_world) = configuration.sensor_to_world(reference)? else {
+        return Err(format!("Reference device {} has no transform", reference).into());
+    };
+    let Some(reference_observations) = observations.get(&reference) else {
+        return Err(format!("Reference device {} saw no reflector", reference).into());
+    };
+    // The reference is trusted, so its observations are where the reflector was
+    let reference_observations: Vec<_> = reference_observations
+        .iter()
+        .map(|observation| Observation {
+            position: reference_to_world.apply(observation.position),
+            ..*observation
+        })
+        .collect();
+
+    for (&id, device_observations) in observations.iter() {
+        if id == reference {
+            continue;
+        }
+        let pairs = associate(&reference_observations, device_observations, tolerance);
+        let (mut transform, inliers) = match rigid_transform_robust(&pairs, OUTLIER_THRESHOLD) {
+            Ok(solution) => solution,
+            Err(e) => {
+                error!(error=%e, %id, "Unable to calibrate device");
+                continue;
+            }
+        };
+        if refine {
+            let source: Vec<_> = device_observations.iter().map(|o| o.position).collect();
+            let target: Vec<_> = reference_observations.iter().map(|o| o.position).collect();
+            let max_distance = (Residuals::of(&transform, &inliers).max * 2.0).max(0.1);
+            match icp(&source, &target, &transform, max_distance, 50) {
+                Ok(refined) => transform = refined,
+                Err(e) => {
+                    warn!(error=%e, %id, "ICP refinement failed, keeping the closed form solution")
+                }
+            }
+        }
+
+        report(configuration, id, &pairs, &inliers, &transform)?;
+    }
+    info!("Calibration complete");
+    Ok(())
+}
+
+/// Solves for each radar's sensor to world transform from the positions of a
+/// person walking around, as seen by the radar and by the camera's body tracking
+fn calibrate_camera(
+    configuration: &mut Configuration,
+    recorded: HashMap<Id, Vec<PointCloud>>,
+    camera: Id,
+    radars: Vec<Id>,
+    tolerance: chrono::Duration,
+    cluster_distance: f32,
+    min_points: usize,
+) -> Result<(), Box<dyn Error>> {
+    let Some(camera_to_world) = configuration.sensor_to_world(camera)? else {
+        return Err(format!("Camera {} has no transform", camera).into());
+    };
+    let Some(camera_pointclouds) = recorded.get(&camera) else {
+        return Err(format!("Camera {} published nothing", camera).into());
+    };
+    // The camera is trusted, so the bodies it tracked are where the person was
+    let mut camera_observations: Vec<_> = camera_pointclouds
+        .iter()
+        .filter_map(|pointcloud| camera::body_centroid(pointcloud, min_points))
+        .map(|observation| Observation {
+            position: camera_to_world.apply(observation.position),
+            ..observation
+        })
+        .collect();
+    camera_observations.sort_by_key(|observation| observation.time);
+
+    let radars = if radars.is_empty() {
+        recorded
+            .keys()
+            .copied()
+            .filter(|&id| id != camera)
+            .collect()
+    } else {
+        radars
+    };
+    for id in radars {
+        let Some(pointclouds) = recorded.get(&id) else {
+            warn!(%id, "Radar published nothing");
+            continue;
+        };
+        let mut radar_observations: Vec<_> = pointclouds
+            .iter()
+            .filter_map(|pointcloud| {
+                camera::largest_cluster(pointcloud, cluster_distance, min_points)
+            })
+            .collect();
+        radar_observations.sort_by_key(|observation| observation.time);
+
+        let pairs = associate(&camera_observations, &radar_observations, tolerance);
+        let (transform, inliers) = match rigid_transform_robust(&pairs, OUTLIER_THRESHOLD) {
+            Ok(solution) => solution,
+            Err(e) => {
+                error!(error=%e, %id, "Unable to calibrate device");
+                continue;
+            }
+        };
+        report(configuration, id, &pairs, &inliers, &transform)?;
+        println!(
+            "  relative:  {}",
+            camera_to_world.inverse().compose(&transform)
+        );
+    }
+    info!("Calibration complete");
+    Ok(())
+}
+
+/// Fits the floor each device sees and levels each device onto the site
+/// floor, which is the configured one unless a reference device is trusted
+/// to see it
+fn calibrate_floor(
+    configuration: &mut Configuration,
+    recorded: HashMap<Id, Vec<PointCloud>>,
+    reference: Option<Id>,
+    params: &RansacParams,
+    max_speed: f32,
+) -> Result<(), Box<dyn Error>> {
+    let mut floors = HashMap::new();
+    for (&id, pointclouds) in recorded.iter() {
+        let Some(sensor_to_world) = configuration.sensor_to_world(id)? else {
+            continue;
+        };
+        let points = static_points(pointclouds, &sensor_to_world, max_speed);
+        match fit_floor(&points, params) {
+            Some((plane, inliers)) => {
+                floors.insert(id, (sensor_to_world, plane, inliers.len()));
+            }
+            None => warn!(%id, "Device saw no floor"),
+        }
+    }
+
+    if let Some(reference) = reference {
+        let Some((_, plane, _)) = floors.get(&reference) else {
+            return Err(format!("Reference device {} saw no floor", reference).into());
+        };
+        configuration.floor = *plane;
+    }
+    let site = configuration.floor;
+    println!(
+        "site floor: normal {:?}, offset {:.3} m",
+        site.normal, site.offset
+    );
+
+    let mut ids: Vec<Id> = floors.keys().copied().collect();
+    ids.sort();
+    for id in ids {
+        if Some(id) == reference {
+            continue;
+        }
+        let (previous, apparent, points) = &floors[&id];
+        let corrected = level(previous, apparent, &site);
+        println!("{}", id);
+        println!(
+            "  before:    {}",
+            FloorError::of(apparent, &site, *points, previous, &corrected)
+        );
+        println!("  transform: {}", corrected);
+        if !configuration.set_sensor_to_world(id, &corrected)? {
+            warn!(%id, "Device is no longer in the configuration");
+        }
+    }
+    info!("Calibration complete");
+    Ok(())
+}
+
+/// Prints the residuals before and after calibrating a device, and proposes
+/// its new transform
+fn report(
+    configuration: &mut Configuration,
+    id: Id,
+    pairs: &[Correspondence],
+    inliers: &[Correspondence],
+    transform: &Transform,
+) -> Result<(), Box<dyn Error>> {
+    let previous = configuration.sensor_to_world(id)?.unwrap_or_default();
+    println!("{}", id);
+    println!("  before:    {}", Residuals::of(&previous, pairs));
+    println!(
+        "  after:     {} ({} outliers dropped)",
+        Residuals::of(transform, inliers),
+        pairs.len() - inliers.len()
+    );
+    println!("  transform: {}", transform);
+    if !configuration.set_sensor_to_world(id, transform)? {
+        warn!(%id, "Device is no longer in the configuration");
+    }
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use chrono::{DateTime, Utc};
+
+use crate::solve::Correspondence;
+
+/// Where a calibration target was seen by one sensor at one point in time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Observation {
+    pub time: DateTime<Utc>,
+    pub position: [f32; 3],
+    pub strength: f32,
+}
+
+/// Pairs each observation with the reference observation nearest to it in
+/// time, dropping those with no reference observation within the tolerance.
+/// The reference observations must be sorted by time.
+pub fn associate(
+    reference: &[Observation],
+    observations: &[Observation],
+    tolerance: chrono::Duration,
+) -> Vec<Correspondence> {
+    observations
+        .iter()
+        .filter_map(|observation| {
+            let i = reference.partition_point(|r| r.time < observation.time);
+            let nearest = [i.checked_sub(1), Some(i)]
+                .into_iter()
+                .flatten()
+                .filter_map(|i| reference.get(i))
+                .min_by_key(|r| (r.time - observation.time).abs())?;
+            ((nearest.time - observation.time).abs() <= tolerance)
+                .then_some((observation.position, nearest.position))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{associate, Observation};
+
+    #[test]
+    pub fn test_associate() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let at = |ms: i64, x: f32| Observation {
+            time: start + Duration::milliseconds(ms),
+            position: [x, 0.0, 0.0],
+            strength: 20.0,
+        };
+        let reference = [at(0, 0.0), at(100, 1.0), at(200, 2.0)];
+        let observations = [at(95, 10.0), at(160, 11.0), at(400, 12.0)];
+        let pairs = associate(&reference, &observations, Duration::milliseconds(50));
+        assert_eq!(
+            pairs,
+            vec![
+                ([10.0, 0.0, 0.0], [1.0, 0.0, 0.0]),
+                ([11.0, 0.0, 0.0], [2.0, 0.0, 0.0])
+            ]
+        );
+    }
+}
@@ -0,0 +1,60 @@
+use std::{collections::HashMap, error::Error, time::Duration};
+
+use async_nats::Client;
+use mmwave_core::{
+    config::Configuration, message::Id, pointcloud::PointCloud, pubsub::Subscription,
+    subject::SubjectFilter,
+};
+use tokio::{pin, select, signal};
+use tracing::{info, warn};
+
+/// Records pointclouds from every device with a transform for the given
+/// duration (or until ctrl-c), moved back into each device's sensor frame
+/// using the transforms in the configuration they were published with.
+pub async fn record(
+    client: &Client,
+    configuration: &Configuration,
+    duration: Duration,
+) -> Result<HashMap<Id, Vec<PointCloud>>, Box<dyn Error>> {
+    let mut subscription = Subscription::<PointCloud>::new(client, SubjectFilter::all()).await?;
+    let mut recorded: HashMap<Id, Vec<PointCloud>> = HashMap::new();
+
+    info!(
+        seconds = duration.as_secs(),
+        "Recording, press ctrl-c to stop early"
+    );
+    let deadline = tokio::time::sleep(duration);
+    let ctrl_c = signal::ctrl_c();
+    pin!(deadline, ctrl_c);
+    loop {
+        select! {
+            _ = &mut deadline => break,
+            _ = &mut ctrl_c => break,
+            received = subscription.next() => {
+                let Some(received) = received else {
+                    warn!("Subscription closed");
+                    break;
+                };
+                let Some(id) = received.from else {
+                    continue;
+                };
+                let sensor_to_world = match configuration.sensor_to_world(id) {
+                    Ok(Some(transform)) => transform,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        warn!(error=%e, %id, "Skipping device whose frame cannot be resolved");
+                        continue;
+                    }
+                };
+                let mut pointcloud = received.content;
+                sensor_to_world.inverse().apply_to_cloud(&mut pointcloud);
+                recorded.entry(id).or_default().push(pointcloud);
+            }
+        }
+    }
+
+    for (id, pointclouds) in recorded.iter() {
+        info!(%id, frames = pointclouds.len(), "Recorded");
+    }
+    Ok(recorded)
+}
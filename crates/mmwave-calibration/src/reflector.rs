@@ -0,0 +1,48 @@
+use mmwave_core::pointcloud::PointCloud;
+
+use crate::observation::Observation;
+
+/// The strongest return in the cloud, which is presumed to be the corner
+/// reflector. Clouds without snr (or intensity) cannot be used.
+pub fn strongest_return(pointcloud: &PointCloud, min_strength: f32) -> Option<Observation> {
+    let strengths = if pointcloud.attributes.snr.len() == pointcloud.points.len() {
+        &pointcloud.attributes.snr
+    } else if pointcloud.attributes.intensity.len() == pointcloud.points.len() {
+        &pointcloud.attributes.intensity
+    } else {
+        return None;
+    };
+    let (point, &strength) = pointcloud
+        .points
+        .iter()
+        .zip(strengths)
+        .max_by(|a, b| a.1.total_cmp(b.1))?;
+    (strength >= min_strength).then_some(Observation {
+        time: pointcloud.time,
+        position: (*point).into(),
+        strength,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use mmwave_core::pointcloud::PointCloud;
+
+    use super::strongest_return;
+
+    #[test]
+    pub fn test_strongest_return() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let mut pointcloud = PointCloud::from(vec![
+            [0.0, 1.0, 0.0, 0.0].into(),
+            [1.0, 2.0, 0.0, 0.0].into(),
+        ]);
+        pointcloud.time = start;
+        assert_eq!(strongest_return(&pointcloud, 0.0), None);
+        pointcloud.attributes.snr = vec![10.0, 25.0];
+        let observation = strongest_return(&pointcloud, 15.0).unwrap();
+        assert_eq!(observation.position, [1.0, 2.0, 0.0]);
+        assert_eq!(strongest_return(&pointcloud, 30.0), None);
+    }
+}
@@ -0,0 +1,320 @@
+use std::fmt::Display;
+
+use mmwave_core::{
+    spatial::KdTree,
+    transform::{Quaternion, Transform},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SolveError {
+    #[error("Need at least 3 correspondences, got {0}")]
+    TooFewPoints(usize),
+    #[error("Correspondences are (nearly) collinear, move the target around more")]
+    Degenerate,
+}
+
+/// The same target observed in the frame being solved for (source) and in the
+/// frame it is being solved into (target)
+pub type Correspondence = ([f32; 3], [f32; 3]);
+
+/// The least squares rigid transform taking the source points onto the target
+/// points, using Horn's closed form quaternion method.
+pub fn rigid_transform(pairs: &[Correspondence]) -> Result<Transform, SolveError> {
+    if pairs.len() < 3 {
+        return Err(SolveError::TooFewPoints(pairs.len()));
+    }
+    let n = pairs.len() as f64;
+    let mut source_mean = [0.0f64; 3];
+    let mut target_mean = [0.0f64; 3];
+    for (a, b) in pairs {
+        for i in 0..3 {
+            source_mean[i] += a[i] as f64 / n;
+            target_mean[i] += b[i] as f64 / n;
+        }
+    }
+
+    // Cross covariance of the centred points, and the source's own spread
+    let mut s = [[0.0f64; 3]; 3];
+    let mut spread = [[0.0f64; 3]; 3];
+    for (a, b) in pairs {
+        let a = [0, 1, 2].map(|i| a[i] as f64 - source_mean[i]);
+        let b = [0, 1, 2].map(|i| b[i] as f64 - target_mean[i]);
+        for i in 0..3 {
+            for j in 0..3 {
+                s[i][j] += a[i] * b[j];
+                spread[i][j] += a[i] * a[j] / n;
+            }
+        }
+    }
+
+    // A rotation about the line through collinear points is undetermined
+    let (mut variances, _) = jacobi_eigen(spread);
+    variances.sort_by(|a, b| b.total_cmp(a));
+    if variances[1] < 1e-6 * variances[0].max(1e-12) || variances[1] < 1e-8 {
+        return Err(SolveError::Degenerate);
+    }
+
+    let [[sxx, sxy, sxz], [syx, syy, syz], [szx, szy, szz]] = s;
+    let n = [
+        [sxx + syy + szz, syz - szy, szx - sxz, sxy - syx],
+        [syz - szy, sxx - syy - szz, sxy + syx, szx + sxz],
+        [szx - sxz, sxy + syx, -sxx + syy - szz, syz + szy],
+        [sxy - syx, szx + sxz, syz + szy, -sxx - syy + szz],
+    ];
+    let (values, vectors) = jacobi_eigen(n);
+    let largest = (0..4)
+        .max_by(|&i, &j| values[i].total_cmp(&values[j]))
+        .unwrap_or(0);
+    let rotation = Quaternion {
+        w: vectors[0][largest] as f32,
+        x: vectors[1][largest] as f32,
+        y: vectors[2][largest] as f32,
+        z: vectors[3][largest] as f32,
+    }
+    .normalized();
+
+    let rotated = rotation.rotate(source_mean.map(|x| x as f32));
+    let translation = [0, 1, 2].map(|i| target_mean[i] as f32 - rotated[i]);
+    Ok(Transform::from_quaternion(translation, rotation))
+}
+
+/// Eigen decomposition of a symmetric matrix with the cyclic Jacobi method.
+/// Returns the eigenvalues and the matching eigenvectors as columns.
+pub fn jacobi_eigen<const N: usize>(mut a: [[f64; N]; N]) -> ([f64; N], [[f64; N]; N]) {
+    let mut v = [[0.0; N]; N];
+    for (i, row) in v.iter_mut().enumerate() {
+        row[i] = 1.0;
+    }
+
+    for _ in 0..64 {
+        let off_diagonal: f64 = (0..N)
+            .flat_map(|p| (0..N).filter(move |&q| q != p).map(move |q| (p, q)))
+            .map(|(p, q)| a[p][q] * a[p][q])
+            .sum();
+        if off_diagonal < 1e-24 {
+            break;
+        }
+        for p in 0..N {
+            for q in p + 1..N {
+                if a[p][q].abs() < 1e-300 {
+                    continue;
+                }
+                let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                for row in a.iter_mut() {
+                    let (kp, kq) = (row[p], row[q]);
+                    row[p] = c * kp - s * kq;
+                    row[q] = s * kp + c * kq;
+                }
+                let (upper, lower) = a.split_at_mut(q);
+                for (pk, qk) in upper[p].iter_mut().zip(lower[0].iter_mut()) {
+                    (*pk, *qk) = (c * *pk - s * *qk, s * *pk + c * *qk);
+                }
+                for row in v.iter_mut() {
+                    let (kp, kq) = (row[p], row[q]);
+                    row[p] = c * kp - s * kq;
+                    row[q] = s * kp + c * kq;
+                }
+            }
+        }
+    }
+
+    let mut values = [0.0; N];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = a[i][i];
+    }
+    (values, v)
+}
+
+/// Refines a transform taking source points onto target points without known
+/// correspondences (iterative closest point). Each iteration pairs every
+/// source point with its nearest target point, ignoring pairs further apart
+/// than max_distance, and solves for the rigid transform of those pairs.
+pub fn icp(
+    source: &[[f32; 3]],
+    target: &[[f32; 3]],
+    initial: &Transform,
+    max_distance: f32,
+    iterations: usize,
+) -> Result<Transform, SolveError> {
+    let tree = KdTree::new(target);
+    let mut transform = initial.clone();
+    let mut previous_error = f32::INFINITY;
+    for _ in 0..iterations {
+        let matrix = transform.matrix();
+        let pairs: Vec<Correspondence> = source
+            .iter()
+            .filter_map(|&point| {
+                let moved = matrix.apply(point);
+                let (nearest, distance) = *tree.nearest(moved, 1).first()?;
+                (distance <= max_distance).then_some((point, target[nearest]))
+            })
+            .collect();
+        transform = rigid_transform(&pairs)?;
+
+        let error = Residuals::of(&transform, &pairs).rms;
+        if (previous_error - error).abs() < 1e-6 {
+            break;
+        }
+        previous_error = error;
+    }
+    Ok(transform)
+}
+
+pub fn distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| (a - b) * (a - b))
+        .sum::<f32>()
+        .sqrt()
+}
+
+/// How far the transformed source points land from their targets, in meters
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Residuals {
+    pub count: usize,
+    pub rms: f32,
+    pub median: f32,
+    pub max: f32,
+}
+
+impl Residuals {
+    pub fn of(transform: &Transform, pairs: &[Correspondence]) -> Self {
+        let matrix = transform.matrix();
+        let mut errors: Vec<f32> = pairs
+            .iter()
+            .map(|&(source, target)| distance(matrix.apply(source), target))
+            .collect();
+        if errors.is_empty() {
+            return Self::default();
+        }
+        errors.sort_by(|a, b| a.total_cmp(b));
+        Self {
+            count: errors.len(),
+            rms: (errors.iter().map(|e| e * e).sum::<f32>() / errors.len() as f32).sqrt(),
+            median: errors[errors.len() / 2],
+            max: errors[errors.len() - 1],
+        }
+    }
+}
+
+impl Display for Residuals {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} pairs, rms {:.3}m, median {:.3}m, max {:.3}m",
+            self.count, self.rms, self.median, self.max
+        )
+    }
+}
+
+/// Solves for the transform, then solves again without the pairs that land
+/// further than `threshold` times the median residual from their target
+pub fn rigid_transform_robust(
+    pairs: &[Correspondence],
+    threshold: f32,
+) -> Result<(Transform, Vec<Correspondence>), SolveError> {
+    let transform = rigid_transform(pairs)?;
+    let residuals = Residuals::of(&transform, pairs);
+    // Never reject pairs that are within a couple of centimeters anyway
+    let limit = (residuals.median * threshold).max(0.02);
+    let matrix = transform.matrix();
+    let inliers: Vec<_> = pairs
+        .iter()
+        .copied()
+        .filter(|&(source, target)| distance(matrix.apply(source), target) <= limit)
+        .collect();
+    if inliers.len() == pairs.len() {
+        return Ok((transform, inliers));
+    }
+    Ok((rigid_transform(&inliers)?, inliers))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use mmwave_core::transform::Transform;
+
+    use super::{icp, jacobi_eigen, rigid_transform, rigid_transform_robust, SolveError};
+
+    fn truth() -> Transform {
+        Transform {
+            translation: [2.0, -1.0, 0.5],
+            orientation: [PI / 3., 0.2, -0.1],
+        }
+    }
+
+    fn points() -> Vec<[f32; 3]> {
+        (0..40)
+            .map(|i| {
+                let i = i as f32;
+                [(i * 0.7).sin() * 3.0, 1.0 + i * 0.1, (i * 0.3).cos()]
+            })
+            .collect()
+    }
+
+    fn close(a: &Transform, b: &Transform) -> bool {
+        let point = [1.0, 2.0, 3.0];
+        super::distance(a.apply(point), b.apply(point)) < 1e-3
+    }
+
+    #[test]
+    pub fn test_jacobi_eigen() {
+        let (values, vectors) = jacobi_eigen([[2.0, 1.0], [1.0, 2.0]]);
+        let mut sorted = values;
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        assert!((sorted[0] - 1.0).abs() < 1e-9 && (sorted[1] - 3.0).abs() < 1e-9);
+        for i in 0..2 {
+            // A v = lambda v
+            let av = [
+                2.0 * vectors[0][i] + vectors[1][i],
+                vectors[0][i] + 2.0 * vectors[1][i],
+            ];
+            assert!((av[0] - values[i] * vectors[0][i]).abs() < 1e-9);
+            assert!((av[1] - values[i] * vectors[1][i]).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    pub fn test_recovers_transform() {
+        let truth = truth();
+        let pairs: Vec<_> = points().into_iter().map(|p| (p, truth.apply(p))).collect();
+        assert!(close(&rigid_transform(&pairs).unwrap(), &truth));
+
+        // A few wild associations are discarded
+        let mut noisy = pairs.clone();
+        noisy[3].1 = [10.0, 10.0, 10.0];
+        noisy[17].1 = [-5.0, 0.0, 2.0];
+        let (transform, inliers) = rigid_transform_robust(&noisy, 3.0).unwrap();
+        assert_eq!(inliers.len(), pairs.len() - 2);
+        assert!(close(&transform, &truth));
+    }
+
+    #[test]
+    pub fn test_degenerate() {
+        let line: Vec<_> = (0..10)
+            .map(|i| ([i as f32, 0.0, 0.0], [0.0, i as f32, 0.0]))
+            .collect();
+        assert_eq!(rigid_transform(&line), Err(SolveError::Degenerate));
+        assert_eq!(
+            rigid_transform(&line[..2]),
+            Err(SolveError::TooFewPoints(2))
+        );
+    }
+
+    #[test]
+    pub fn test_icp_refines() {
+        let truth = truth();
+        let source = points();
+        let target: Vec<_> = source.iter().map(|&p| truth.apply(p)).collect();
+        let mut initial = truth.clone();
+        initial.translation[0] += 0.05;
+        initial.orientation[0] += 0.02;
+        let refined = icp(&source, &target, &initial, 0.5, 30).unwrap();
+        assert!(close(&refined, &truth));
+    }
+}
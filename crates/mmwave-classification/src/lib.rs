@@ -0,0 +1,401 @@
+pub mod motion;
+pub mod onnx;
+
+use async_nats::{
+    connection::State,
+    jetstream::{
+        self,
+        kv::{Entry, Watch},
+    },
+    Client,
+};
+use async_trait::async_trait;
+use egui::Ui;
+use futures::StreamExt;
+use mmwave_core::{
+    address::ServerAddress,
+    classify::{ClassifierDescriptor, WindowClassifier, Windower},
+    config::Configuration,
+    devices::DeviceDescriptor,
+    message::{Id, Tag},
+    nats::get_store,
+    pointcloud::PointCloud,
+    pubsub::{Publisher, Subscription},
+    subject::SubjectFilter,
+    telemetry::{DeviceState, Predictions},
+    wire::Encoding,
+};
+use motion::MotionDescriptor;
+use onnx::OnnxDescriptor;
+use serde::{Deserialize, Serialize};
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    time::Duration,
+};
+use tokio::{
+    select,
+    task::{spawn_blocking, yield_now},
+};
+use tracing::{error, info, instrument, warn};
+
+/// What is classified
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize, Default)]
+pub enum ClassificationInput {
+    #[default]
+    Pointclouds, // Pointclouds of ordinary devices
+    Fused,    // Pointclouds of fusion devices
+    Filtered, // Pointclouds of filter devices
+}
+
+/// Hosts window classifiers, running each of them over sliding windows of
+/// the pointclouds of every source and publishing their predictions.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClassificationDescriptor {
+    pub sources: Vec<Id>,           // Devices to classify, all of them if empty
+    pub input: ClassificationInput, // What to classify from them
+    pub window: usize,              // Pointclouds in a window
+    pub stride: usize,              // Pointclouds between windows
+    pub classifiers: Vec<Box<dyn ClassifierDescriptor>>,
+    pub encoding: Encoding, // Encoding of published messages
+}
+
+impl Default for ClassificationDescriptor {
+    fn default() -> Self {
+        Self {
+            sources: Vec::new(),
+            input: ClassificationInput::default(),
+            window: 20,
+            stride: 5,
+            classifiers: vec![Box::new(MotionDescriptor::default())],
+            encoding: Encoding::default(),
+        }
+    }
+}
+
+impl Eq for ClassificationDescriptor {}
+
+impl Display for ClassificationDescriptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<String> = self.classifiers.iter().map(|c| c.name()).collect();
+        write!(f, "Classification ({})", names.join(", "))
+    }
+}
+
+#[typetag::serde]
+#[async_trait]
+impl DeviceDescriptor for ClassificationDescriptor {
+    #[instrument(skip_all, fields(self=%self, id=%id))]
+    async fn init(self: Box<Self>, id: Id, address: ServerAddress) {
+        if let Err(e) = start_classification(*self, id, address).await {
+            error!(error=?e, "Classification closed with error");
+        }
+    }
+
+    fn clone_boxed(&self) -> Box<dyn DeviceDescriptor> {
+        Box::new(self.clone())
+    }
+
+    fn title(&self) -> String {
+        format!("{}", self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.label("Sources:");
+        let mut removed = None;
+        for (i, source) in self.sources.iter_mut().enumerate() {
+            ui.push_id(("source", i), |ui| {
+                ui.horizontal(|ui| {
+                    source.ui(ui);
+                    if ui.button("remove").clicked() {
+                        removed = Some(i);
+                    }
+                });
+            });
+        }
+        if let Some(i) = removed {
+            self.sources.remove(i);
+        }
+        if ui.button("add source").clicked() {
+            self.sources.push(Id::Device(0, 0));
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Input:");
+            egui::ComboBox::from_id_source(ui.make_persistent_id("classification_input"))
+                .selected_text(format!("{:?}", self.input))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(
+                        &mut self.input,
+                        ClassificationInput::Pointclouds,
+                        "Pointclouds",
+                    );
+                    ui.selectable_value(&mut self.input, ClassificationInput::Fused, "Fused");
+                    ui.selectable_value(&mut self.input, ClassificationInput::Filtered, "Filtered");
+                });
+            ui.label("Window:");
+            ui.add(egui::DragValue::new(&mut self.window).clamp_range(1..=1000));
+            ui.label("Stride:");
+            ui.add(egui::DragValue::new(&mut self.stride).clamp_range(1..=1000));
+        });
+
+        ui.label("Classifiers:");
+        let mut removed = None;
+        for (i, classifier) in self.classifiers.iter_mut().enumerate() {
+            ui.push_id(("classifier", i), |ui| {
+                ui.group(|ui| {
+                    ui.horizontal(|ui| {
+                        ui.label(classifier.name());
+                        if ui.button("remove").clicked() {
+                            removed = Some(i);
+                        }
+                    });
+                    classifier.ui(ui);
+                });
+            });
+        }
+        if let Some(i) = removed {
+            self.classifiers.remove(i);
+        }
+        ui.horizontal(|ui| {
+            if ui.button("add motion").clicked() {
+                self.classifiers.push(Box::new(MotionDescriptor::default()));
+            }
+            if ui.button("add onnx").clicked() {
+                self.classifiers.push(Box::new(OnnxDescriptor::default()));
+            }
+        });
+        self.encoding.ui(ui);
+    }
+}
+
+#[instrument(skip_all)]
+async fn start_classification(
+    mut descriptor: ClassificationDescriptor,
+    id: Id,
+    address: ServerAddress,
+) -> Result<(), Box<dyn Error>> {
+    // Connect to the NATS server
+    let client = async_nats::connect(address.address().to_string()).await?;
+    let jetstream = jetstream::new(client.clone());
+
+    // Listen for config updates on a separate task
+    let store = get_store(jetstream).await?;
+    let mut entries = store.watch("config").await?;
+
+    let mut interval = tokio::time::interval(Duration::from_millis(5000));
+    loop {
+        // Verify the client connection state
+        if client.connection_state() == State::Disconnected {
+            return Err(String::from("Lost connection to NATS").into());
+        }
+
+        let result = run_classification(&client, &mut entries, &mut descriptor, id)
+            .await
+            .map_err(|e| e.to_string());
+        if let Err(e) = result {
+            error!(error=%e, "Classification stopped running");
+            let publisher = Publisher::new(client.clone(), id);
+            if let Err(e) = publisher.error(descriptor.title(), e).await {
+                warn!(error=%e, "Failed to report classification error");
+            }
+        }
+        interval.tick().await;
+    }
+}
+
+#[instrument(skip_all)]
+async fn run_classification(
+    client: &Client,
+    entries: &mut Watch,
+    descriptor: &mut ClassificationDescriptor,
+    id: Id,
+) -> Result<(), Box<dyn Error>> {
+    let mut publisher = Publisher::new(client.clone(), id).with_encoding(descriptor.encoding);
+    // Models are loaded once per run, and reloaded when their settings change
+    let mut classifiers = Vec::new();
+    for classifier in descriptor.classifiers.iter() {
+        let built: Box<dyn WindowClassifier> = classifier.build()?;
+        classifiers.push((classifier.name(), built));
+    }
+    publisher
+        .status(DeviceState::Running, descriptor.title(), None)
+        .await?;
+
+    let mut pointclouds = Subscription::<PointCloud>::new(client, SubjectFilter::all()).await?;
+    let mut fused =
+        Subscription::<PointCloud>::with_kind(client, SubjectFilter::all(), Tag::Fused).await?;
+    let mut filtered =
+        Subscription::<PointCloud>::with_kind(client, SubjectFilter::all(), Tag::Filtered).await?;
+    let mut windowers: HashMap<Id, Windower> = HashMap::new();
+
+    loop {
+        yield_now().await;
+        let (from, pointcloud) = select! {
+            Some(config) = entries.next() => {
+                let previous = descriptor.clone();
+                maintain_config(config?, descriptor, id)?;
+                publisher.set_encoding(descriptor.encoding);
+                let changed = (descriptor.input, descriptor.window, descriptor.stride)
+                    != (previous.input, previous.window, previous.stride);
+                if changed || descriptor.classifiers != previous.classifiers {
+                    info!("Restarting classification with new settings");
+                    return Ok(());
+                }
+                continue;
+            }
+            received = pointclouds.next() => {
+                let Some(received) = received else {
+                    return Err("Pointcloud subscription closed".into());
+                };
+                let Some(from) = source(descriptor, ClassificationInput::Pointclouds, received.from, id) else {
+                    continue;
+                };
+                (from, received.content)
+            }
+            received = fused.next() => {
+                let Some(received) = received else {
+                    return Err("Fused subscription closed".into());
+                };
+                let Some(from) = source(descriptor, ClassificationInput::Fused, received.from, id) else {
+                    continue;
+                };
+                (from, received.content)
+            }
+            received = filtered.next() => {
+                let Some(received) = received else {
+                    return Err("Filtered subscription closed".into());
+                };
+                let Some(from) = source(descriptor, ClassificationInput::Filtered, received.from, id) else {
+                    continue;
+                };
+                (from, received.content)
+            }
+        };
+
+        let time = pointcloud.time;
+        let windower = windowers
+            .entry(from)
+            .or_insert_with(|| Windower::new(descriptor.window, descriptor.stride));
+        let Some(window) = windower.push(pointcloud) else {
+            continue;
+        };
+
+        // Models can take a while, so they run off the async workers, taking
+        // the classifiers with them and handing them back
+        let window = window.to_vec();
+        let (returned, results) = spawn_blocking(move || {
+            let results: Vec<_> = classifiers
+                .iter_mut()
+                .map(|(name, classifier)| (name.clone(), classifier.classify(&window)))
+                .collect();
+            (classifiers, results)
+        })
+        .await?;
+        classifiers = returned;
+
+        for (name, scores) in results {
+            // A window the model rejects should not stop the others
+            let scores = match scores {
+                Ok(scores) => scores,
+                Err(e) => {
+                    warn!(error=%e, classifier=%name, "Failed to classify window");
+                    publisher.error(descriptor.title(), e.to_string()).await?;
+                    continue;
+                }
+            };
+            let predictions = Predictions {
+                time,
+                source: Some(from),
+                classifier: name,
+                scores,
+            };
+            publisher.publish(predictions).await?;
+        }
+    }
+}
+
+/// The device a pointcloud came from, if it is one of the sources for the
+/// input
+fn source(
+    descriptor: &ClassificationDescriptor,
+    input: ClassificationInput,
+    from: Option<Id>,
+    id: Id,
+) -> Option<Id> {
+    from.filter(|from| {
+        descriptor.input == input
+            && *from != id
+            && (descriptor.sources.is_empty() || descriptor.sources.contains(from))
+    })
+}
+
+fn maintain_config(
+    entry: Entry,
+    descriptor: &mut ClassificationDescriptor,
+    id: Id,
+) -> Result<(), Box<dyn Error>> {
+    let Ok(configuration) = serde_json::from_slice::<Configuration>(&entry.value) else {
+        return Ok(());
+    };
+
+    for device_config in configuration.descriptors {
+        if device_config.id != id {
+            continue;
+        }
+
+        let erased_desc = device_config.device_descriptor.as_any();
+
+        let updated_desc = match erased_desc.downcast_ref::<ClassificationDescriptor>() {
+            Some(classification_desc) => classification_desc,
+            None => {
+                tracing::error!(
+                    "Failed to downcast: actual type id = {:?}, expected type id = {:?}",
+                    erased_desc.type_id(),
+                    TypeId::of::<Box<ClassificationDescriptor>>()
+                );
+                continue;
+            }
+        };
+
+        if descriptor != updated_desc {
+            info!("Updated classification descriptor");
+            *descriptor = updated_desc.clone();
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use mmwave_core::{classify::WindowClassifier, pointcloud::PointCloud};
+
+    use crate::{motion::MotionDescriptor, ClassificationDescriptor};
+
+    #[test]
+    pub fn test_motion_classifier() {
+        let mut motion = MotionDescriptor { speed: 0.5 };
+        let window = [
+            PointCloud::from(vec![[0.0, 1.0, 0.0, 1.0].into()]),
+            PointCloud::from(vec![[0.0, 1.0, 0.0, -2.0].into()]),
+        ];
+        let scores = motion.classify(&window).unwrap();
+        assert_eq!(scores[0].label, "moving");
+        assert!((scores[0].score - 0.75).abs() < 1e-6);
+        assert_eq!(motion.classify(&[]).unwrap()[0].label, "still");
+
+        // Classifiers round trip through the configuration by type
+        let descriptor = ClassificationDescriptor::default();
+        let json = serde_json::to_string(&descriptor).unwrap();
+        let parsed: ClassificationDescriptor = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, descriptor);
+    }
+}
@@ -0,0 +1,73 @@
+use egui::Ui;
+use mmwave_core::{
+    classify::{rank, ClassifierDescriptor, ClassifierError, WindowClassifier},
+    pointcloud::PointCloud,
+    telemetry::Score,
+};
+use serde::{Deserialize, Serialize};
+
+/// Tells whether a window is `moving` or `still` from the mean radial speed
+/// of its points, which needs no model. Useful for checking a deployment
+/// before plugging in a trained classifier.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MotionDescriptor {
+    pub speed: f32, // Mean speed (m/s) at which moving and still score evenly
+}
+
+impl Default for MotionDescriptor {
+    fn default() -> Self {
+        Self { speed: 0.2 }
+    }
+}
+
+#[typetag::serde]
+impl ClassifierDescriptor for MotionDescriptor {
+    fn build(&self) -> Result<Box<dyn WindowClassifier>, ClassifierError> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn clone_boxed(&self) -> Box<dyn ClassifierDescriptor> {
+        Box::new(self.clone())
+    }
+
+    fn name(&self) -> String {
+        "motion".to_owned()
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Speed (m/s):");
+            ui.add(
+                egui::DragValue::new(&mut self.speed)
+                    .speed(0.01)
+                    .clamp_range(0.01..=10.0),
+            );
+        });
+    }
+}
+
+impl WindowClassifier for MotionDescriptor {
+    fn classify(&mut self, window: &[PointCloud]) -> Result<Vec<Score>, ClassifierError> {
+        let speeds: Vec<f32> = window
+            .iter()
+            .flat_map(|pc| pc.points.iter().map(|p| p.v.abs()))
+            .collect();
+        let mean = if speeds.is_empty() {
+            0.0
+        } else {
+            speeds.iter().sum::<f32>() / speeds.len() as f32
+        };
+        let moving = mean / (mean + self.speed);
+        Ok(rank(vec![
+            Score {
+                label: "moving".to_owned(),
+                score: moving,
+            },
+            Score {
+                label: "still".to_owned(),
+                score: 1.0 - moving,
+            },
+        ]))
+    }
+}
@@ -0,0 +1,203 @@
+use egui::Ui;
+use mmwave_core::{
+    classify::{rank, ClassifierDescriptor, ClassifierError, WindowClassifier},
+    pointcloud::PointCloud,
+    telemetry::Score,
+};
+use serde::{Deserialize, Serialize};
+
+/// Runs an ONNX model on the CPU. The model takes a single f32 tensor of
+/// shape [1, window, max_points, 4] holding the x, y, z and velocity of the
+/// points of each pointcloud, oldest first and zero padded, and outputs a
+/// score for each label. Needs the `onnx` feature, and the onnxruntime
+/// library at `ORT_DYLIB_PATH`.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OnnxDescriptor {
+    pub name: String,        // Name predictions are published under
+    pub model_path: String,  // Path to the .onnx model on the machine
+    pub labels: Vec<String>, // Label of each output, in order
+    pub max_points: usize,   // Points per pointcloud, extra points are dropped
+    pub softmax: bool,       // Whether the model outputs logits
+    pub threads: usize,      // Threads for running the model
+}
+
+impl Default for OnnxDescriptor {
+    fn default() -> Self {
+        Self {
+            name: "onnx".to_owned(),
+            model_path: String::new(),
+            labels: Vec::new(),
+            max_points: 64,
+            softmax: false,
+            threads: 1,
+        }
+    }
+}
+
+#[typetag::serde]
+impl ClassifierDescriptor for OnnxDescriptor {
+    #[cfg(feature = "onnx")]
+    fn build(&self) -> Result<Box<dyn WindowClassifier>, ClassifierError> {
+        if self.max_points == 0 {
+            return Err(ClassifierError::Load(
+                "Max points must be at least 1".to_owned(),
+            ));
+        }
+        Ok(Box::new(runtime::OnnxClassifier::new(self.clone())?))
+    }
+
+    #[cfg(not(feature = "onnx"))]
+    fn build(&self) -> Result<Box<dyn WindowClassifier>, ClassifierError> {
+        Err(ClassifierError::Load(
+            "Built without the onnx feature".to_owned(),
+        ))
+    }
+
+    fn clone_boxed(&self) -> Box<dyn ClassifierDescriptor> {
+        Box::new(self.clone())
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Name:");
+            ui.text_edit_singleline(&mut self.name);
+            ui.label("Model path:");
+            ui.text_edit_singleline(&mut self.model_path);
+        });
+        let mut labels = self.labels.join(", ");
+        ui.horizontal(|ui| {
+            ui.label("Labels:");
+            if ui.text_edit_singleline(&mut labels).changed() {
+                self.labels = labels
+                    .split(',')
+                    .map(|label| label.trim().to_owned())
+                    .filter(|label| !label.is_empty())
+                    .collect();
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Max points:");
+            ui.add(egui::DragValue::new(&mut self.max_points).clamp_range(1..=4096));
+            ui.label("Threads:");
+            ui.add(egui::DragValue::new(&mut self.threads).clamp_range(1..=64));
+            ui.checkbox(&mut self.softmax, "Softmax");
+        });
+    }
+}
+
+/// The model input for a window, row major over pointclouds, points and
+/// x, y, z and velocity
+pub fn features(window: &[PointCloud], max_points: usize) -> Vec<f32> {
+    let mut features = vec![0.0; window.len() * max_points * 4];
+    for (pointcloud, frame) in window.iter().zip(features.chunks_mut(max_points * 4)) {
+        for (point, values) in pointcloud.points.iter().zip(frame.chunks_mut(4)) {
+            values.copy_from_slice(&[point.x, point.y, point.z, point.v]);
+        }
+    }
+    features
+}
+
+/// Pairs the model outputs with their labels, best first. Outputs without a
+/// label are named by their index.
+pub fn scores(outputs: &[f32], labels: &[String], softmax: bool) -> Vec<Score> {
+    let mut outputs = outputs.to_vec();
+    if softmax {
+        let max = outputs.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        outputs.iter_mut().for_each(|x| *x = (*x - max).exp());
+        let total: f32 = outputs.iter().sum();
+        outputs.iter_mut().for_each(|x| *x /= total);
+    }
+    rank(
+        outputs
+            .into_iter()
+            .enumerate()
+            .map(|(i, score)| Score {
+                label: labels.get(i).cloned().unwrap_or_else(|| i.to_string()),
+                score,
+            })
+            .collect(),
+    )
+}
+
+#[cfg(feature = "onnx")]
+mod runtime {
+    use mmwave_core::{
+        classify::{ClassifierError, WindowClassifier},
+        pointcloud::PointCloud,
+        telemetry::Score,
+    };
+    use ort::{session::Session, value::Tensor};
+
+    use super::{features, scores, OnnxDescriptor};
+
+    pub struct OnnxClassifier {
+        descriptor: OnnxDescriptor,
+        session: Session,
+    }
+
+    impl OnnxClassifier {
+        pub fn new(descriptor: OnnxDescriptor) -> Result<Self, ClassifierError> {
+            let session = Session::builder()
+                .and_then(|builder| builder.with_intra_threads(descriptor.threads))
+                .and_then(|builder| builder.commit_from_file(&descriptor.model_path))
+                .map_err(|e| ClassifierError::Load(format!("{}: {}", descriptor.model_path, e)))?;
+            Ok(Self {
+                descriptor,
+                session,
+            })
+        }
+    }
+
+    impl WindowClassifier for OnnxClassifier {
+        fn classify(&mut self, window: &[PointCloud]) -> Result<Vec<Score>, ClassifierError> {
+            let max_points = self.descriptor.max_points;
+            let shape = [1, window.len(), max_points, 4];
+            let input = Tensor::from_array((shape, features(window, max_points)))
+                .map_err(|e| ClassifierError::Classify(e.to_string()))?;
+            let outputs = self
+                .session
+                .run(ort::inputs![input])
+                .map_err(|e| ClassifierError::Classify(e.to_string()))?;
+            let (_, outputs) = outputs[0]
+                .try_extract_tensor::<f32>()
+                .map_err(|e| ClassifierError::Classify(e.to_string()))?;
+            Ok(scores(
+                outputs,
+                &self.descriptor.labels,
+                self.descriptor.softmax,
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mmwave_core::pointcloud::PointCloud;
+
+    use super::{features, scores};
+
+    #[test]
+    pub fn test_onnx_io() {
+        let window = [
+            PointCloud::from(vec![
+                [1.0, 2.0, 3.0, 4.0].into(),
+                [5.0, 6.0, 7.0, 8.0].into(),
+            ]),
+            PointCloud::from(vec![[9.0, 9.0, 9.0, 9.0].into()]),
+        ];
+        let features = features(&window, 1);
+        assert_eq!(features, vec![1.0, 2.0, 3.0, 4.0, 9.0, 9.0, 9.0, 9.0]);
+
+        let labels = vec!["wave".to_owned(), "swipe".to_owned()];
+        let scores = scores(&[0.0, 2.0, 1.0], &labels, true);
+        assert_eq!(scores[0].label, "swipe");
+        assert_eq!(scores[1].label, "2");
+        let total: f32 = scores.iter().map(|s| s.score).sum();
+        assert!((total - 1.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,244 @@
+use async_nats::{
+    connection::State,
+    jetstream::{
+        self,
+        kv::{Entry, Watch},
+    },
+    Client,
+};
+use async_trait::async_trait;
+use egui::Ui;
+use futures::StreamExt;
+use mmwave_core::{
+    address::ServerAddress,
+    cluster::{cluster, DbscanParams},
+    config::Configuration,
+    devices::DeviceDescriptor,
+    message::{Id, Tag},
+    nats::get_store,
+    pointcloud::PointCloud,
+    pubsub::{Publisher, Subscription},
+    subject::SubjectFilter,
+    telemetry::DeviceState,
+    wire::Encoding,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    any::{Any, TypeId},
+    error::Error,
+    fmt::Display,
+    time::Duration,
+};
+use tokio::{
+    select,
+    task::{spawn_blocking, yield_now},
+};
+use tracing::{error, info, instrument, warn};
+
+/// Clusters the pointclouds (or fused or filtered pointclouds) of the source
+/// devices into objects with DBSCAN, publishing a summary of each cluster.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct ClusterDescriptor {
+    pub sources: Vec<Id>,     // Devices whose pointclouds are clustered
+    pub params: DbscanParams, // Parameters for DBSCAN
+    pub encoding: Encoding,   // Encoding of published messages
+}
+
+impl Eq for ClusterDescriptor {}
+
+impl Display for ClusterDescriptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sources: Vec<String> = self.sources.iter().map(|id| id.to_string()).collect();
+        write!(f, "Cluster ({})", sources.join(", "))
+    }
+}
+
+#[typetag::serde]
+#[async_trait]
+impl DeviceDescriptor for ClusterDescriptor {
+    #[instrument(skip_all, fields(self=%self, id=%id))]
+    async fn init(self: Box<Self>, id: Id, address: ServerAddress) {
+        if let Err(e) = start_cluster(*self, id, address).await {
+            error!(error=?e, "Cluster closed with error");
+        }
+    }
+
+    fn clone_boxed(&self) -> Box<dyn DeviceDescriptor> {
+        Box::new(self.clone())
+    }
+
+    fn title(&self) -> String {
+        format!("{}", self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.label("Sources:");
+        let mut removed = None;
+        for (i, source) in self.sources.iter_mut().enumerate() {
+            ui.push_id(("source", i), |ui| {
+                ui.horizontal(|ui| {
+                    source.ui(ui);
+                    if ui.button("remove").clicked() {
+                        removed = Some(i);
+                    }
+                });
+            });
+        }
+        if let Some(i) = removed {
+            self.sources.remove(i);
+        }
+        if ui.button("add source").clicked() {
+            self.sources.push(Id::Device(0, 0));
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Eps (m):");
+            for eps in self.params.eps.iter_mut() {
+                ui.add(
+                    egui::DragValue::new(eps)
+                        .speed(0.01)
+                        .clamp_range(0.01..=10.0),
+                );
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Eps velocity (m/s):");
+            ui.add(
+                egui::DragValue::new(&mut self.params.eps_velocity)
+                    .speed(0.01)
+                    .clamp_range(0.0..=20.0),
+            );
+            ui.label("Min points:");
+            ui.add(egui::DragValue::new(&mut self.params.min_points).clamp_range(1..=100));
+        });
+        self.encoding.ui(ui);
+    }
+}
+
+#[instrument(skip_all)]
+async fn start_cluster(
+    mut descriptor: ClusterDescriptor,
+    id: Id,
+    address: ServerAddress,
+) -> Result<(), Box<dyn Error>> {
+    // Connect to the NATS server
+    let client = async_nats::connect(address.address().to_string()).await?;
+    let jetstream = jetstream::new(client.clone());
+
+    // Listen for config updates on a separate task
+    let store = get_store(jetstream).await?;
+    let mut entries = store.watch("config").await?;
+
+    let mut interval = tokio::time::interval(Duration::from_millis(5000));
+    loop {
+        // Verify the client connection state
+        if client.connection_state() == State::Disconnected {
+            return Err(String::from("Lost connection to NATS").into());
+        }
+
+        let result = run_cluster(&client, &mut entries, &mut descriptor, id)
+            .await
+            .map_err(|e| e.to_string());
+        if let Err(e) = result {
+            error!(error=%e, "Cluster stopped running");
+            let publisher = Publisher::new(client.clone(), id);
+            if let Err(e) = publisher.error(descriptor.title(), e).await {
+                warn!(error=%e, "Failed to report cluster error");
+            }
+        }
+        interval.tick().await;
+    }
+}
+
+#[instrument(skip_all)]
+async fn run_cluster(
+    client: &Client,
+    entries: &mut Watch,
+    descriptor: &mut ClusterDescriptor,
+    id: Id,
+) -> Result<(), Box<dyn Error>> {
+    let mut publisher = Publisher::new(client.clone(), id).with_encoding(descriptor.encoding);
+    publisher
+        .status(DeviceState::Running, descriptor.title(), None)
+        .await?;
+    if descriptor.sources.is_empty() {
+        warn!("Cluster device has no sources");
+    }
+
+    // Sources may be ordinary devices, fusion devices or filter devices
+    let mut pointclouds = Subscription::<PointCloud>::new(client, SubjectFilter::all()).await?;
+    let mut fused =
+        Subscription::<PointCloud>::with_kind(client, SubjectFilter::all(), Tag::Fused).await?;
+    let mut filtered =
+        Subscription::<PointCloud>::with_kind(client, SubjectFilter::all(), Tag::Filtered).await?;
+
+    loop {
+        yield_now().await;
+        let received = select! {
+            Some(config) = entries.next() => {
+                maintain_config(config?, descriptor, id)?;
+                publisher.set_encoding(descriptor.encoding);
+                continue;
+            }
+            received = pointclouds.next() => received,
+            received = fused.next() => received,
+            received = filtered.next() => received,
+        };
+        let Some(received) = received else {
+            return Err("Pointcloud subscription closed".into());
+        };
+        let Some(from) = received
+            .from
+            .filter(|from| *from != id && descriptor.sources.contains(from))
+        else {
+            continue;
+        };
+        // Clustering a large cloud takes a while, so it runs off the async workers
+        let (pointcloud, params) = (received.content, descriptor.params);
+        let mut clusters = spawn_blocking(move || cluster(&pointcloud, &params)).await?;
+        clusters.source = Some(from);
+        publisher.publish(clusters).await?;
+    }
+}
+
+fn maintain_config(
+    entry: Entry,
+    descriptor: &mut ClusterDescriptor,
+    id: Id,
+) -> Result<(), Box<dyn Error>> {
+    let Ok(configuration) = serde_json::from_slice::<Configuration>(&entry.value) else {
+        return Ok(());
+    };
+
+    for device_config in configuration.descriptors {
+        if device_config.id != id {
+            continue;
+        }
+
+        let erased_desc = device_config.device_descriptor.as_any();
+
+        let updated_desc = match erased_desc.downcast_ref::<ClusterDescriptor>() {
+            Some(cluster_desc) => cluster_desc,
+            None => {
+                tracing::error!(
+                    "Failed to downcast: actual type id = {:?}, expected type id = {:?}",
+                    erased_desc.type_id(),
+                    TypeId::of::<Box<ClusterDescriptor>>()
+                );
+                continue;
+            }
+        };
+
+        if descriptor != updated_desc {
+            info!("Updated cluster descriptor");
+            *descriptor = updated_desc.clone();
+        }
+    }
+
+    Ok(())
+}
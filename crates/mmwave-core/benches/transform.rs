@@ -0,0 +1,62 @@
+use std::f32::consts::PI;
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use mmwave_core::{point::Point, pointcloud::PointCloud, transform::Transform};
+
+const POINTS: usize = 10_000;
+
+fn pointcloud() -> PointCloud {
+    PointCloud {
+        points: (0..POINTS)
+            .map(|i| {
+                let i = i as f32;
+                Point {
+                    x: (i * 0.37).sin() * 5.0,
+                    y: (i * 0.11).cos() * 8.0,
+                    z: (i * 0.07).sin(),
+                    v: (i * 0.03).cos(),
+                }
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+fn transform_pointcloud(c: &mut Criterion) {
+    let transform = Transform {
+        translation: [1.0, -2.0, 1.5],
+        orientation: [PI / 3., -0.2, 0.1],
+    };
+    let pointcloud = pointcloud();
+
+    let mut group = c.benchmark_group("transform_10k_points");
+    group.throughput(Throughput::Elements(POINTS as u64));
+    group.bench_function("apply_per_point", |b| {
+        b.iter_batched_ref(
+            || pointcloud.clone(),
+            |pointcloud| {
+                for pt in pointcloud.points.iter_mut() {
+                    let v = pt.v;
+                    *pt = transform.apply((*pt).into()).into();
+                    pt.v = v;
+                }
+                black_box(pointcloud);
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("apply_to_cloud", |b| {
+        b.iter_batched_ref(
+            || pointcloud.clone(),
+            |pointcloud| {
+                black_box(&transform).apply_to_cloud(pointcloud);
+                black_box(pointcloud);
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, transform_pointcloud);
+criterion_main!(benches);
@@ -1,126 +1,212 @@
-use crate::core::pointcloud::PointCloud;
-use std::{
-    collections::{HashMap, VecDeque},
-    fmt,
-    time::{Duration, SystemTime, UNIX_EPOCH},
-};
+use std::collections::{BTreeMap, VecDeque};
+
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{message::Id, pointcloud::PointCloud};
+
+/// What to do with a cloud whose window has already been emitted
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum LatePolicy {
+    /// Discard it
+    #[default]
+    Drop,
+    /// Fuse it into the oldest window that has not been emitted yet
+    NextWindow,
+    /// Emit it on its own, with its original timestamp
+    Alone,
+}
 
+/// Buckets pointclouds from many devices into fixed windows of time by their
+/// timestamps, fusing each window into a single cloud once no more clouds are
+/// expected for it. Windows are aligned to the unix epoch, so accumulators
+/// with the same window size agree on where windows start.
+#[derive(Debug, Clone)]
 pub struct Accumulator {
-    blocks: HashMap<u128, Vec<PointCloud>>,
-    finished: VecDeque<PointCloud>,
-    threshold: u128, // Threshold in ms before a block is complete
-    peeked: bool,
+    window: Duration,
+    latency: Duration, // How long after a window ends to wait for its clouds
+    late: LatePolicy,
+    windows: BTreeMap<DateTime<Utc>, PointCloud>, // Open windows, by start time
+    ready: VecDeque<PointCloud>,
+    emitted_until: Option<DateTime<Utc>>, // End of the newest emitted window
+    dropped: usize,
 }
 
-impl fmt::Debug for Accumulator {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut debug_struct = f.debug_struct("TimeBlockMap");
-
-        // Add custom formatting for 'blocks'
-        // debug_struct.field("blocks", &self.blocks);
-        debug_struct.field(
-            "blocks",
-            &self
-                .blocks
-                .iter()
-                .map(|(k, v)| (k, v.iter().map(|p| p.time).collect::<Vec<_>>()))
-                .collect::<HashMap<_, _>>(),
-        );
-
-        // Add custom formatting for 'd'
-        debug_struct.field("finished", &self.finished.len());
+impl Accumulator {
+    pub fn new(window: Duration, latency: Duration) -> Self {
+        Accumulator {
+            window: window.max(Duration::nanoseconds(1)),
+            latency: latency.max(Duration::zero()),
+            late: LatePolicy::default(),
+            windows: BTreeMap::new(),
+            ready: VecDeque::new(),
+            emitted_until: None,
+            dropped: 0,
+        }
+    }
 
-        // Add 'threshold' field
-        debug_struct.field("threshold", &self.threshold);
+    pub fn with_late_policy(mut self, late: LatePolicy) -> Self {
+        self.late = late;
+        self
+    }
 
-        debug_struct.finish()
+    /// The start of the window time falls in
+    pub fn window_start(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        time.duration_trunc(self.window).unwrap_or(time)
     }
-}
 
-impl Accumulator {
-    pub fn new(threshold: u128) -> Self {
-        Accumulator {
-            blocks: HashMap::new(),
-            finished: VecDeque::new(),
-            threshold,
-            peeked: false,
+    /// Adds a cloud from source to the window its timestamp falls in. Points
+    /// not already tagged with a source are tagged with source, so the fused
+    /// cloud keeps track of where each point came from. Returns false if the
+    /// cloud was late, or so far ahead of now that its sensor's clock must be
+    /// wrong, and dropped.
+    pub fn push(&mut self, now: DateTime<Utc>, source: Id, pointcloud: PointCloud) -> bool {
+        let mut pointcloud = pointcloud;
+        if pointcloud.attributes.source.len() != pointcloud.points.len() {
+            pointcloud = pointcloud.with_source(source);
         }
-    }
 
-    pub fn push(&mut self, item: PointCloud) {
-        if self.is_item_within_threshold(&item) {
-            let block = self.get_block_for_item(&item);
-            self.blocks.entry(block).or_insert_with(Vec::new).push(item);
+        let mut start = self.window_start(pointcloud.time);
+        // Its window would stay open until the clock caught up
+        if start > now + self.window + self.latency {
+            self.dropped += 1;
+            return false;
+        }
+        if let Some(emitted_until) = self.emitted_until.filter(|&until| start < until) {
+            match self.late {
+                LatePolicy::Drop => {
+                    self.dropped += 1;
+                    return false;
+                }
+                LatePolicy::NextWindow => start = emitted_until,
+                LatePolicy::Alone => {
+                    self.ready.push_back(pointcloud);
+                    return true;
+                }
+            }
         }
-    }
 
-    pub fn push_multiple(&mut self, items: Vec<PointCloud>) {
-        for item in items {
-            self.push(item);
+        match self.windows.get_mut(&start) {
+            Some(fused) => fused.extend(pointcloud),
+            None => {
+                pointcloud.time = start;
+                self.windows.insert(start, pointcloud);
+            }
         }
+        true
     }
 
-    fn get_block_for_item(&self, item: &PointCloud) -> u128 {
-        item.time - (item.time % 100)
+    /// When the oldest open window will be ready, if there is one
+    pub fn next_deadline(&self) -> Option<DateTime<Utc>> {
+        if !self.ready.is_empty() {
+            return Some(DateTime::<Utc>::MIN_UTC);
+        }
+        self.windows
+            .keys()
+            .next()
+            .map(|&start| start + self.window + self.latency)
     }
 
-    fn is_item_within_threshold(&self, item: &PointCloud) -> bool {
-        !self.is_block_old(self.get_block_for_item(&item))
+    /// Fused clouds of every window that ended at least the latency before
+    /// now, oldest first. Clouds arriving for these windows afterwards are late.
+    pub fn pop_ready(&mut self, now: DateTime<Utc>) -> Vec<PointCloud> {
+        while let Some(entry) = self.windows.first_entry() {
+            let end = *entry.key() + self.window;
+            if end + self.latency > now {
+                break;
+            }
+            self.ready.push_back(entry.remove());
+            self.emitted_until = Some(self.emitted_until.map_or(end, |until| until.max(end)));
+        }
+        self.ready.drain(..).collect()
     }
 
-    fn is_block_old(&self, block: u128) -> bool {
-        let current_time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .map(|t| t.as_millis())
-            .unwrap_or(0);
-
-        (current_time > block) && (current_time - block > self.threshold)
+    /// Fused clouds of every open window, regardless of whether they are ready
+    pub fn flush(&mut self) -> Vec<PointCloud> {
+        self.pop_ready(DateTime::<Utc>::MAX_UTC)
     }
 
-    pub fn reorganize(&mut self) {
-        let old_blocks = self
-            .blocks
-            .keys()
-            .cloned()
-            .filter(|&k| self.is_block_old(k))
-            .collect::<Vec<_>>();
-
-        for block in old_blocks {
-            if let Some(items) = self.blocks.remove(&block) {
-                let merged_item = items.into_iter().reduce(|mut a, b| {
-                    a.extend(b);
-                    a
-                });
-                if let Some(mut item) = merged_item {
-                    item.time = block;
-                    self.finished.push_back(item);
-                    self.peeked = false;
-                }
-            }
-        }
+    /// Number of late (or future) clouds dropped so far
+    pub fn dropped(&self) -> usize {
+        self.dropped
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
 
-    pub fn pop_finished(&mut self) -> VecDeque<PointCloud> {
-        let mut replacement = VecDeque::new();
-        std::mem::swap(&mut replacement, &mut self.finished);
-        replacement
+    use super::{Accumulator, LatePolicy};
+    use crate::{message::Id, pointcloud::PointCloud};
+
+    fn cloud(ms: i64, points: usize) -> PointCloud {
+        let mut pointcloud = PointCloud::from(vec![[0.0, 1.0, 0.0, 0.0].into(); points]);
+        pointcloud.time = Utc.timestamp_millis_opt(1_700_000_000_000 + ms).unwrap();
+        pointcloud
     }
 
-    pub fn peekable(&self) -> bool {
-        !self.peeked
+    #[test]
+    pub fn test_fuses_windows() {
+        let (a, b) = (Id::Device(1, 0), Id::Device(2, 0));
+        let mut accumulator =
+            Accumulator::new(Duration::milliseconds(100), Duration::milliseconds(50));
+        let start = cloud(0, 0).time;
+        accumulator.push(start, a, cloud(10, 2));
+        accumulator.push(start, b, cloud(90, 1));
+        accumulator.push(start, a, cloud(110, 3));
+
+        assert!(accumulator
+            .pop_ready(start + Duration::milliseconds(140))
+            .is_empty());
+        let fused = accumulator.pop_ready(start + Duration::milliseconds(150));
+        assert_eq!(fused.len(), 1);
+        assert_eq!(fused[0].time, start);
+        assert_eq!(fused[0].attributes.source, vec![a, a, b]);
+        assert_eq!(
+            accumulator.next_deadline(),
+            Some(start + Duration::milliseconds(250))
+        );
+        assert_eq!(accumulator.flush()[0].points.len(), 3);
     }
 
-    pub async fn peek(&mut self) -> Option<PointCloud> {
-        while !self.peekable() {
-            // Recheck every 100ms
-            tokio::time::sleep(Duration::from_millis(100));
+    #[test]
+    pub fn test_late_policy() {
+        let a = Id::Device(1, 0);
+        let start = cloud(0, 0).time;
+        for (policy, expected) in [
+            (LatePolicy::Drop, vec![2]),
+            (LatePolicy::NextWindow, vec![3]),
+            (LatePolicy::Alone, vec![1, 2]),
+        ] {
+            let mut accumulator = Accumulator::new(Duration::milliseconds(100), Duration::zero())
+                .with_late_policy(policy);
+            accumulator.push(start, a, cloud(10, 1));
+            let now = start + Duration::milliseconds(100);
+            accumulator.pop_ready(now);
+            accumulator.push(now, a, cloud(120, 2));
+            accumulator.push(now, a, cloud(20, 1));
+            let sizes: Vec<_> = accumulator.flush().iter().map(|p| p.points.len()).collect();
+            assert_eq!(sizes, expected, "{:?}", policy);
+            assert_eq!(accumulator.dropped(), (policy == LatePolicy::Drop) as usize);
         }
-        self.peeked = true;
-        self.finished.back().cloned()
     }
-
-    /// Returns the front of finished, which is the oldest item stored
-    pub async fn pop_single_finished(&mut self) -> Option<PointCloud> {
-        self.finished.pop_front()
+    #[test]
+    pub fn test_drop_future() {
+        let a = Id::Device(1, 0);
+        let now = cloud(0, 0).time;
+        let mut accumulator =
+            Accumulator::new(Duration::milliseconds(100), Duration::milliseconds(50));
+        // A sensor whose clock runs an hour ahead
+        for i in 0..10 {
+            assert!(!accumulator.push(now, a, cloud(3_600_000 + i * 100, 1)));
+        }
+        assert_eq!(accumulator.dropped(), 10);
+        assert_eq!(accumulator.next_deadline(), None);
+
+        // Clouds for the next window are still taken, but not the one after
+        assert!(accumulator.push(now, a, cloud(150, 1)));
+        assert!(!accumulator.push(now, a, cloud(250, 1)));
+        assert_eq!(accumulator.dropped(), 11);
+        assert_eq!(accumulator.flush().len(), 1);
     }
 }
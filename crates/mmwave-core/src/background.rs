@@ -0,0 +1,324 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use chrono::{DateTime, Utc};
+use egui::{DragValue, Ui};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::pointcloud::PointCloud;
+
+#[derive(Debug, Error)]
+pub enum BackgroundError {
+    #[error("Failed to access background file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid background file: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Background was learnt with {saved} m voxels, not the configured {configured} m")]
+    VoxelSize { saved: f32, configured: f32 },
+}
+
+/// How a device learns the static returns of its surroundings and removes
+/// them. Unlike static clutter removal, a person standing still is only
+/// removed if they were there while the background was learnt.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct BackgroundParams {
+    pub enabled: bool,
+    pub learn_s: f32,     // Seconds of frames to learn from when starting
+    pub voxel_size: f32,  // Edge length of a cell of the model (m)
+    pub max_speed: f32,   // Points slower than this (m/s) are static
+    pub min_density: f32, // Fraction of frames a cell must be seen in to be background
+    pub path: String,     // File the model is saved to and loaded from, if set
+    pub relearn: bool,    // Learn on start even if a model is saved
+}
+
+impl Default for BackgroundParams {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            learn_s: 30.0,
+            voxel_size: 0.2,
+            max_speed: 0.05,
+            min_density: 0.3,
+            path: String::new(),
+            relearn: false,
+        }
+    }
+}
+
+impl BackgroundParams {
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.enabled, "Subtract background");
+            ui.checkbox(&mut self.relearn, "Relearn on start");
+        });
+        ui.horizontal(|ui| {
+            ui.label("Learn for (s):");
+            ui.add(DragValue::new(&mut self.learn_s).clamp_range(1.0..=3600.0));
+            ui.label("Voxel size (m):");
+            ui.add(
+                DragValue::new(&mut self.voxel_size)
+                    .speed(0.01)
+                    .clamp_range(0.01..=2.0),
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.label("Max speed (m/s):");
+            ui.add(
+                DragValue::new(&mut self.max_speed)
+                    .speed(0.01)
+                    .clamp_range(0.0..=1.0),
+            );
+            ui.label("Min density:");
+            ui.add(
+                DragValue::new(&mut self.min_density)
+                    .speed(0.01)
+                    .clamp_range(0.0..=1.0),
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.label("Background file:");
+            ui.text_edit_singleline(&mut self.path);
+        });
+    }
+}
+
+/// A cell of a background, and the fraction of learning frames a static
+/// return was seen in it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BackgroundCell {
+    pub voxel: [i32; 3],
+    pub density: f32,
+}
+
+/// The spatial density of static returns of a device, in its sensor frame so
+/// that it survives the device being moved in the configuration
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Background {
+    pub time: DateTime<Utc>, // When learning finished
+    pub voxel_size: f32,
+    pub frames: u32, // Frames learnt from
+    pub cells: Vec<BackgroundCell>,
+}
+
+impl Background {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BackgroundError> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), BackgroundError> {
+        Ok(fs::write(path, serde_json::to_string(self)?)?)
+    }
+}
+
+/// Learns the background of a device from its first frames, then removes the
+/// static points that fall in it
+#[derive(Debug, Clone)]
+pub struct BackgroundModel {
+    params: BackgroundParams,
+    started: Option<DateTime<Utc>>,
+    frames: u32,
+    counts: HashMap<[i32; 3], u32>, // Learning frames with a static return in each cell
+    background: HashMap<[i32; 3], f32>, // Density of each cell once learnt
+    learnt: bool,
+}
+
+impl BackgroundModel {
+    /// A model that starts learning with the first frame
+    pub fn new(params: BackgroundParams) -> Self {
+        Self {
+            params,
+            started: None,
+            frames: 0,
+            counts: HashMap::new(),
+            background: HashMap::new(),
+            learnt: false,
+        }
+    }
+
+    /// A model using a previously learnt background, which must have been
+    /// learnt with the same voxel size
+    pub fn with_background(
+        params: BackgroundParams,
+        background: &Background,
+    ) -> Result<Self, BackgroundError> {
+        if background.voxel_size != params.voxel_size {
+            return Err(BackgroundError::VoxelSize {
+                saved: background.voxel_size,
+                configured: params.voxel_size,
+            });
+        }
+        let mut model = Self::new(params);
+        model.background = background
+            .cells
+            .iter()
+            .map(|cell| (cell.voxel, cell.density))
+            .collect();
+        model.learnt = true;
+        Ok(model)
+    }
+
+    /// A model using the background saved at the configured path, or one that
+    /// learns it if there is none or relearning is asked for
+    pub fn load(params: BackgroundParams) -> Result<Self, BackgroundError> {
+        if params.relearn || params.path.is_empty() || !Path::new(&params.path).exists() {
+            return Ok(Self::new(params));
+        }
+        let background = Background::load(&params.path)?;
+        Self::with_background(params, &background)
+    }
+
+    pub fn is_learnt(&self) -> bool {
+        self.learnt
+    }
+
+    /// Learns from or subtracts the background from a sensor frame
+    /// pointcloud. Learning frames pass through untouched, and the background
+    /// is returned with the frame that finishes learning.
+    pub fn apply(&mut self, pointcloud: PointCloud) -> (PointCloud, Option<Background>) {
+        if !self.params.enabled {
+            return (pointcloud, None);
+        }
+        if self.learnt {
+            return (self.subtract(&pointcloud), None);
+        }
+
+        let started = *self.started.get_or_insert(pointcloud.time);
+        let mut occupied: Vec<[i32; 3]> = self.static_voxels(&pointcloud).collect();
+        occupied.sort_unstable();
+        occupied.dedup();
+        for voxel in occupied {
+            *self.counts.entry(voxel).or_default() += 1;
+        }
+        self.frames += 1;
+
+        let elapsed = (pointcloud.time - started).num_milliseconds() as f32 / 1000.0;
+        if elapsed < self.params.learn_s {
+            return (pointcloud, None);
+        }
+        let background = self.finish(pointcloud.time);
+        (pointcloud, Some(background))
+    }
+
+    fn finish(&mut self, time: DateTime<Utc>) -> Background {
+        let frames = self.frames.max(1) as f32;
+        self.background = self
+            .counts
+            .drain()
+            .map(|(voxel, count)| (voxel, count as f32 / frames))
+            .collect();
+        self.learnt = true;
+
+        let mut cells: Vec<BackgroundCell> = self
+            .background
+            .iter()
+            .map(|(&voxel, &density)| BackgroundCell { voxel, density })
+            .collect();
+        cells.sort_by_key(|cell| cell.voxel);
+        Background {
+            time,
+            voxel_size: self.params.voxel_size,
+            frames: self.frames,
+            cells,
+        }
+    }
+
+    fn subtract(&self, pointcloud: &PointCloud) -> PointCloud {
+        let indices: Vec<usize> = (0..pointcloud.points.len())
+            .filter(|&i| {
+                let point = pointcloud.points[i];
+                point.v.abs() > self.params.max_speed
+                    || self
+                        .background
+                        .get(&self.voxel(point.into()))
+                        .is_none_or(|&density| density < self.params.min_density)
+            })
+            .collect();
+        pointcloud.select(&indices)
+    }
+
+    fn static_voxels<'a>(
+        &'a self,
+        pointcloud: &'a PointCloud,
+    ) -> impl Iterator<Item = [i32; 3]> + 'a {
+        pointcloud
+            .points
+            .iter()
+            .filter(|p| p.v.abs() <= self.params.max_speed)
+            .map(|&p| self.voxel(p.into()))
+    }
+
+    fn voxel(&self, point: [f32; 3]) -> [i32; 3] {
+        point.map(|x| (x / self.params.voxel_size).floor() as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration, Utc};
+
+    use super::{Background, BackgroundError, BackgroundModel, BackgroundParams};
+    use crate::pointcloud::PointCloud;
+
+    #[test]
+    pub fn test_background_model() {
+        let params = BackgroundParams {
+            enabled: true,
+            learn_s: 1.0,
+            ..Default::default()
+        };
+        let mut model = BackgroundModel::new(params.clone());
+        let start = DateTime::<Utc>::default();
+        let frame = |ms: i64, points: Vec<[f32; 4]>| PointCloud {
+            time: start + Duration::milliseconds(ms),
+            ..PointCloud::from(points.into_iter().map(|p| p.into()).collect::<Vec<_>>())
+        };
+
+        // A wall is always there, a chair only for one frame in ten
+        let mut learnt = None;
+        for i in 0..11 {
+            let mut points = vec![[1.05, 2.05, 0.05, 0.0]];
+            if i == 0 {
+                points.push([-1.05, 1.05, 0.05, 0.0]);
+            }
+            let (passed, background) = model.apply(frame(i * 100, points.clone()));
+            assert_eq!(passed.points.len(), points.len());
+            learnt = learnt.or(background);
+        }
+        let learnt = learnt.unwrap();
+        assert!(model.is_learnt());
+        assert_eq!(learnt.frames, 11);
+        assert_eq!(learnt.cells.len(), 2);
+
+        // The wall is removed, but not the chair or someone moving by it
+        let passed = model.apply(frame(
+            2000,
+            vec![
+                [1.1, 2.1, 0.1, 0.0],
+                [1.1, 2.1, 0.1, 0.5],
+                [-1.1, 1.1, 0.1, 0.0],
+            ],
+        ));
+        assert_eq!(passed.0.points.len(), 2);
+
+        // The same happens with the background loaded from a file
+        let path = std::env::temp_dir().join("mmwave_test_background.json");
+        learnt.save(&path).unwrap();
+        let loaded = Background::load(&path).unwrap();
+        assert_eq!(loaded, learnt);
+        let model = BackgroundModel::with_background(params.clone(), &loaded).unwrap();
+        assert!(model.is_learnt());
+
+        // But not when it was learnt with other voxels
+        let params = BackgroundParams {
+            voxel_size: params.voxel_size * 2.0,
+            path: path.to_string_lossy().into_owned(),
+            ..params
+        };
+        assert!(matches!(
+            BackgroundModel::load(params),
+            Err(BackgroundError::VoxelSize { .. })
+        ));
+        let _ = std::fs::remove_file(path);
+    }
+}
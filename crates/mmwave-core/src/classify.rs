@@ -0,0 +1,126 @@
+use std::{any::Any, collections::VecDeque, fmt::Debug};
+
+use egui::Ui;
+use thiserror::Error;
+
+use crate::{pointcloud::PointCloud, telemetry::Score};
+
+#[derive(Debug, Error)]
+pub enum ClassifierError {
+    #[error("Failed to load classifier: {0}")]
+    Load(String),
+    #[error("Failed to classify window: {0}")]
+    Classify(String),
+}
+
+/// Labels a time ordered window of pointclouds, e.g. with a gesture or an
+/// activity
+pub trait WindowClassifier: Send {
+    /// A score for each label the classifier knows, given a window with the
+    /// oldest pointcloud first
+    fn classify(&mut self, window: &[PointCloud]) -> Result<Vec<Score>, ClassifierError>;
+}
+
+/// The settings of a classifier, from which a classification device builds
+/// it. New kinds of classifier are registered by implementing this with
+/// `#[typetag::serde]`, after which they can be named in a configuration.
+#[typetag::serde(tag = "type", content = "value")]
+pub trait ClassifierDescriptor: Send + Sync + Any {
+    fn build(&self) -> Result<Box<dyn WindowClassifier>, ClassifierError>;
+    fn clone_boxed(&self) -> Box<dyn ClassifierDescriptor>;
+    /// Name the predictions of the classifier are published under
+    fn name(&self) -> String;
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.label("Unimplemented config for this classifier");
+    }
+}
+
+impl Clone for Box<dyn ClassifierDescriptor> {
+    fn clone(&self) -> Self {
+        self.clone_boxed()
+    }
+}
+
+impl Debug for Box<dyn ClassifierDescriptor> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl PartialEq for Box<dyn ClassifierDescriptor> {
+    fn eq(&self, other: &Self) -> bool {
+        // Descriptors of different types serialize differently, so this is
+        // enough to tell whether a classifier has to be rebuilt
+        match (serde_json::to_value(self), serde_json::to_value(other)) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// Sorts scores best first
+pub fn rank(mut scores: Vec<Score>) -> Vec<Score> {
+    scores.sort_by(|a, b| b.score.total_cmp(&a.score));
+    scores
+}
+
+/// Keeps the latest pointclouds of a device, offering them up as a window
+/// every `stride` pointclouds once there are `length` of them
+#[derive(Debug, Clone)]
+pub struct Windower {
+    length: usize,
+    stride: usize,
+    pointclouds: VecDeque<PointCloud>,
+    since: usize, // Pointclouds pushed since the last window
+}
+
+impl Windower {
+    pub fn new(length: usize, stride: usize) -> Self {
+        Self {
+            length: length.max(1),
+            stride: stride.max(1),
+            pointclouds: VecDeque::new(),
+            since: 0,
+        }
+    }
+
+    /// Adds the next pointcloud, returning the window ending with it if one
+    /// is due
+    pub fn push(&mut self, pointcloud: PointCloud) -> Option<&[PointCloud]> {
+        self.pointclouds.push_back(pointcloud);
+        if self.pointclouds.len() > self.length {
+            self.pointclouds.pop_front();
+        }
+        self.since += 1;
+        if self.pointclouds.len() < self.length || self.since < self.stride {
+            return None;
+        }
+        self.since = 0;
+        Some(self.pointclouds.make_contiguous())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+
+    use super::Windower;
+    use crate::pointcloud::PointCloud;
+
+    #[test]
+    pub fn test_windower() {
+        let mut windower = Windower::new(3, 2);
+        let mut windows = Vec::new();
+        for i in 0..8 {
+            let pointcloud = PointCloud {
+                time: DateTime::<Utc>::from_timestamp(i, 0).unwrap(),
+                ..Default::default()
+            };
+            if let Some(window) = windower.push(pointcloud) {
+                let times: Vec<i64> = window.iter().map(|pc| pc.time.timestamp()).collect();
+                windows.push(times);
+            }
+        }
+        assert_eq!(windows, vec![vec![0, 1, 2], vec![2, 3, 4], vec![4, 5, 6]]);
+    }
+}
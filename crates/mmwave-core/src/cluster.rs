@@ -0,0 +1,206 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    pointcloud::PointCloud,
+    spatial::KdTree,
+    telemetry::{Cluster, Clusters},
+};
+
+/// Parameters for DBSCAN. Two points are neighbours when their difference,
+/// scaled by eps along each axis (and by the velocity eps), has a length of
+/// at most one, so eps describes an ellipsoid rather than a sphere.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct DbscanParams {
+    pub eps: [f32; 3],     // Largest distance in metres along x, y and z
+    pub eps_velocity: f32, // Largest radial velocity difference in m/s, 0 to ignore velocity
+    pub min_points: usize, // Neighbours (including itself) a point needs to be a core point
+}
+
+impl Default for DbscanParams {
+    fn default() -> Self {
+        Self {
+            eps: [0.5, 0.5, 1.0],
+            eps_velocity: 1.0,
+            min_points: 4,
+        }
+    }
+}
+
+impl DbscanParams {
+    fn neighbours(&self, a: &[f32; 4], b: &[f32; 4]) -> bool {
+        let mut distance = 0.0;
+        for k in 0..3 {
+            distance += ((a[k] - b[k]) / self.eps[k]).powi(2);
+        }
+        if self.eps_velocity > 0.0 {
+            distance += ((a[3] - b[3]) / self.eps_velocity).powi(2);
+        }
+        distance <= 1.0
+    }
+}
+
+/// Labels each point with the cluster it belongs to, or None for noise.
+/// Clusters are numbered from 0 in the order they are found.
+pub fn dbscan(pointcloud: &PointCloud, params: &DbscanParams) -> Vec<Option<usize>> {
+    let points: Vec<[f32; 4]> = pointcloud
+        .points
+        .iter()
+        .map(|p| [p.x, p.y, p.z, p.v])
+        .collect();
+    let params = DbscanParams {
+        eps: params.eps.map(|e| e.max(f32::EPSILON)),
+        ..*params
+    };
+    // Scaled by eps, the spatial neighbours of a point are those within one
+    let scaled: Vec<[f32; 3]> = points
+        .iter()
+        .map(|p| [0, 1, 2].map(|k| p[k] / params.eps[k]))
+        .collect();
+    let tree = KdTree::new(&scaled);
+    let neighbours = |i: usize| -> Vec<usize> {
+        let mut found = tree.within(scaled[i], 1.0);
+        found.retain(|&j| params.neighbours(&points[i], &points[j]));
+        found
+    };
+
+    let mut labels: Vec<Option<usize>> = vec![None; points.len()];
+    let mut visited = vec![false; points.len()];
+    let mut clusters = 0;
+    for i in 0..points.len() {
+        if visited[i] {
+            continue;
+        }
+        visited[i] = true;
+        let seeds = neighbours(i);
+        if seeds.len() < params.min_points {
+            continue;
+        }
+
+        let cluster = clusters;
+        clusters += 1;
+        labels[i] = Some(cluster);
+        let mut queue = seeds;
+        while let Some(j) = queue.pop() {
+            if labels[j].is_none() {
+                labels[j] = Some(cluster);
+            }
+            if visited[j] {
+                continue;
+            }
+            visited[j] = true;
+            let expansion = neighbours(j);
+            if expansion.len() >= params.min_points {
+                queue.extend(expansion);
+            }
+        }
+    }
+    labels
+}
+
+/// Summarises the points of each cluster found by dbscan
+pub fn summarise(pointcloud: &PointCloud, labels: &[Option<usize>]) -> Vec<Cluster> {
+    let count = labels.iter().flatten().max().map_or(0, |&max| max + 1);
+    let mut clusters: Vec<Cluster> = (0..count)
+        .map(|id| Cluster {
+            id: id as u32,
+            centroid: [0.0; 3],
+            min: [f32::INFINITY; 3],
+            max: [f32::NEG_INFINITY; 3],
+            points: 0,
+            velocity: 0.0,
+        })
+        .collect();
+    for (point, label) in pointcloud.points.iter().zip(labels) {
+        let Some(cluster) = label.map(|label| &mut clusters[label]) else {
+            continue;
+        };
+        let position = [point.x, point.y, point.z];
+        for (k, x) in position.into_iter().enumerate() {
+            cluster.centroid[k] += x;
+            cluster.min[k] = cluster.min[k].min(x);
+            cluster.max[k] = cluster.max[k].max(x);
+        }
+        cluster.velocity += point.v;
+        cluster.points += 1;
+    }
+    for cluster in clusters.iter_mut() {
+        let n = cluster.points.max(1) as f32;
+        cluster.centroid = cluster.centroid.map(|x| x / n);
+        cluster.velocity /= n;
+    }
+    clusters
+}
+
+/// Clusters a pointcloud with dbscan and summarises the result
+pub fn cluster(pointcloud: &PointCloud, params: &DbscanParams) -> Clusters {
+    let labels = dbscan(pointcloud, params);
+    Clusters {
+        time: pointcloud.time,
+        source: None,
+        clusters: summarise(pointcloud, &labels),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cluster, dbscan, DbscanParams};
+    use crate::pointcloud::PointCloud;
+
+    #[test]
+    pub fn test_dbscan() {
+        let pointcloud = PointCloud::from(vec![
+            [0.0, 2.0, 0.0, 1.0].into(),
+            [0.2, 2.0, 0.0, 1.0].into(),
+            [0.4, 2.1, 0.0, 1.2].into(),
+            // Close to the first cluster, but moving the other way
+            [0.3, 2.0, 0.0, -2.0].into(),
+            [0.3, 2.1, 0.0, -2.0].into(),
+            [0.4, 2.0, 0.0, -2.1].into(),
+            [5.0, 5.0, 0.0, 0.0].into(),
+        ]);
+        let params = DbscanParams {
+            eps: [0.3, 0.3, 0.3],
+            eps_velocity: 0.5,
+            min_points: 3,
+        };
+        let labels = dbscan(&pointcloud, &params);
+        assert_eq!(
+            labels,
+            vec![Some(0), Some(0), Some(0), Some(1), Some(1), Some(1), None]
+        );
+
+        let clusters = cluster(&pointcloud, &params).clusters;
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0].points, 3);
+        assert_eq!(clusters[0].min, [0.0, 2.0, 0.0]);
+        assert!((clusters[1].velocity + 2.0333).abs() < 1e-3);
+
+        // Without velocity, they are one cluster
+        let params = DbscanParams {
+            eps_velocity: 0.0,
+            ..params
+        };
+        assert_eq!(cluster(&pointcloud, &params).clusters.len(), 1);
+    }
+
+    #[test]
+    pub fn test_dbscan_per_axis_eps() {
+        // A column of points 0.5m apart vertically
+        let pointcloud = PointCloud::from(
+            (0..4)
+                .map(|i| [0.0, 3.0, i as f32 * 0.5, 0.0].into())
+                .collect::<Vec<_>>(),
+        );
+        let params = DbscanParams {
+            eps: [0.3, 0.3, 0.6],
+            eps_velocity: 0.0,
+            min_points: 2,
+        };
+        assert_eq!(dbscan(&pointcloud, &params), vec![Some(0); 4]);
+        let params = DbscanParams {
+            eps: [0.6, 0.6, 0.3],
+            ..params
+        };
+        assert_eq!(dbscan(&pointcloud, &params), vec![None; 4]);
+    }
+}
@@ -1,6 +1,89 @@
-use crate::devices::DeviceConfig;
+use tracing::warn;
+
+use crate::{
+    devices::DeviceConfig,
+    frames::{FrameError, FrameTree},
+    ground::Plane,
+    message::Id,
+    transform::Transform,
+    zones::Zone,
+};
 
 #[derive(PartialEq, Debug, Clone, serde::Serialize, serde::Deserialize, Default)]
 pub struct Configuration {
     pub descriptors: Vec<DeviceConfig>,
+    #[serde(default)]
+    pub frames: FrameTree,
+    #[serde(default)]
+    pub zones: Vec<Zone>,
+    #[serde(default)]
+    pub floor: Plane, // Floor of the site in the world frame, heights are measured from it
+}
+
+impl Configuration {
+    pub fn device(&self, id: Id) -> Option<&DeviceConfig> {
+        self.descriptors.iter().find(|device| device.id == id)
+    }
+
+    /// The transform from a device's sensor frame into the world frame, None
+    /// if the device is not in the configuration or has no transform
+    pub fn sensor_to_world(&self, id: Id) -> Result<Option<Transform>, FrameError> {
+        let Some(device) = self.device(id) else {
+            return Ok(None);
+        };
+        let Some(transform) = device.device_descriptor.transform() else {
+            return Ok(None);
+        };
+        let parent = self.frames.to_world(&device.parent_frame)?;
+        Ok(Some(parent.compose(&transform)))
+    }
+
+    /// Moves a device so its sensor to world transform becomes the given one,
+    /// expressed relative to the device's parent frame. False if the device is
+    /// not in the configuration or has no transform.
+    pub fn set_sensor_to_world(
+        &mut self,
+        id: Id,
+        transform: &Transform,
+    ) -> Result<bool, FrameError> {
+        let Some(device) = self.descriptors.iter_mut().find(|device| device.id == id) else {
+            return Ok(false);
+        };
+        if device.device_descriptor.transform().is_none() {
+            return Ok(false);
+        }
+        let parent = self.frames.to_world(&device.parent_frame)?;
+        device
+            .device_descriptor
+            .set_transform(parent.inverse().compose(transform));
+        Ok(true)
+    }
+
+    /// The transform from a device's parent frame into the world frame, the
+    /// frame the poses of a moving device are given in
+    pub fn parent_to_world(&self, id: Id) -> Result<Option<Transform>, FrameError> {
+        let Some(device) = self.device(id) else {
+            return Ok(None);
+        };
+        Ok(Some(self.frames.to_world(&device.parent_frame)?))
+    }
+
+    /// Where the poses of the platform a device is mounted on come from, None
+    /// if the device does not move
+    pub fn pose_source(&self, id: Id) -> Option<Id> {
+        self.device(id).and_then(|device| device.pose_source)
+    }
+
+    /// Like `sensor_to_world`, but falling back to the given transform (usually
+    /// the device's own) when the configuration does not resolve one
+    pub fn sensor_to_world_or(&self, id: Id, fallback: &Transform) -> Transform {
+        match self.sensor_to_world(id) {
+            Ok(Some(transform)) => transform,
+            Ok(None) => fallback.clone(),
+            Err(e) => {
+                warn!(error=%e, %id, "Unable to resolve the frame of device");
+                fallback.clone()
+            }
+        }
+    }
 }
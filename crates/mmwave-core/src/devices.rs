@@ -4,12 +4,22 @@ use serde::{Deserialize, Serialize};
 use std::{any::Any, hash::Hash, time::Duration};
 use tracing::{info, instrument, warn};
 
-use crate::{address::ServerAddress, message::Id, point::Point, transform::Transform};
+use crate::{
+    address::ServerAddress,
+    frames::{self, WORLD},
+    message::Id,
+    point::Point,
+    transform::Transform,
+};
 
 #[derive(Serialize, Deserialize)]
 pub struct DeviceConfig {
     pub id: Id,
     pub device_descriptor: Box<dyn DeviceDescriptor>,
+    #[serde(default = "frames::world")]
+    pub parent_frame: String, // Frame the device's transform is relative to
+    #[serde(default)]
+    pub pose_source: Option<Id>, // Publisher of the pose of the platform the device is on, if it moves
 }
 
 impl Clone for DeviceConfig {
@@ -17,6 +27,8 @@ impl Clone for DeviceConfig {
         Self {
             id: self.id,
             device_descriptor: self.device_descriptor.clone_boxed(),
+            parent_frame: self.parent_frame.clone(),
+            pose_source: self.pose_source,
         }
     }
 }
@@ -42,6 +54,15 @@ impl Hash for DeviceConfig {
 }
 
 impl DeviceConfig {
+    pub fn new(id: Id, device_descriptor: Box<dyn DeviceDescriptor>) -> Self {
+        Self {
+            id,
+            device_descriptor,
+            parent_frame: WORLD.to_owned(),
+            pose_source: None,
+        }
+    }
+
     pub fn title(&self) -> String {
         self.device_descriptor.title()
     }
@@ -52,6 +73,19 @@ impl DeviceConfig {
 
     pub fn ui(&mut self, ui: &mut Ui) {
         self.id.ui(ui);
+        ui.horizontal(|ui| {
+            ui.label("Parent frame");
+            ui.text_edit_singleline(&mut self.parent_frame);
+        });
+        ui.horizontal(|ui| {
+            let mut moving = self.pose_source.is_some();
+            ui.checkbox(&mut moving, "Moving platform, pose from");
+            match (moving, &mut self.pose_source) {
+                (true, Some(source)) => source.ui(ui),
+                (true, None) => self.pose_source = Some(Id::Device(0, 0)),
+                (false, _) => self.pose_source = None,
+            }
+        });
         self.device_descriptor.ui(ui);
     }
 }
@@ -75,6 +109,9 @@ pub trait DeviceDescriptor: Send + Sync + Any {
         None
     }
 
+    /// Moves the device, for descriptors that have a transform
+    fn set_transform(&mut self, _transform: Transform) {}
+
     /// if the descriptor has a spatial position, return it
     fn position(&self) -> Option<Point> {
         None
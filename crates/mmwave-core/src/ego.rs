@@ -0,0 +1,281 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{
+    pointcloud::PointCloud,
+    telemetry::Pose,
+    transform::{cross, norm, Quaternion, Transform},
+};
+
+/// How long poses are kept for, clouds older than this cannot be placed
+const HISTORY: Duration = Duration::seconds(5);
+/// How far past the known poses a pose is extrapolated from the velocity
+const MAX_EXTRAPOLATION: Duration = Duration::milliseconds(200);
+
+/// The recent poses of a moving platform, from which its pose at any moment
+/// in between them can be interpolated
+#[derive(Debug, Clone, Default)]
+pub struct PoseBuffer {
+    poses: VecDeque<Pose>, // Oldest first
+}
+
+impl PoseBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.poses.is_empty()
+    }
+
+    /// Adds a pose, which may arrive out of order, and forgets the poses
+    /// that are too old to be needed
+    pub fn push(&mut self, pose: Pose) {
+        let index = self.poses.partition_point(|p| p.time <= pose.time);
+        self.poses.insert(index, pose);
+        let latest = self.poses.back().map(|p| p.time).unwrap_or_default();
+        while self
+            .poses
+            .front()
+            .is_some_and(|p| p.time < latest - HISTORY)
+        {
+            self.poses.pop_front();
+        }
+    }
+
+    /// The pose of the platform at time, with its velocities filled in from
+    /// the poses either side when they were not reported. None if time is
+    /// outside of the known poses by more than can be extrapolated.
+    pub fn at(&self, time: DateTime<Utc>) -> Option<Pose> {
+        let index = self.poses.partition_point(|p| p.time <= time);
+        if index > 0 && index < self.poses.len() {
+            return Some(interpolate(
+                &self.poses[index - 1],
+                &self.poses[index],
+                time,
+            ));
+        }
+
+        // Past either end, so move the nearest pose along with its motion
+        let (nearest, neighbour) = if index == 0 {
+            (self.poses.front()?, self.poses.get(1))
+        } else {
+            (self.poses.back()?, self.poses.iter().rev().nth(1))
+        };
+        if (time - nearest.time).abs() > MAX_EXTRAPOLATION {
+            return None;
+        }
+        let mut pose = nearest.clone();
+        if let Some(neighbour) = neighbour {
+            let (velocity, angular_velocity) = if neighbour.time < nearest.time {
+                motion(neighbour, nearest)
+            } else {
+                motion(nearest, neighbour)
+            };
+            pose.velocity = pose.velocity.or(Some(velocity));
+            pose.angular_velocity = pose.angular_velocity.or(Some(angular_velocity));
+        }
+        Some(extrapolate(&pose, time))
+    }
+}
+
+/// The pose between a and b (a first) at time
+fn interpolate(a: &Pose, b: &Pose, time: DateTime<Utc>) -> Pose {
+    let span = seconds(b.time - a.time);
+    let t = if span > 0.0 {
+        seconds(time - a.time) / span
+    } else {
+        0.0
+    };
+    let lerp = |x: [f32; 3], y: [f32; 3]| [0, 1, 2].map(|i| x[i] + (y[i] - x[i]) * t);
+    let (velocity, angular_velocity) = motion(a, b);
+    Pose {
+        time,
+        position: lerp(a.position, b.position),
+        orientation: a.orientation.slerp(b.orientation, t),
+        velocity: Some(match (a.velocity, b.velocity) {
+            (Some(x), Some(y)) => lerp(x, y),
+            _ => velocity,
+        }),
+        angular_velocity: Some(match (a.angular_velocity, b.angular_velocity) {
+            (Some(x), Some(y)) => lerp(x, y),
+            _ => angular_velocity,
+        }),
+    }
+}
+
+/// Moves a pose to time at constant velocity and turn rate
+fn extrapolate(pose: &Pose, time: DateTime<Utc>) -> Pose {
+    let dt = seconds(time - pose.time);
+    let velocity = pose.velocity.unwrap_or_default();
+    let angular_velocity = pose.angular_velocity.unwrap_or_default();
+    let rate = norm(angular_velocity);
+    let turn = if rate > 0.0 {
+        Quaternion::from_axis_angle(angular_velocity.map(|w| w / rate), rate * dt)
+    } else {
+        Quaternion::IDENTITY
+    };
+    Pose {
+        time,
+        position: [0, 1, 2].map(|i| pose.position[i] + velocity[i] * dt),
+        orientation: (turn * pose.orientation).normalized(),
+        velocity: Some(velocity),
+        angular_velocity: Some(angular_velocity),
+    }
+}
+
+/// Velocity and angular velocity (in the parent frame) of moving from a to b
+fn motion(a: &Pose, b: &Pose) -> ([f32; 3], [f32; 3]) {
+    let dt = seconds(b.time - a.time);
+    if dt <= 0.0 {
+        return ([0.0; 3], [0.0; 3]);
+    }
+    let velocity = [0, 1, 2].map(|i| (b.position[i] - a.position[i]) / dt);
+
+    // The rotation taking a to b, as an axis scaled by its angle
+    let mut turn = (b.orientation * a.orientation.conjugate()).normalized();
+    if turn.w < 0.0 {
+        turn = Quaternion {
+            w: -turn.w,
+            x: -turn.x,
+            y: -turn.y,
+            z: -turn.z,
+        };
+    }
+    let axis = [turn.x, turn.y, turn.z];
+    let sin = norm(axis);
+    if sin == 0.0 {
+        return (velocity, [0.0; 3]);
+    }
+    let angle = 2.0 * sin.atan2(turn.w);
+    (velocity, axis.map(|x| x / sin * angle / dt))
+}
+
+fn seconds(duration: Duration) -> f32 {
+    duration.num_microseconds().unwrap_or(i64::MAX) as f32 / 1e6
+}
+
+/// A sensor on a moving platform, e.g. a robot, whose pose in the parent
+/// frame is published by its odometry or localisation
+#[derive(Debug, Clone, Default)]
+pub struct MovingMount {
+    pub parent: Transform, // Parent frame to world
+    pub mount: Transform,  // Sensor to platform
+    pub poses: PoseBuffer,
+}
+
+impl MovingMount {
+    pub fn new(parent: Transform, mount: Transform) -> Self {
+        Self {
+            parent,
+            mount,
+            poses: PoseBuffer::new(),
+        }
+    }
+
+    /// Moves a sensor frame cloud into the world frame with the platform
+    /// where it was when the cloud was captured, and removes the platform's
+    /// own motion from the radial velocities so static returns read zero.
+    /// False, leaving the cloud as is, if the pose at that time is unknown.
+    pub fn apply(&self, pointcloud: &mut PointCloud) -> bool {
+        let Some(pose) = self.poses.at(pointcloud.time) else {
+            return false;
+        };
+        let platform = Transform::from_quaternion(pose.position, pose.orientation);
+        self.parent
+            .compose(&platform)
+            .compose(&self.mount)
+            .apply_to_cloud(pointcloud);
+
+        // The sensor also moves when the platform turns about a point away
+        // from it
+        let lever = pose.orientation.rotate(self.mount.translation);
+        let turning = cross(pose.angular_velocity.unwrap_or_default(), lever);
+        let velocity = pose.velocity.unwrap_or_default();
+        let sensor_velocity = self
+            .parent
+            .quaternion()
+            .rotate([0, 1, 2].map(|i| velocity[i] + turning[i]));
+
+        // A radar measures the speed of the point relative to itself, positive
+        // moving away, so its own velocity towards the point is added back
+        for i in 0..pointcloud.points.len() {
+            if let Some(direction) = pointcloud.radial_direction(i) {
+                let towards: f32 = direction
+                    .iter()
+                    .zip(sensor_velocity)
+                    .map(|(d, v)| d * v)
+                    .sum();
+                pointcloud.points[i].v += towards;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use chrono::{DateTime, Duration, Utc};
+
+    use super::{MovingMount, PoseBuffer};
+    use crate::{
+        message::Id,
+        pointcloud::PointCloud,
+        telemetry::Pose,
+        transform::{Quaternion, Transform},
+    };
+
+    #[test]
+    pub fn test_moving_mount() {
+        let start = DateTime::<Utc>::default();
+        let at = |ms: i64| start + Duration::milliseconds(ms);
+        let pose = |ms: i64, y: f32, yaw: f32| Pose {
+            time: at(ms),
+            position: [0.0, y, 0.0],
+            orientation: Quaternion::from_euler([yaw, 0.0, 0.0]),
+            velocity: None,
+            angular_velocity: None,
+        };
+
+        // Driving forwards at 1 m/s, turning at the end
+        let mut poses = PoseBuffer::new();
+        poses.push(pose(1000, 1.0, 0.0));
+        poses.push(pose(0, 0.0, 0.0));
+        poses.push(pose(2000, 2.0, FRAC_PI_2));
+        let halfway = poses.at(at(500)).unwrap();
+        assert!((halfway.position[1] - 0.5).abs() < 1e-6);
+        assert!((halfway.velocity.unwrap()[1] - 1.0).abs() < 1e-6);
+        let turning = poses.at(at(1500)).unwrap();
+        assert!((turning.orientation.to_euler()[0] - FRAC_PI_2 / 2.0).abs() < 1e-4);
+        assert!((turning.angular_velocity.unwrap()[2] - FRAC_PI_2).abs() < 1e-4);
+        assert!(poses.at(at(2100)).is_some());
+        assert!(poses.at(at(3000)).is_none());
+
+        // A static wall ahead closes at the platform's speed, until compensated
+        let mut mount = MovingMount::new(
+            Transform {
+                translation: [10.0, 0.0, 0.0],
+                orientation: [0.0; 3],
+            },
+            Transform::default(),
+        );
+        mount.poses = poses;
+        let id = Id::Device(0, 0);
+        let mut pointcloud = PointCloud {
+            time: at(500),
+            ..PointCloud::from(vec![[0.0, 2.0, 0.0, -1.0].into()])
+        }
+        .with_source(id)
+        .with_origin(Some(id));
+        assert!(mount.apply(&mut pointcloud));
+        let point = pointcloud.points[0];
+        assert!((point.x - 10.0).abs() < 1e-6 && (point.y - 2.5).abs() < 1e-6);
+        assert!(point.v.abs() < 1e-6);
+
+        pointcloud.time = at(5000);
+        assert!(!mount.apply(&mut pointcloud));
+    }
+}
@@ -0,0 +1,160 @@
+//! Named coordinate frames, such as rigs placed in a room placed in a site.
+//! Every frame is positioned relative to a parent frame, and the chain of
+//! parents ends at the implicit `world` frame. Devices reference the frame
+//! they are mounted on, and their own transform is relative to it.
+
+use egui::Ui;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::transform::Transform;
+
+pub const WORLD: &str = "world";
+
+pub fn world() -> String {
+    WORLD.to_owned()
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum FrameError {
+    #[error("Unknown frame {0:?}")]
+    UnknownFrame(String),
+    #[error("Frame {0:?} is its own ancestor")]
+    Cycle(String),
+    #[error("Frame {0:?} is defined more than once")]
+    Duplicate(String),
+}
+
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct Frame {
+    pub name: String,
+    #[serde(default = "world")]
+    pub parent: String,
+    pub transform: Transform, // From this frame into the parent frame
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            parent: world(),
+            transform: Transform::default(),
+        }
+    }
+}
+
+impl Frame {
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Name");
+            ui.text_edit_singleline(&mut self.name);
+        });
+        ui.horizontal(|ui| {
+            ui.label("Parent");
+            ui.text_edit_singleline(&mut self.parent);
+        });
+        self.transform.ui(ui);
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(transparent)]
+pub struct FrameTree {
+    pub frames: Vec<Frame>,
+}
+
+impl FrameTree {
+    pub fn get(&self, name: &str) -> Option<&Frame> {
+        self.frames.iter().find(|frame| frame.name == name)
+    }
+
+    /// The transform from the named frame into the world frame
+    pub fn to_world(&self, name: &str) -> Result<Transform, FrameError> {
+        let mut transform = Transform::default();
+        let mut current = name;
+        // A chain longer than the number of frames must revisit one of them
+        for _ in 0..=self.frames.len() {
+            if current == WORLD {
+                return Ok(transform);
+            }
+            let frame = self
+                .get(current)
+                .ok_or_else(|| FrameError::UnknownFrame(current.to_owned()))?;
+            transform = frame.transform.compose(&transform);
+            current = &frame.parent;
+        }
+        Err(FrameError::Cycle(name.to_owned()))
+    }
+
+    /// Checks every frame is uniquely named and resolves to the world frame
+    pub fn validate(&self) -> Result<(), FrameError> {
+        for (i, frame) in self.frames.iter().enumerate() {
+            if frame.name == WORLD || self.frames[..i].iter().any(|f| f.name == frame.name) {
+                return Err(FrameError::Duplicate(frame.name.clone()));
+            }
+            self.to_world(&frame.name)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::{Frame, FrameError, FrameTree, WORLD};
+    use crate::transform::Transform;
+
+    fn frame(name: &str, parent: &str, translation: [f32; 3], yaw: f32) -> Frame {
+        Frame {
+            name: name.to_owned(),
+            parent: parent.to_owned(),
+            transform: Transform {
+                translation,
+                orientation: [yaw, 0.0, 0.0],
+            },
+        }
+    }
+
+    #[test]
+    pub fn test_resolve_chain() {
+        let tree = FrameTree {
+            frames: vec![
+                frame("rig", "room", [1.0, 0.0, 0.0], 0.0),
+                frame("room", "site", [0.0, 0.0, 0.0], PI / 2.),
+                frame("site", WORLD, [10.0, 0.0, 0.0], 0.0),
+            ],
+        };
+        assert!(tree.validate().is_ok());
+        let point = tree.to_world("rig").unwrap().apply([0.0, 0.0, 1.0]);
+        let expected = [10.0, 1.0, 1.0];
+        assert!(point
+            .iter()
+            .zip(expected)
+            .all(|(a, b)| (a - b).abs() < 1e-5));
+        assert_eq!(tree.to_world(WORLD), Ok(Transform::default()));
+    }
+
+    #[test]
+    pub fn test_invalid_trees() {
+        let tree = FrameTree {
+            frames: vec![
+                frame("a", "b", [0.0; 3], 0.0),
+                frame("b", "a", [0.0; 3], 0.0),
+            ],
+        };
+        assert_eq!(tree.to_world("a"), Err(FrameError::Cycle("a".into())));
+        assert_eq!(
+            tree.to_world("c"),
+            Err(FrameError::UnknownFrame("c".into()))
+        );
+
+        let tree = FrameTree {
+            frames: vec![
+                frame("a", WORLD, [0.0; 3], 0.0),
+                frame("a", WORLD, [0.0; 3], 0.0),
+            ],
+        };
+        assert_eq!(tree.validate(), Err(FrameError::Duplicate("a".into())));
+    }
+}
@@ -0,0 +1,239 @@
+use std::f32::consts::PI;
+
+use egui::{DragValue, Ui};
+use serde::{Deserialize, Serialize};
+
+use crate::transform::{cross, dot, norm, sub, Quaternion, Transform};
+
+/// A plane in the world frame, e.g. the floor of the site, holding the points
+/// whose height above it is zero
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Plane {
+    pub normal: [f32; 3], // Unit length, pointing up
+    pub offset: f32,
+}
+
+impl Default for Plane {
+    /// The plane z = 0
+    fn default() -> Self {
+        Self {
+            normal: [0.0, 0.0, 1.0],
+            offset: 0.0,
+        }
+    }
+}
+
+impl Plane {
+    /// The plane through three points, None if they are (nearly) collinear
+    pub fn through([a, b, c]: [[f32; 3]; 3]) -> Option<Self> {
+        let normal = cross(sub(b, a), sub(c, a));
+        let length = norm(normal);
+        if length < 1e-6 {
+            return None;
+        }
+        let sign = if normal[2] < 0.0 { -1.0 } else { 1.0 };
+        let normal = normal.map(|x| sign * x / length);
+        Some(Self {
+            normal,
+            offset: -dot(normal, a),
+        })
+    }
+
+    /// Signed distance of point above the plane
+    pub fn height(&self, point: [f32; 3]) -> f32 {
+        dot(self.normal, point) + self.offset
+    }
+
+    /// Angle of the plane from the horizontal (radians)
+    pub fn tilt(&self) -> f32 {
+        self.normal[2].clamp(-1.0, 1.0).acos()
+    }
+
+    /// The point of the plane directly below (or above) point
+    pub fn project(&self, point: [f32; 3]) -> [f32; 3] {
+        let height = self.height(point);
+        [0, 1, 2].map(|i| point[i] - self.normal[i] * height)
+    }
+
+    /// The transform that lays this plane onto z = 0 with the least rotation,
+    /// turning about the point of the plane below pivot so it only moves up
+    /// or down
+    pub fn leveling(&self, pivot: [f32; 3]) -> Transform {
+        let [x, y, z] = self.normal;
+        // Turning about the horizontal axis normal x up takes the normal to up
+        let axis = [y, -x, 0.0];
+        let sin = norm(axis);
+        let rotation = if sin < 1e-6 {
+            Quaternion::IDENTITY
+        } else {
+            Quaternion::from_axis_angle(axis.map(|a| a / sin), sin.atan2(z))
+        };
+        let foot = self.project(pivot);
+        let turned = rotation.rotate(foot);
+        Transform::from_quaternion(
+            [foot[0] - turned[0], foot[1] - turned[1], -turned[2]],
+            rotation,
+        )
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Normal");
+            for i in 0..3 {
+                ui.add(DragValue::new(&mut self.normal[i]).speed(0.01));
+            }
+            ui.label("Offset");
+            ui.add(DragValue::new(&mut self.offset).speed(0.01));
+        });
+        let length = norm(self.normal);
+        if length > 0.0 {
+            self.normal = self.normal.map(|x| x / length);
+        } else {
+            self.normal = [0.0, 0.0, 1.0];
+        }
+    }
+}
+
+/// How a floor is searched for with RANSAC
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct RansacParams {
+    pub iterations: usize, // Planes tried through random triples of points
+    pub threshold: f32,    // Furthest a point can be from the plane to lie on it (m)
+    pub max_tilt: f32,     // Steepest a plane can be and still be a floor (radians)
+}
+
+impl Default for RansacParams {
+    fn default() -> Self {
+        Self {
+            iterations: 500,
+            threshold: 0.05,
+            max_tilt: 20.0 * PI / 180.0,
+        }
+    }
+}
+
+/// Finds the (nearly) horizontal plane most points lie on, refined by least
+/// squares over those points. Returns it with the indices of its points, or
+/// None if no floor could be found.
+pub fn fit_floor(points: &[[f32; 3]], params: &RansacParams) -> Option<(Plane, Vec<usize>)> {
+    if points.len() < 3 {
+        return None;
+    }
+    let inliers = |plane: &Plane| -> Vec<usize> {
+        (0..points.len())
+            .filter(|&i| plane.height(points[i]).abs() <= params.threshold)
+            .collect()
+    };
+
+    // Seeded so that a recording always gives the same floor
+    let mut random = XorShift(0x9e37_79b9_7f4a_7c15);
+    let mut best: Option<(Plane, usize)> = None;
+    for _ in 0..params.iterations {
+        let sample = [(); 3].map(|_| points[random.below(points.len())]);
+        let Some(plane) = Plane::through(sample) else {
+            continue;
+        };
+        if plane.tilt() > params.max_tilt {
+            continue;
+        }
+        let count = points
+            .iter()
+            .filter(|&&p| plane.height(p).abs() <= params.threshold)
+            .count();
+        if best.is_none_or(|(_, most)| count > most) {
+            best = Some((plane, count));
+        }
+    }
+
+    let (plane, _) = best?;
+    let plane = least_squares(points, &inliers(&plane))
+        .filter(|refined| refined.tilt() <= params.max_tilt)
+        .unwrap_or(plane);
+    let found = inliers(&plane);
+    Some((plane, found))
+}
+
+/// The plane z = ax + by + c closest to the points at indices
+fn least_squares(points: &[[f32; 3]], indices: &[usize]) -> Option<Plane> {
+    if indices.len() < 3 {
+        return None;
+    }
+    let n = indices.len() as f64;
+    let mut mean = [0.0f64; 3];
+    for &i in indices {
+        for (m, x) in mean.iter_mut().zip(points[i]) {
+            *m += x as f64 / n;
+        }
+    }
+    let (mut sxx, mut sxy, mut syy, mut sxz, mut syz) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for &i in indices {
+        let [x, y, z] = [0, 1, 2].map(|j| points[i][j] as f64 - mean[j]);
+        sxx += x * x;
+        sxy += x * y;
+        syy += y * y;
+        sxz += x * z;
+        syz += y * z;
+    }
+    let det = sxx * syy - sxy * sxy;
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let a = (sxz * syy - syz * sxy) / det;
+    let b = (syz * sxx - sxz * sxy) / det;
+    let c = mean[2] - a * mean[0] - b * mean[1];
+    let length = (a * a + b * b + 1.0).sqrt();
+    Some(Plane {
+        normal: [-a / length, -b / length, 1.0 / length].map(|x| x as f32),
+        offset: (-c / length) as f32,
+    })
+}
+
+struct XorShift(u64);
+
+impl XorShift {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fit_floor, RansacParams};
+
+    #[test]
+    pub fn test_fit_floor() {
+        // A floor sloping up along x from 0.2 m, under a wall and some clutter
+        let floor = |x: f32, y: f32| [x, y, 0.2 + 0.05 * x];
+        let mut points = Vec::new();
+        for i in 0..20 {
+            for j in 0..10 {
+                points.push(floor(i as f32 * 0.25 - 2.0, j as f32 * 0.4));
+            }
+        }
+        for i in 0..30 {
+            points.push([i as f32 * 0.1 - 1.5, 4.0, i as f32 * 0.07]);
+        }
+        points.extend([[0.5, 1.0, 1.2], [-1.0, 2.0, 0.8], [1.5, 3.0, 1.7]]);
+
+        let (plane, inliers) = fit_floor(&points, &RansacParams::default()).unwrap();
+        assert!(inliers.len() >= 200 && inliers.len() < 205);
+        assert!((plane.tilt() - 0.05f32.atan()).abs() < 1e-3);
+        // Heights are measured square to the floor
+        let height = 1.0 / (1.0f32 + 0.05 * 0.05).sqrt();
+        assert!((plane.height([0.0, 0.0, 1.2]) - height).abs() < 1e-3);
+
+        // Leveling lays it onto z = 0, keeping the pivot at its height above it
+        let pivot = [1.0, 1.0, 2.0];
+        let leveling = plane.leveling(pivot);
+        for point in [floor(1.0, 1.0), floor(-2.0, 3.0), floor(4.0, -1.0)] {
+            assert!(leveling.apply(point)[2].abs() < 1e-3);
+        }
+        assert!((leveling.apply(pivot)[2] - plane.height(pivot)).abs() < 1e-4);
+
+        assert!(fit_floor(&points[200..230], &RansacParams::default()).is_none());
+    }
+}
@@ -1,14 +1,30 @@
-// pub mod accumulator;
+pub mod accumulator;
 // pub mod manager;
 pub mod devices;
 pub mod message;
 // pub mod point;
 pub mod pointcloud;
+pub mod posture;
 // pub mod pointcloud_stream;
 // pub mod relay;
 pub mod address;
+pub mod background;
+pub mod classify;
+pub mod cluster;
 pub mod config;
+pub mod ego;
+pub mod frames;
+pub mod ground;
 pub mod logging;
 pub mod nats;
+pub mod occupancy;
 pub mod point;
+pub mod pubsub;
+pub mod schema;
+pub mod spatial;
+pub mod subject;
+pub mod telemetry;
+pub mod tracking;
 pub mod transform;
+pub mod wire;
+pub mod zones;
@@ -9,12 +9,31 @@ use std::{
 };
 use thiserror::Error;
 
-use crate::pointcloud::PointCloud;
+use crate::{
+    pointcloud::PointCloud,
+    subject,
+    telemetry::{
+        Clusters, DeviceStatus, Event, Heartbeat, OccupancyGrid, Pose, Predictions, Spectrum,
+        TrackedObjects, VitalSigns,
+    },
+};
 
 #[derive(Serialize, PartialOrd, Ord, Deserialize, Debug, Hash, Clone, Eq, PartialEq)]
 pub enum Tag {
     Pointcloud,
     FromId(Id),
+    Status,
+    Heartbeat,
+    Event,
+    Tracks,
+    Spectrum,
+    Fused, // Pointclouds fused from many devices
+    Clusters,
+    Occupancy,
+    Vitals,
+    Predictions,
+    Pose,
+    Filtered, // Pointclouds of filter devices
 }
 
 #[derive(Hash, Eq, PartialOrd, Ord, PartialEq, Serialize, Deserialize, Debug, Clone, Copy)]
@@ -27,6 +46,16 @@ pub enum Id {
 pub enum MessageContent {
     PointCloud(PointCloud),
     Empty,
+    DeviceStatus(DeviceStatus),
+    Heartbeat(Heartbeat),
+    Event(Event),
+    TrackedObjects(TrackedObjects),
+    Spectrum(Spectrum),
+    Clusters(Clusters),
+    OccupancyGrid(OccupancyGrid),
+    VitalSigns(VitalSigns),
+    Predictions(Predictions),
+    Pose(Pose),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -51,12 +80,70 @@ pub trait TagsToSubject {
 }
 
 impl TagsToSubject for Vec<Tag> {
-    fn to_subject(mut self) -> String {
-        self.sort();
-        self.iter()
-            .map(|tag| tag.to_string())
-            .collect::<Vec<String>>()
-            .join(".")
+    fn to_subject(self) -> String {
+        subject::subject(&self)
+    }
+}
+
+impl Tag {
+    /// The subject token for tags describing the kind of content, None for
+    /// tags identifying where a message came from.
+    pub fn kind(&self) -> Option<&'static str> {
+        match self {
+            Tag::Pointcloud => Some("pointcloud"),
+            Tag::FromId(_) => None,
+            Tag::Status => Some("status"),
+            Tag::Heartbeat => Some("heartbeat"),
+            Tag::Event => Some("event"),
+            Tag::Tracks => Some("tracks"),
+            Tag::Spectrum => Some("spectrum"),
+            Tag::Fused => Some("fused"),
+            Tag::Clusters => Some("clusters"),
+            Tag::Occupancy => Some("occupancy"),
+            Tag::Vitals => Some("vitals"),
+            Tag::Predictions => Some("predictions"),
+            Tag::Pose => Some("pose"),
+            Tag::Filtered => Some("filtered"),
+        }
+    }
+
+    pub fn from_kind(kind: &str) -> Option<Tag> {
+        match kind {
+            "pointcloud" => Some(Tag::Pointcloud),
+            "status" => Some(Tag::Status),
+            "heartbeat" => Some(Tag::Heartbeat),
+            "event" => Some(Tag::Event),
+            "tracks" => Some(Tag::Tracks),
+            "spectrum" => Some(Tag::Spectrum),
+            "fused" => Some(Tag::Fused),
+            "clusters" => Some(Tag::Clusters),
+            "occupancy" => Some(Tag::Occupancy),
+            "vitals" => Some(Tag::Vitals),
+            "predictions" => Some(Tag::Predictions),
+            "pose" => Some(Tag::Pose),
+            "filtered" => Some(Tag::Filtered),
+            _ => None,
+        }
+    }
+}
+
+impl MessageContent {
+    /// The tag describing this kind of content, None for empty messages
+    pub fn tag(&self) -> Option<Tag> {
+        match self {
+            MessageContent::PointCloud(_) => Some(Tag::Pointcloud),
+            MessageContent::Empty => None,
+            MessageContent::DeviceStatus(_) => Some(Tag::Status),
+            MessageContent::Heartbeat(_) => Some(Tag::Heartbeat),
+            MessageContent::Event(_) => Some(Tag::Event),
+            MessageContent::TrackedObjects(_) => Some(Tag::Tracks),
+            MessageContent::Spectrum(_) => Some(Tag::Spectrum),
+            MessageContent::Clusters(_) => Some(Tag::Clusters),
+            MessageContent::OccupancyGrid(_) => Some(Tag::Occupancy),
+            MessageContent::VitalSigns(_) => Some(Tag::Vitals),
+            MessageContent::Predictions(_) => Some(Tag::Predictions),
+            MessageContent::Pose(_) => Some(Tag::Pose),
+        }
     }
 }
 
@@ -65,6 +152,18 @@ impl Display for Tag {
         match self {
             Tag::Pointcloud => write!(f, "Pointcloud"),
             Tag::FromId(id) => write!(f, "FromId({})", id),
+            Tag::Status => write!(f, "Status"),
+            Tag::Heartbeat => write!(f, "Heartbeat"),
+            Tag::Event => write!(f, "Event"),
+            Tag::Tracks => write!(f, "Tracks"),
+            Tag::Spectrum => write!(f, "Spectrum"),
+            Tag::Fused => write!(f, "Fused"),
+            Tag::Clusters => write!(f, "Clusters"),
+            Tag::Occupancy => write!(f, "Occupancy"),
+            Tag::Vitals => write!(f, "Vitals"),
+            Tag::Predictions => write!(f, "Predictions"),
+            Tag::Pose => write!(f, "Pose"),
+            Tag::Filtered => write!(f, "Filtered"),
         }
     }
 }
@@ -109,6 +208,16 @@ impl Display for MessageContent {
         match self {
             MessageContent::PointCloud(_pointcloud) => write!(f, "pointcloud"),
             MessageContent::Empty => write!(f, "empty"),
+            MessageContent::DeviceStatus(_) => write!(f, "status"),
+            MessageContent::Heartbeat(_) => write!(f, "heartbeat"),
+            MessageContent::Event(_) => write!(f, "event"),
+            MessageContent::TrackedObjects(_) => write!(f, "tracks"),
+            MessageContent::Spectrum(_) => write!(f, "spectrum"),
+            MessageContent::Clusters(_) => write!(f, "clusters"),
+            MessageContent::OccupancyGrid(_) => write!(f, "occupancy"),
+            MessageContent::VitalSigns(_) => write!(f, "vitals"),
+            MessageContent::Predictions(_) => write!(f, "predictions"),
+            MessageContent::Pose(_) => write!(f, "pose"),
         }
     }
 }
@@ -134,14 +243,17 @@ pub enum IdParseError {
 impl FromStr for Id {
     type Err = ParseIntError;
 
+    /// Parses the display form, `<machine>` or `<machine>:<device>`
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let id = s.parse::<usize>()?;
-        Ok(Id::Machine(id))
+        match s.split_once(':') {
+            Some((m, d)) => Ok(Id::Device(m.parse()?, d.parse()?)),
+            None => Ok(Id::Machine(s.parse()?)),
+        }
     }
 }
 
 impl ToSubject for Tag {
     fn to_subject(&self) -> async_nats::Subject {
-        self.to_string().into()
+        subject::subject(std::slice::from_ref(self)).into()
     }
 }
@@ -1,7 +1,7 @@
 use std::error::Error;
 
 use async_nats::jetstream::{self, kv::Store, Context};
-use tracing::{info, instrument};
+use tracing::{info, instrument, warn};
 
 use crate::config::Configuration;
 
@@ -28,3 +28,17 @@ pub async fn get_store(jetstream: Context) -> Result<Store, Box<dyn Error>> {
         }
     })
 }
+
+/// The configuration currently in the store, None if there is none or it cannot be parsed
+pub async fn get_config(store: &Store) -> Result<Option<Configuration>, Box<dyn Error>> {
+    let Some(config) = store.get("config").await? else {
+        return Ok(None);
+    };
+    match serde_json::from_slice(&config) {
+        Ok(config) => Ok(Some(config)),
+        Err(e) => {
+            warn!(error=%e, "Failed to parse config");
+            Ok(None)
+        }
+    }
+}
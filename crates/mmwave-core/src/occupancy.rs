@@ -0,0 +1,136 @@
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{
+    pointcloud::PointCloud,
+    telemetry::{OccupancyGrid, TrackState, TrackedObjects},
+};
+
+impl OccupancyGrid {
+    pub fn new(origin: [f32; 3], cell_size: [f32; 3], shape: [u32; 3]) -> Self {
+        let cells = shape.iter().map(|&n| n as usize).product();
+        OccupancyGrid {
+            time: DateTime::<Utc>::default(),
+            origin,
+            cell_size,
+            shape,
+            values: vec![0.0; cells],
+        }
+    }
+
+    /// Index into values of the cell containing a world frame position, if
+    /// it is inside the grid
+    pub fn index(&self, position: [f32; 3]) -> Option<usize> {
+        let mut index = 0;
+        for k in (0..3).rev() {
+            let cell = ((position[k] - self.origin[k]) / self.cell_size[k]).floor();
+            if self.cell_size[k] <= 0.0 || !(0.0..self.shape[k] as f32).contains(&cell) {
+                return None;
+            }
+            index = index * self.shape[k] as usize + cell as usize;
+        }
+        Some(index)
+    }
+
+    /// World frame position of the centre of the cell at an index
+    pub fn centre(&self, index: usize) -> [f32; 3] {
+        let [nx, ny, _] = self.shape.map(|n| n as usize);
+        let cell = [index % nx, (index / nx) % ny, index / (nx * ny)];
+        [0, 1, 2].map(|k| self.origin[k] + (cell[k] as f32 + 0.5) * self.cell_size[k])
+    }
+
+    /// Adds weight to the cell containing a position, returning whether it
+    /// was inside the grid
+    pub fn add(&mut self, position: [f32; 3], weight: f32) -> bool {
+        match self.index(position) {
+            Some(i) => {
+                self.values[i] += weight;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Adds every point of a world frame pointcloud with a weight of one
+    pub fn add_pointcloud(&mut self, pointcloud: &PointCloud) {
+        for point in pointcloud.points.iter() {
+            self.add([point.x, point.y, point.z], 1.0);
+        }
+        self.time = self.time.max(pointcloud.time);
+    }
+
+    /// Adds the position of every confirmed or coasting track with a weight
+    /// of one, so a grid updated at a steady rate measures dwell time
+    pub fn add_tracks(&mut self, time: DateTime<Utc>, tracks: &TrackedObjects) {
+        for object in tracks.objects.iter() {
+            if object.state != TrackState::Tentative {
+                self.add(object.position, 1.0);
+            }
+        }
+        self.time = self.time.max(time);
+    }
+
+    /// Exponentially decays every cell, halving them each half life. A half
+    /// life of zero or less disables decay.
+    pub fn decay(&mut self, elapsed: Duration, half_life: Duration) {
+        let half_life = half_life.num_microseconds().unwrap_or(i64::MAX);
+        if half_life <= 0 {
+            return;
+        }
+        let elapsed = elapsed.num_microseconds().unwrap_or(i64::MAX).max(0);
+        let factor = 0.5f64.powf(elapsed as f64 / half_life as f64) as f32;
+        for value in self.values.iter_mut() {
+            *value *= factor;
+        }
+    }
+
+    /// Sums the grid along z into a row major x by y grid, for top down views
+    pub fn project(&self) -> Vec<f32> {
+        let cells = self.shape[0] as usize * self.shape[1] as usize;
+        let mut projected = vec![0.0; cells];
+        if cells == 0 {
+            return projected;
+        }
+        for layer in self.values.chunks(cells) {
+            for (total, value) in projected.iter_mut().zip(layer) {
+                *total += value;
+            }
+        }
+        projected
+    }
+
+    pub fn max(&self) -> f32 {
+        self.values.iter().copied().fold(0.0, f32::max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use crate::{pointcloud::PointCloud, telemetry::OccupancyGrid};
+
+    #[test]
+    pub fn test_occupancy_grid() {
+        let mut grid = OccupancyGrid::new([-1.0, 0.0, 0.0], [0.5; 3], [4, 4, 2]);
+        assert_eq!(grid.values.len(), 32);
+        grid.add_pointcloud(&PointCloud::from(vec![
+            [-0.9, 0.1, 0.1, 0.0].into(),
+            [-0.8, 0.2, 0.7, 0.0].into(),
+            [0.9, 1.9, 0.9, 0.0].into(),
+            [1.1, 0.0, 0.0, 0.0].into(), // Outside the grid
+        ]));
+        assert_eq!(grid.values.iter().sum::<f32>(), 3.0);
+        assert_eq!(grid.index([0.9, 1.9, 0.9]), Some(31));
+        assert_eq!(grid.centre(31), [0.75, 1.75, 0.75]);
+
+        let projected = grid.project();
+        assert_eq!(projected.len(), 16);
+        assert_eq!(projected[0], 2.0);
+        assert_eq!(projected[15], 1.0);
+
+        grid.decay(Duration::seconds(20), Duration::seconds(10));
+        assert!((grid.max() - 0.25).abs() < 1e-6);
+        grid.decay(Duration::seconds(20), Duration::zero());
+        assert!((grid.max() - 0.25).abs() < 1e-6);
+    }
+}
@@ -1,21 +1,226 @@
-use crate::point::Point;
+use crate::{ground::Plane, message::Id, point::Point};
 use chrono::{DateTime, Utc};
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone)]
 pub struct PointCloud {
     pub time: DateTime<Utc>,
     pub points: Vec<Point>, // x, y, z, v
-    pub labels: Vec<String>
+    pub labels: Vec<String>,
+    pub attributes: PointAttributes,
+    pub origins: Vec<SensorOrigin>, // Where the sensors that observed the points were
+}
+
+/// The position of a sensor, in the same frame as the points, when it observed
+/// the points from `source` (or the points with no source, if None). Radial
+/// velocities are measured along the direction from here to the point.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct SensorOrigin {
+    pub source: Option<Id>,
+    pub position: [f32; 3],
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PointMetaData {}
 
+/// Optional per-point columns attached to a pointcloud.
+///
+/// Each column is either empty (the device does not provide that attribute)
+/// or holds exactly one entry per point, in the same order as `points`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct PointAttributes {
+    pub snr: Vec<f32>,       // Signal to noise ratio in dB
+    pub noise: Vec<f32>,     // Noise floor in dB
+    pub intensity: Vec<f32>, // Unitless return strength/confidence
+    pub track_id: Vec<u32>,  // Track or body the point belongs to
+    pub keypoint: Vec<u16>,  // Skeleton keypoint index within a body
+    pub source: Vec<Id>,     // Device that produced the point
+    pub height: Vec<f32>,    // Height above the site floor (m)
+}
+
+impl PointAttributes {
+    /// Whether every column is either empty or holds one entry per point
+    pub fn lines_up(&self, len: usize) -> bool {
+        fn fits<T>(column: &[T], len: usize) -> bool {
+            column.is_empty() || column.len() == len
+        }
+        fits(&self.snr, len)
+            && fits(&self.noise, len)
+            && fits(&self.intensity, len)
+            && fits(&self.track_id, len)
+            && fits(&self.keypoint, len)
+            && fits(&self.source, len)
+            && fits(&self.height, len)
+    }
+
+    /// Empties the columns that do not hold one entry per point, as they can
+    /// no longer be matched to the points
+    pub fn drop_misaligned(&mut self, len: usize) {
+        fn fit<T>(column: &mut Vec<T>, len: usize) {
+            if column.len() != len {
+                column.clear();
+            }
+        }
+        fit(&mut self.snr, len);
+        fit(&mut self.noise, len);
+        fit(&mut self.intensity, len);
+        fit(&mut self.track_id, len);
+        fit(&mut self.keypoint, len);
+        fit(&mut self.source, len);
+        fit(&mut self.height, len);
+    }
+
+    /// Appends the columns of other onto self, given the number of points
+    /// each side holds. A column only survives if both sides provide it,
+    /// otherwise it would no longer line up with the points.
+    fn extend(&mut self, len: usize, other: PointAttributes, other_len: usize) {
+        fn merge<T>(a: &mut Vec<T>, len: usize, mut b: Vec<T>, other_len: usize) {
+            match (
+                a.len() == len && len > 0,
+                b.len() == other_len && other_len > 0,
+            ) {
+                (true, true) => a.append(&mut b),
+                (false, true) if len == 0 => *a = b,
+                (true, false) if other_len == 0 => {}
+                _ => a.clear(),
+            }
+        }
+        merge(&mut self.snr, len, other.snr, other_len);
+        merge(&mut self.noise, len, other.noise, other_len);
+        merge(&mut self.intensity, len, other.intensity, other_len);
+        merge(&mut self.track_id, len, other.track_id, other_len);
+        merge(&mut self.keypoint, len, other.keypoint, other_len);
+        merge(&mut self.source, len, other.source, other_len);
+        merge(&mut self.height, len, other.height, other_len);
+    }
+
+    /// The entries at indices of every column that is present, given the
+    /// number of points. Columns that do not line up are treated as absent.
+    fn select(&self, len: usize, indices: &[usize]) -> PointAttributes {
+        fn pick<T: Clone>(column: &[T], len: usize, indices: &[usize]) -> Vec<T> {
+            if column.len() != len {
+                return Vec::new();
+            }
+            indices.iter().map(|&i| column[i].clone()).collect()
+        }
+        PointAttributes {
+            snr: pick(&self.snr, len, indices),
+            noise: pick(&self.noise, len, indices),
+            intensity: pick(&self.intensity, len, indices),
+            track_id: pick(&self.track_id, len, indices),
+            keypoint: pick(&self.keypoint, len, indices),
+            source: pick(&self.source, len, indices),
+            height: pick(&self.height, len, indices),
+        }
+    }
+}
+
 impl PointCloud {
     pub fn extend(&mut self, mut other: PointCloud) {
         // Extends this pointcloud with other, consuming it
+        let (len, other_len) = (self.points.len(), other.points.len());
+        if self.labels.len() == len && other.labels.len() == other_len {
+            self.labels.append(&mut other.labels);
+        } else {
+            self.labels.clear();
+        }
+        self.attributes.extend(len, other.attributes, other_len);
         self.points.append(&mut other.points);
+        for origin in other.origins {
+            if !self.origins.iter().any(|o| o.source == origin.source) {
+                self.origins.push(origin);
+            }
+        }
+    }
+
+    /// A cloud holding only the points at indices (in that order), along with
+    /// their labels and attributes
+    pub fn select(&self, indices: &[usize]) -> PointCloud {
+        let labels = if self.labels.len() == self.points.len() {
+            indices.iter().map(|&i| self.labels[i].clone()).collect()
+        } else {
+            Vec::new()
+        };
+        PointCloud {
+            time: self.time,
+            points: indices.iter().map(|&i| self.points[i]).collect(),
+            labels,
+            attributes: self.attributes.select(self.points.len(), indices),
+            origins: self.origins.clone(),
+        }
+    }
+
+    /// Tags every point in the cloud as originating from the given device
+    pub fn with_source(mut self, id: Id) -> Self {
+        self.attributes.source = vec![id; self.points.len()];
+        self
+    }
+
+    /// The device that produced the point at index, if known
+    pub fn source(&self, index: usize) -> Option<Id> {
+        self.attributes.source.get(index).copied()
+    }
+
+    /// Records the sensor observing the points from source sat at the origin
+    /// of the cloud's current frame. Call this before transforming the cloud
+    /// out of the sensor frame, and the origin will be transformed with it.
+    pub fn with_origin(mut self, source: Option<Id>) -> Self {
+        self.origins.retain(|o| o.source != source);
+        self.origins.push(SensorOrigin {
+            source,
+            position: [0.0; 3],
+        });
+        self
+    }
+
+    /// Where the sensor that observed the point at index was
+    pub fn origin(&self, index: usize) -> Option<[f32; 3]> {
+        let source = self.source(index);
+        self.origins
+            .iter()
+            .find(|o| o.source == source)
+            .map(|o| o.position)
+    }
+
+    /// Unit vector from the sensor to the point at index, the direction its
+    /// radial velocity was measured in
+    pub fn radial_direction(&self, index: usize) -> Option<[f32; 3]> {
+        let origin = self.origin(index)?;
+        let point = self.points.get(index)?;
+        let direction = [
+            point.x - origin[0],
+            point.y - origin[1],
+            point.z - origin[2],
+        ];
+        let norm = direction.iter().map(|d| d * d).sum::<f32>().sqrt();
+        if norm == 0.0 {
+            return None;
+        }
+        Some(direction.map(|d| d / norm))
+    }
+
+    /// Records the height of every point above the floor, call this once the
+    /// cloud is in the world frame
+    pub fn with_heights(mut self, floor: &Plane) -> Self {
+        self.attributes.height = self
+            .points
+            .iter()
+            .map(|&p| floor.height(p.into()))
+            .collect();
+        self
+    }
+
+    /// The measured radial velocity of the point at index as a vector
+    pub fn radial_velocity(&self, index: usize) -> Option<[f32; 3]> {
+        let v = self.points.get(index)?.v;
+        Some(self.radial_direction(index)?.map(|d| d * v))
+    }
+
+    /// The component of the point's radial velocity along a unit direction,
+    /// so velocities from different sensors can be compared along a common axis
+    pub fn velocity_along(&self, index: usize, direction: [f32; 3]) -> Option<f32> {
+        let velocity = self.radial_velocity(index)?;
+        Some(velocity.iter().zip(direction).map(|(a, b)| a * b).sum())
     }
 }
 
@@ -24,10 +229,11 @@ impl From<Vec<Point>> for PointCloud {
         Self {
             time: chrono::Utc::now(),
             points: value,
-            labels: Vec::new()
+            labels: Vec::new(),
+            attributes: PointAttributes::default(),
+            origins: Vec::new(),
         }
     }
-
 }
 
 impl From<(Vec<Point>, Vec<String>)> for PointCloud {
@@ -35,7 +241,9 @@ impl From<(Vec<Point>, Vec<String>)> for PointCloud {
         Self {
             time: chrono::Utc::now(),
             points,
-            labels
+            labels,
+            attributes: PointAttributes::default(),
+            origins: Vec::new(),
         }
     }
 }
@@ -45,7 +253,9 @@ impl Default for PointCloud {
         PointCloud {
             time: Utc::now(),
             points: Vec::new(),
-            labels: Vec::new()
+            labels: Vec::new(),
+            attributes: PointAttributes::default(),
+            origins: Vec::new(),
         }
     }
 }
@@ -57,7 +267,23 @@ struct PointCloudHelper {
     y: Vec<f32>,
     z: Vec<f32>,
     v: Vec<f32>,
-    l: Vec<String>
+    l: Vec<String>,
+    #[serde(default)]
+    snr: Vec<f32>,
+    #[serde(default)]
+    noise: Vec<f32>,
+    #[serde(default)]
+    intensity: Vec<f32>,
+    #[serde(default)]
+    track_id: Vec<u32>,
+    #[serde(default)]
+    keypoint: Vec<u16>,
+    #[serde(default)]
+    source: Vec<Id>,
+    #[serde(default)]
+    origins: Vec<SensorOrigin>,
+    #[serde(default)]
+    height: Vec<f32>,
 }
 
 impl From<PointCloud> for PointCloudHelper {
@@ -65,20 +291,37 @@ impl From<PointCloud> for PointCloudHelper {
         let (x, y, z, v): (Vec<f32>, Vec<f32>, Vec<f32>, Vec<f32>) =
             pc.points.into_iter().map(|p| (p.x, p.y, p.z, p.v)).unzip4(); // requires the itertools crate
         let l = pc.labels;
+        let PointAttributes {
+            snr,
+            noise,
+            intensity,
+            track_id,
+            keypoint,
+            source,
+            height,
+        } = pc.attributes;
         PointCloudHelper {
             time: pc.time,
             x,
             y,
             z,
             v,
-            l
+            l,
+            snr,
+            noise,
+            intensity,
+            track_id,
+            keypoint,
+            source,
+            origins: pc.origins,
+            height,
         }
     }
 }
 
 impl From<PointCloudHelper> for PointCloud {
     fn from(helper: PointCloudHelper) -> Self {
-        let points = helper
+        let points: Vec<Point> = helper
             .x
             .into_iter()
             .zip(helper.y.into_iter())
@@ -86,11 +329,26 @@ impl From<PointCloudHelper> for PointCloud {
             .zip(helper.v.into_iter())
             .map(|(((x, y), z), v)| Point { x, y, z, v })
             .collect();
-        let labels = helper.l;
+        let mut labels = helper.l;
+        if labels.len() != points.len() {
+            labels.clear();
+        }
+        let mut attributes = PointAttributes {
+            snr: helper.snr,
+            noise: helper.noise,
+            intensity: helper.intensity,
+            track_id: helper.track_id,
+            keypoint: helper.keypoint,
+            source: helper.source,
+            height: helper.height,
+        };
+        attributes.drop_misaligned(points.len());
         PointCloud {
             time: helper.time,
             points,
             labels,
+            attributes,
+            origins: helper.origins,
         }
     }
 }
@@ -111,6 +369,13 @@ impl<'de> serde::Deserialize<'de> for PointCloud {
         D: serde::Deserializer<'de>,
     {
         let helper = PointCloudHelper::deserialize(deserializer)?;
+        let len = helper.x.len();
+        if [&helper.y, &helper.z, &helper.v]
+            .iter()
+            .any(|column| column.len() != len)
+        {
+            return Err(serde::de::Error::custom("point columns differ in length"));
+        }
         Ok(helper.into())
     }
 }
@@ -138,3 +403,76 @@ where
         (x, y, z, v)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{PointCloud, PointCloudHelper};
+    use crate::{message::Id, transform::Transform};
+
+    #[test]
+    pub fn test_radial_velocity_in_world_frame() {
+        let id = Id::Device(1, 0);
+        // A target straight ahead of the sensor, closing at 2 m/s
+        let mut pointcloud = PointCloud::from(vec![[0.0, 4.0, 0.0, -2.0].into()])
+            .with_source(id)
+            .with_origin(Some(id));
+        // Sensor at (1, 1, 0) facing along -x in the world
+        let transform = Transform {
+            translation: [1.0, 1.0, 0.0],
+            orientation: [std::f32::consts::FRAC_PI_2, 0.0, 0.0],
+        };
+        transform.apply_to_cloud(&mut pointcloud);
+
+        assert_eq!(pointcloud.origins[0].position, [1.0, 1.0, 0.0]);
+        let direction = pointcloud.radial_direction(0).unwrap();
+        assert!((direction[0] + 1.0).abs() < 1e-5 && direction[1].abs() < 1e-5);
+        let velocity = pointcloud.radial_velocity(0).unwrap();
+        assert!((velocity[0] - 2.0).abs() < 1e-5);
+        assert!((pointcloud.velocity_along(0, [1.0, 0.0, 0.0]).unwrap() - 2.0).abs() < 1e-5);
+        assert!(pointcloud.velocity_along(0, [0.0, 1.0, 0.0]).unwrap().abs() < 1e-5);
+    }
+
+    #[test]
+    pub fn test_extend_keeps_origins() {
+        let (a, b) = (Id::Device(1, 0), Id::Device(2, 0));
+        let mut pointcloud = PointCloud::from(vec![[1.0, 0.0, 0.0, 0.0].into()])
+            .with_source(a)
+            .with_origin(Some(a));
+        let mut other = PointCloud::from(vec![[0.0, 1.0, 0.0, 0.0].into()])
+            .with_source(b)
+            .with_origin(Some(b));
+        other.origins[0].position = [0.0, -1.0, 0.0];
+        pointcloud.extend(other);
+
+        assert_eq!(pointcloud.origin(0), Some([0.0, 0.0, 0.0]));
+        assert_eq!(pointcloud.origin(1), Some([0.0, -1.0, 0.0]));
+        assert_eq!(pointcloud.radial_direction(1), Some([0.0, 1.0, 0.0]));
+
+        let selected = pointcloud.select(&[1]);
+        assert_eq!(selected.points.len(), 1);
+        assert_eq!(selected.attributes.source, vec![b]);
+        assert_eq!(selected.origin(0), Some([0.0, -1.0, 0.0]));
+    }
+
+    #[test]
+    pub fn test_misaligned_columns() {
+        let mut helper = PointCloudHelper::from(PointCloud::from(vec![[0.0; 4].into(); 3]));
+        helper.l = vec!["a".to_owned(); 2];
+        helper.snr = vec![1.0; 2];
+        helper.noise = vec![2.0; 3];
+        let bytes = bincode::serialize(&helper).unwrap();
+        let mut pointcloud: PointCloud = bincode::deserialize(&bytes).unwrap();
+        assert!(pointcloud.labels.is_empty() && pointcloud.attributes.snr.is_empty());
+        assert_eq!(pointcloud.attributes.noise, vec![2.0; 3]);
+
+        // Columns set by hand are left out of selections rather than indexed
+        pointcloud.attributes.intensity = vec![0.5];
+        let selected = pointcloud.select(&[2, 1]);
+        assert!(selected.attributes.intensity.is_empty());
+        assert_eq!(selected.attributes.noise, vec![2.0; 2]);
+
+        helper.y.pop();
+        let bytes = bincode::serialize(&helper).unwrap();
+        assert!(bincode::deserialize::<PointCloud>(&bytes).is_err());
+    }
+}
@@ -0,0 +1,431 @@
+//! A rules based baseline for posture classification and fall detection.
+//! Postures come from the height of a track above the floor or, when a Zed
+//! skeleton is available, from the angles of its torso and thighs. A fall is
+//! a quick drop in height that ends lying down.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ground::Plane,
+    message::Id,
+    pointcloud::PointCloud,
+    telemetry::{Event, EventKind, Posture, Severity},
+};
+
+/// Thresholds for a deployment. Heights are in metres above the site floor,
+/// angles are in degrees from vertical.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct PostureParams {
+    pub standing_height: f32, // Lowest track height above the floor when standing
+    pub lying_height: f32,    // Highest track height above the floor when lying
+    pub lying_angle: f32,     // Smallest torso angle when lying
+    pub sitting_angle: f32,   // Smallest thigh angle when sitting
+    pub fall_drop: f32,       // Smallest drop in height of a fall
+    pub fall_speed: f32,      // Smallest downward speed of a fall in m/s
+    pub fall_window_s: f32,   // Longest time a fall takes
+}
+
+impl Default for PostureParams {
+    fn default() -> Self {
+        Self {
+            standing_height: 0.8,
+            lying_height: 0.4,
+            lying_angle: 60.0,
+            sitting_angle: 45.0,
+            fall_drop: 0.5,
+            fall_speed: 1.0,
+            fall_window_s: 1.5,
+        }
+    }
+}
+
+/// Confidence in a rule that passed (or failed) by a margin, from 0.5 at the
+/// threshold up to 1 at a margin of scale or more
+fn margin_confidence(margin: f32, scale: f32) -> f32 {
+    0.5 + 0.5 * (margin.abs() / scale).min(1.0)
+}
+
+/// Classifies a track by its height above the floor
+pub fn classify_height(height: f32, params: &PostureParams) -> (Posture, f32) {
+    let scale = (params.standing_height - params.lying_height).max(0.1) / 2.0;
+    if height >= params.standing_height {
+        (
+            Posture::Standing,
+            margin_confidence(height - params.standing_height, scale),
+        )
+    } else if height <= params.lying_height {
+        (
+            Posture::Lying,
+            margin_confidence(params.lying_height - height, scale),
+        )
+    } else {
+        let margin = (height - params.lying_height).min(params.standing_height - height);
+        (Posture::Sitting, margin_confidence(margin, scale))
+    }
+}
+
+/// Keypoint indices of the Zed's BODY_18 format
+mod body_18 {
+    pub const NOSE: usize = 0;
+    pub const NECK: usize = 1;
+    pub const RIGHT_HIP: usize = 8;
+    pub const RIGHT_KNEE: usize = 9;
+    pub const LEFT_HIP: usize = 11;
+    pub const LEFT_KNEE: usize = 12;
+}
+
+/// The keypoints of one body from a Zed, None where it could not place them
+#[derive(Debug, Clone, PartialEq)]
+pub struct Skeleton {
+    pub body: u32,
+    pub keypoints: Vec<Option<[f32; 3]>>,
+}
+
+impl Skeleton {
+    fn keypoint(&self, index: usize) -> Option<[f32; 3]> {
+        self.keypoints.get(index).copied().flatten()
+    }
+
+    fn mid_hip(&self) -> Option<[f32; 3]> {
+        let (right, left) = (
+            self.keypoint(body_18::RIGHT_HIP)?,
+            self.keypoint(body_18::LEFT_HIP)?,
+        );
+        Some([0, 1, 2].map(|k| (right[k] + left[k]) / 2.0))
+    }
+
+    /// World frame position of the body, its hips or failing that its neck
+    pub fn position(&self) -> Option<[f32; 3]> {
+        self.mid_hip().or(self.keypoint(body_18::NECK))
+    }
+
+    /// Height of the head above the floor
+    pub fn head_height(&self, floor: &Plane) -> Option<f32> {
+        let head = self
+            .keypoint(body_18::NOSE)
+            .or(self.keypoint(body_18::NECK))?;
+        Some(floor.height(head))
+    }
+
+    /// Classifies the body by the angle of its torso and thighs, or by its
+    /// head height above the floor when those keypoints are missing
+    pub fn classify(&self, params: &PostureParams, floor: &Plane) -> Option<(Posture, f32)> {
+        let (Some(neck), Some(hip)) = (self.keypoint(body_18::NECK), self.mid_hip()) else {
+            // A head is about twice as high as the centre of a track
+            return Some(classify_height(self.head_height(floor)? / 2.0, params));
+        };
+        let torso = angle_from_vertical(hip, neck);
+        if torso >= params.lying_angle {
+            return Some((
+                Posture::Lying,
+                margin_confidence(torso - params.lying_angle, 30.0),
+            ));
+        }
+
+        let thighs: Vec<f32> = [
+            (body_18::RIGHT_HIP, body_18::RIGHT_KNEE),
+            (body_18::LEFT_HIP, body_18::LEFT_KNEE),
+        ]
+        .into_iter()
+        .filter_map(|(hip, knee)| {
+            Some(angle_from_vertical(
+                self.keypoint(knee)?,
+                self.keypoint(hip)?,
+            ))
+        })
+        .collect();
+        if thighs.is_empty() {
+            return Some((Posture::Standing, 0.5));
+        }
+        let thigh = thighs.iter().sum::<f32>() / thighs.len() as f32;
+        let margin = thigh - params.sitting_angle;
+        let posture = if margin >= 0.0 {
+            Posture::Sitting
+        } else {
+            Posture::Standing
+        };
+        Some((posture, margin_confidence(margin, 30.0)))
+    }
+}
+
+/// Angle in degrees between vertical and the segment from a to b
+fn angle_from_vertical(a: [f32; 3], b: [f32; 3]) -> f32 {
+    let d = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+    let length = (d[0] * d[0] + d[1] * d[1] + d[2] * d[2])
+        .sqrt()
+        .max(f32::EPSILON);
+    (d[2].abs() / length).clamp(0.0, 1.0).acos().to_degrees()
+}
+
+/// The skeletons in a Zed cloud, in order of body. Clouds without keypoint
+/// attributes have none.
+pub fn skeletons(pointcloud: &PointCloud) -> Vec<Skeleton> {
+    let attributes = &pointcloud.attributes;
+    let len = pointcloud.points.len();
+    if attributes.keypoint.len() != len || attributes.track_id.len() != len {
+        return Vec::new();
+    }
+    let mut bodies: BTreeMap<u32, Vec<Option<[f32; 3]>>> = BTreeMap::new();
+    for (i, point) in pointcloud.points.iter().enumerate() {
+        let keypoints = bodies.entry(attributes.track_id[i]).or_default();
+        let k = attributes.keypoint[i] as usize;
+        if keypoints.len() <= k {
+            keypoints.resize(k + 1, None);
+        }
+        let position: [f32; 3] = (*point).into();
+        // The Zed reports keypoints it could not place as nan
+        keypoints[k] = position.iter().all(|x| x.is_finite()).then_some(position);
+    }
+    bodies
+        .into_iter()
+        .map(|(body, keypoints)| Skeleton { body, keypoints })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+struct History {
+    heights: VecDeque<(DateTime<Utc>, f32)>,
+    posture: Option<Posture>,
+    fallen: bool,
+    seen: DateTime<Utc>,
+}
+
+/// Follows the posture of the objects observed by one source over time,
+/// producing an event whenever the posture of one changes and an alert when
+/// one falls.
+#[derive(Debug, Clone)]
+pub struct PostureMonitor {
+    params: PostureParams,
+    source: Option<Id>, // Named in events, as object ids are only unique per source
+    objects: HashMap<u64, History>,
+}
+
+impl PostureMonitor {
+    pub fn new(params: PostureParams, source: Option<Id>) -> Self {
+        PostureMonitor {
+            params,
+            source,
+            objects: HashMap::new(),
+        }
+    }
+
+    /// Updates an object with its height above the floor and the posture it
+    /// was classified with at a time
+    pub fn update(
+        &mut self,
+        time: DateTime<Utc>,
+        object: u64,
+        position: [f32; 3],
+        height: f32,
+        (posture, confidence): (Posture, f32),
+    ) -> Vec<Event> {
+        let (params, source) = (self.params, self.source);
+        let history = self.objects.entry(object).or_insert_with(|| History {
+            heights: VecDeque::new(),
+            posture: None,
+            fallen: false,
+            seen: time,
+        });
+        if time < history.seen {
+            return Vec::new();
+        }
+        history.seen = time;
+        let window = chrono::Duration::milliseconds((params.fall_window_s * 1000.0) as i64);
+        history.heights.push_back((time, height));
+        while history
+            .heights
+            .front()
+            .is_some_and(|&(t, _)| time - t > window)
+        {
+            history.heights.pop_front();
+        }
+
+        let mut events = Vec::new();
+        if history.posture != Some(posture) {
+            history.posture = Some(posture);
+            events.push(Event {
+                kind: EventKind::PostureChanged {
+                    object,
+                    source,
+                    posture,
+                },
+                severity: Severity::Info,
+                description: format!("Object {} is {:?}", object, posture),
+                confidence: Some(confidence),
+                position: Some(position),
+            });
+        }
+
+        if posture != Posture::Lying {
+            history.fallen = false;
+        } else if !history.fallen {
+            if let Some((drop, speed)) = fall(&history.heights) {
+                if drop >= params.fall_drop && speed >= params.fall_speed {
+                    history.fallen = true;
+                    let fall_confidence =
+                        (margin_confidence(drop - params.fall_drop, params.fall_drop)
+                            + margin_confidence(speed - params.fall_speed, params.fall_speed))
+                            / 2.0;
+                    events.push(Event {
+                        kind: EventKind::Fall { object, source },
+                        severity: Severity::Critical,
+                        description: format!(
+                            "Object {} fell {:.2}m at up to {:.2}m/s",
+                            object, drop, speed
+                        ),
+                        confidence: Some(fall_confidence * confidence),
+                        position: Some(position),
+                    });
+                }
+            }
+        }
+        events
+    }
+
+    /// Forgets objects not updated since before a time
+    pub fn forget_before(&mut self, time: DateTime<Utc>) {
+        self.objects.retain(|_, history| history.seen >= time);
+    }
+}
+
+/// The drop from the highest height to the latest, and the fastest downward
+/// speed between two samples after that highest height
+fn fall(heights: &VecDeque<(DateTime<Utc>, f32)>) -> Option<(f32, f32)> {
+    let &(_, latest) = heights.back()?;
+    let (peak, &(_, highest)) = heights
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.1.total_cmp(&b.1))?;
+    let speed = heights
+        .iter()
+        .skip(peak)
+        .zip(heights.iter().skip(peak + 1))
+        .map(|(&(t0, h0), &(t1, h1))| {
+            let dt = (t1 - t0).num_milliseconds() as f32 / 1000.0;
+            if dt > 0.0 {
+                (h0 - h1) / dt
+            } else {
+                0.0
+            }
+        })
+        .fold(0.0, f32::max);
+    Some((highest - latest, speed))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{classify_height, skeletons, PostureMonitor, PostureParams};
+    use crate::{
+        ground::Plane,
+        message::Id,
+        pointcloud::PointCloud,
+        telemetry::{EventKind, Posture},
+    };
+
+    #[test]
+    pub fn test_classify() {
+        let params = PostureParams::default();
+        assert_eq!(classify_height(1.0, &params).0, Posture::Standing);
+        assert_eq!(classify_height(0.6, &params), (Posture::Sitting, 1.0));
+        assert_eq!(classify_height(0.1, &params).0, Posture::Lying);
+
+        // A BODY_18 skeleton standing upright, then lying along x
+        let standing: Vec<[f32; 3]> = vec![
+            [0.0, 0.0, 1.7], // Nose
+            [0.0, 0.0, 1.5], // Neck
+            [0.0, 0.0, 0.0], // Unused keypoints
+            [0.0, 0.0, 0.0],
+            [0.0, 0.0, 0.0],
+            [0.0, 0.0, 0.0],
+            [0.0, 0.0, 0.0],
+            [0.0, 0.0, 0.0],
+            [0.1, 0.0, 0.9],  // Right hip
+            [0.1, 0.0, 0.5],  // Right knee
+            [f32::NAN; 3],    // Right ankle, not placed
+            [-0.1, 0.0, 0.9], // Left hip
+            [-0.1, 0.0, 0.5], // Left knee
+        ];
+        let lying: Vec<[f32; 3]> = standing.iter().map(|&[x, y, z]| [z, y, x + 0.2]).collect();
+        let mut pointcloud = PointCloud::from(
+            standing
+                .iter()
+                .chain(lying.iter())
+                .map(|&p| p.into())
+                .collect::<Vec<_>>(),
+        );
+        pointcloud.attributes.track_id = (0..26).map(|i| i / 13).collect();
+        pointcloud.attributes.keypoint = (0..26).map(|i| i % 13).collect();
+        let skeletons = skeletons(&pointcloud);
+        assert_eq!(skeletons.len(), 2);
+        assert_eq!(skeletons[0].keypoints[10], None);
+        assert_eq!(skeletons[0].position(), Some([0.0, 0.0, 0.9]));
+        let floor = Plane::default();
+        assert_eq!(
+            skeletons[0].classify(&params, &floor).unwrap().0,
+            Posture::Standing
+        );
+        assert_eq!(
+            skeletons[1].classify(&params, &floor).unwrap().0,
+            Posture::Lying
+        );
+
+        // Heads are measured from the floor, wherever it is
+        let raised = Plane {
+            normal: [0.0, 0.0, 1.0],
+            offset: -0.5,
+        };
+        assert_eq!(skeletons[0].head_height(&raised), Some(1.2));
+        let mut headless = skeletons[0].clone();
+        headless.keypoints.truncate(1);
+        assert_eq!(
+            headless.classify(&params, &floor).unwrap().0,
+            Posture::Standing
+        );
+        assert_eq!(
+            headless.classify(&params, &raised).unwrap().0,
+            Posture::Sitting
+        );
+    }
+
+    #[test]
+    pub fn test_fall() {
+        let params = PostureParams::default();
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let source = Some(Id::Device(3, 0));
+        let mut monitor = PostureMonitor::new(params, source);
+        let mut falls = 0;
+        let update = |monitor: &mut PostureMonitor, ms: i64, height: f32| {
+            let events = monitor.update(
+                start + Duration::milliseconds(ms),
+                1,
+                [0.0, 0.0, height],
+                height,
+                classify_height(height, &params),
+            );
+            events
+                .iter()
+                .filter(|e| e.kind == EventKind::Fall { object: 1, source })
+                .count()
+        };
+
+        // Lying down slowly is not a fall
+        for (i, height) in [1.0, 0.9, 0.8, 0.7, 0.6, 0.5, 0.4, 0.3, 0.2]
+            .into_iter()
+            .enumerate()
+        {
+            falls += update(&mut monitor, i as i64 * 500, height);
+        }
+        assert_eq!(falls, 0);
+
+        // Standing up, then dropping 0.8m in 0.4s is
+        for (i, height) in [1.0, 1.0, 0.7, 0.4, 0.2, 0.2, 0.2].into_iter().enumerate() {
+            falls += update(&mut monitor, 10000 + i as i64 * 200, height);
+        }
+        assert_eq!(falls, 1);
+    }
+}
@@ -0,0 +1,195 @@
+use std::marker::PhantomData;
+
+use async_nats::{Client, SubscribeError, Subscriber};
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use tracing::warn;
+
+use crate::{
+    message::{Id, Message, MessageContent, Tag, TagsToSubject},
+    pointcloud::PointCloud,
+    subject::SubjectFilter,
+    telemetry::{
+        Clusters, DeviceState, DeviceStatus, Event, EventKind, Heartbeat, OccupancyGrid, Pose,
+        Predictions, Severity, Spectrum, TrackedObjects, VitalSigns,
+    },
+    wire::{self, Encoding, WireError},
+};
+
+/// A type that is carried by exactly one variant of `MessageContent`
+pub trait Content: Sized {
+    const TAG: Tag;
+
+    fn into_content(self) -> MessageContent;
+    fn from_content(content: MessageContent) -> Option<Self>;
+}
+
+macro_rules! impl_content {
+    ($type:ty, $variant:ident, $tag:expr) => {
+        impl Content for $type {
+            const TAG: Tag = $tag;
+
+            fn into_content(self) -> MessageContent {
+                MessageContent::$variant(self)
+            }
+
+            fn from_content(content: MessageContent) -> Option<Self> {
+                match content {
+                    MessageContent::$variant(inner) => Some(inner),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+impl_content!(PointCloud, PointCloud, Tag::Pointcloud);
+impl_content!(DeviceStatus, DeviceStatus, Tag::Status);
+impl_content!(Heartbeat, Heartbeat, Tag::Heartbeat);
+impl_content!(Event, Event, Tag::Event);
+impl_content!(TrackedObjects, TrackedObjects, Tag::Tracks);
+impl_content!(Spectrum, Spectrum, Tag::Spectrum);
+impl_content!(Clusters, Clusters, Tag::Clusters);
+impl_content!(OccupancyGrid, OccupancyGrid, Tag::Occupancy);
+impl_content!(VitalSigns, VitalSigns, Tag::Vitals);
+impl_content!(Predictions, Predictions, Tag::Predictions);
+impl_content!(Pose, Pose, Tag::Pose);
+
+/// Publishes messages on behalf of a single device (or machine), taking care
+/// of tags, subjects, encoding and schema headers.
+#[derive(Clone, Debug)]
+pub struct Publisher {
+    client: Client,
+    id: Id,
+    encoding: Encoding,
+}
+
+impl Publisher {
+    pub fn new(client: Client, id: Id) -> Self {
+        Self {
+            client,
+            id,
+            encoding: Encoding::default(),
+        }
+    }
+
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
+
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    pub async fn publish<T: Content>(&self, content: T) -> Result<(), WireError> {
+        self.publish_as(content, T::TAG).await
+    }
+
+    /// Publishes content under a kind other than its own, so that consumers
+    /// of that kind of content do not receive it (e.g. fused pointclouds)
+    pub async fn publish_as<T: Content>(&self, content: T, kind: Tag) -> Result<(), WireError> {
+        let message = Message {
+            content: content.into_content(),
+            tags: vec![kind, Tag::FromId(self.id)],
+            timestamp: chrono::Utc::now(),
+        };
+        let subject = message.tags.clone().to_subject();
+        wire::publish(&self.client, subject, &message, self.encoding).await
+    }
+
+    /// Publishes the current state of the device
+    pub async fn status(
+        &self,
+        state: DeviceState,
+        title: String,
+        detail: Option<String>,
+    ) -> Result<(), WireError> {
+        self.publish(DeviceStatus {
+            state,
+            title,
+            detail,
+            frame_rate: None,
+        })
+        .await
+    }
+
+    /// Publishes an error status for the device along with an error event
+    pub async fn error(&self, title: String, error: String) -> Result<(), WireError> {
+        let description = format!("{}: {}", title, error);
+        self.status(DeviceState::Error, title, Some(error)).await?;
+        self.publish(Event {
+            kind: EventKind::DeviceError,
+            severity: Severity::Error,
+            description,
+            confidence: None,
+            position: None,
+        })
+        .await
+    }
+}
+
+/// A message received by a `Subscription`
+#[derive(Debug, Clone)]
+pub struct Received<T> {
+    pub content: T,
+    pub from: Option<Id>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Receives one kind of content, silently skipping anything else published on
+/// the subject and logging messages that cannot be decoded.
+#[derive(Debug)]
+pub struct Subscription<T> {
+    subscriber: Subscriber,
+    content: PhantomData<T>,
+}
+
+impl<T: Content> Subscription<T> {
+    /// Subscribes to content of type T from the devices matching the filter
+    pub async fn new(client: &Client, filter: SubjectFilter) -> Result<Self, SubscribeError> {
+        Self::with_kind(client, filter, T::TAG).await
+    }
+
+    /// Subscribes to content of type T published under another kind
+    pub async fn with_kind(
+        client: &Client,
+        mut filter: SubjectFilter,
+        kind: Tag,
+    ) -> Result<Self, SubscribeError> {
+        filter.kind = Some(kind);
+        Ok(Self {
+            subscriber: client.subscribe(filter).await?,
+            content: PhantomData,
+        })
+    }
+
+    /// Waits for the next message, None once the subscription has closed
+    pub async fn next(&mut self) -> Option<Received<T>> {
+        while let Some(message) = self.subscriber.next().await {
+            let message = match wire::receive(&message) {
+                Ok(message) => message,
+                Err(e) => {
+                    warn!(error=%e, subject=%message.subject, "Dropping message that could not be decoded");
+                    continue;
+                }
+            };
+            let from = message.tags.iter().find_map(|tag| match tag {
+                Tag::FromId(id) => Some(*id),
+                _ => None,
+            });
+            if let Some(content) = T::from_content(message.content) {
+                return Some(Received {
+                    content,
+                    from,
+                    timestamp: message.timestamp,
+                });
+            }
+        }
+        None
+    }
+}
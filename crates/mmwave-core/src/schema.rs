@@ -0,0 +1,749 @@
+use bincode::Options;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{
+    message::{Id, Message, MessageContent, Tag},
+    point::Point,
+    pointcloud::{PointAttributes, PointCloud, SensorOrigin},
+    telemetry::{
+        Clusters, DeviceStatus, Event, EventKind, Heartbeat, OccupancyGrid, Pose, Posture,
+        Predictions, Severity, Spectrum, TrackedObject, TrackedObjects, VitalSigns,
+    },
+    wire::MAX_DECODED_SIZE,
+};
+
+/// Version of the message schema published by this build.
+///
+/// - 0: the original `Message`, pointclouds only carry x, y, z, v and labels
+/// - 1: pointclouds carry per point attributes, compact encoding available
+/// - 2: schema version and content type are sent as nats headers
+/// - 3: pointclouds carry the origins of the sensors that observed them
+/// - 4: pointclouds carry the height of each point above the floor
+/// - 5: tracks carry the device they were observed by even when empty
+/// - 6: zone and posture events carry the device the object was observed by
+///
+/// Bump this whenever the serialized layout of `Message`, `MessageContent`,
+/// `Tag` or `PointCloud` changes, and teach `convert` about the old layout.
+/// Appending new variants to an enum does not change the layout of existing
+/// messages and needs no bump.
+pub const SCHEMA_VERSION: u16 = 6;
+
+/// Header carrying the schema version of a published message
+pub const SCHEMA_HEADER: &str = "Mmwave-Schema";
+/// Header carrying the encoding of a published message's payload
+pub const CONTENT_TYPE_HEADER: &str = "Content-Type";
+/// Header carrying the kind of content in a published message
+pub const CONTENT_HEADER: &str = "Mmwave-Content";
+
+#[derive(Debug, Error)]
+pub enum SchemaError {
+    #[error("Schema version {0} is newer than this build supports ({SCHEMA_VERSION}), update this machine")]
+    TooNew(u16),
+    #[error("Malformed schema version header {0:?}")]
+    MalformedVersion(String),
+    #[error("Unknown content type {0:?}")]
+    UnknownContentType(String),
+    #[error("Payload does not match schema version {0}: {1}")]
+    Mismatch(u16, bincode::Error),
+}
+
+/// The options `bincode::serialize` uses, but refusing trailing bytes so a
+/// payload of one schema version is not mistaken for another, and refusing
+/// to allocate more than any message could need.
+pub(crate) fn strict_bincode() -> impl Options {
+    bincode::options()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+        .with_limit(MAX_DECODED_SIZE)
+}
+
+pub fn parse_version(header: &str) -> Result<u16, SchemaError> {
+    header
+        .trim()
+        .parse()
+        .map_err(|_| SchemaError::MalformedVersion(header.to_owned()))
+}
+
+/// Converts a plain bincode payload of an older schema version into a message
+pub fn convert(version: u16, payload: &[u8]) -> Result<Message, SchemaError> {
+    match version {
+        0 => strict_bincode()
+            .deserialize::<MessageV0>(payload)
+            .map(Into::into)
+            .map_err(|e| SchemaError::Mismatch(0, e)),
+        1 | 2 => strict_bincode()
+            .deserialize::<MessageV2>(payload)
+            .map(Into::into)
+            .map_err(|e| SchemaError::Mismatch(version, e)),
+        3 => strict_bincode()
+            .deserialize::<MessageV3>(payload)
+            .map(Into::into)
+            .map_err(|e| SchemaError::Mismatch(3, e)),
+        4 => strict_bincode()
+            .deserialize::<MessageV4>(payload)
+            .map(Into::into)
+            .map_err(|e| SchemaError::Mismatch(4, e)),
+        5 => strict_bincode()
+            .deserialize::<MessageV5>(payload)
+            .map(Into::into)
+            .map_err(|e| SchemaError::Mismatch(5, e)),
+        version if version <= SCHEMA_VERSION => strict_bincode()
+            .deserialize(payload)
+            .map_err(|e| SchemaError::Mismatch(version, e)),
+        version => Err(SchemaError::TooNew(version)),
+    }
+}
+
+#[derive(Deserialize)]
+struct MessageV0 {
+    content: MessageContentV0,
+    tags: Vec<Tag>,
+    timestamp: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+enum MessageContentV0 {
+    PointCloud(PointCloudV0),
+    Empty,
+}
+
+#[derive(Deserialize)]
+struct PointCloudV0 {
+    time: DateTime<Utc>,
+    x: Vec<f32>,
+    y: Vec<f32>,
+    z: Vec<f32>,
+    v: Vec<f32>,
+    l: Vec<String>,
+}
+
+impl From<MessageV0> for Message {
+    fn from(message: MessageV0) -> Self {
+        let content = match message.content {
+            MessageContentV0::PointCloud(pc) => MessageContent::PointCloud(PointCloud {
+                time: pc.time,
+                points: pc
+                    .x
+                    .into_iter()
+                    .zip(pc.y)
+                    .zip(pc.z)
+                    .zip(pc.v)
+                    .map(|(((x, y), z), v)| Point { x, y, z, v })
+                    .collect(),
+                labels: pc.l,
+                ..Default::default()
+            }),
+            MessageContentV0::Empty => MessageContent::Empty,
+        };
+        Message {
+            content,
+            tags: message.tags,
+            timestamp: message.timestamp,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct MessageV2 {
+    content: MessageContentV2,
+    tags: Vec<Tag>,
+    timestamp: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub(crate) enum MessageContentV2 {
+    PointCloud(PointCloudV2),
+    Empty,
+    DeviceStatus(DeviceStatus),
+    Heartbeat(Heartbeat),
+    Event(EventV5),
+    TrackedObjects(TrackedObjectsV4),
+    Spectrum(Spectrum),
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PointCloudV2 {
+    time: DateTime<Utc>,
+    x: Vec<f32>,
+    y: Vec<f32>,
+    z: Vec<f32>,
+    v: Vec<f32>,
+    l: Vec<String>,
+    snr: Vec<f32>,
+    noise: Vec<f32>,
+    intensity: Vec<f32>,
+    track_id: Vec<u32>,
+    keypoint: Vec<u16>,
+    source: Vec<Id>,
+}
+
+/// Point attributes as they were before points carried their height
+#[derive(Deserialize)]
+pub(crate) struct PointAttributesV3 {
+    snr: Vec<f32>,
+    noise: Vec<f32>,
+    intensity: Vec<f32>,
+    track_id: Vec<u32>,
+    keypoint: Vec<u16>,
+    source: Vec<Id>,
+}
+
+impl From<PointAttributesV3> for PointAttributes {
+    fn from(attributes: PointAttributesV3) -> Self {
+        PointAttributes {
+            snr: attributes.snr,
+            noise: attributes.noise,
+            intensity: attributes.intensity,
+            track_id: attributes.track_id,
+            keypoint: attributes.keypoint,
+            source: attributes.source,
+            height: Vec::new(),
+        }
+    }
+}
+
+impl From<MessageContentV2> for MessageContent {
+    fn from(content: MessageContentV2) -> Self {
+        match content {
+            MessageContentV2::PointCloud(pc) => MessageContent::PointCloud(PointCloud {
+                time: pc.time,
+                points: pc
+                    .x
+                    .into_iter()
+                    .zip(pc.y)
+                    .zip(pc.z)
+                    .zip(pc.v)
+                    .map(|(((x, y), z), v)| Point { x, y, z, v })
+                    .collect(),
+                labels: pc.l,
+                attributes: PointAttributes {
+                    snr: pc.snr,
+                    noise: pc.noise,
+                    intensity: pc.intensity,
+                    track_id: pc.track_id,
+                    keypoint: pc.keypoint,
+                    source: pc.source,
+                    height: Vec::new(),
+                },
+                origins: Vec::new(),
+            }),
+            MessageContentV2::Empty => MessageContent::Empty,
+            MessageContentV2::DeviceStatus(status) => MessageContent::DeviceStatus(status),
+            MessageContentV2::Heartbeat(heartbeat) => MessageContent::Heartbeat(heartbeat),
+            MessageContentV2::Event(event) => MessageContent::Event(event.into()),
+            MessageContentV2::TrackedObjects(tracks) => {
+                MessageContent::TrackedObjects(tracks.into())
+            }
+            MessageContentV2::Spectrum(spectrum) => MessageContent::Spectrum(spectrum),
+        }
+    }
+}
+
+impl From<MessageV2> for Message {
+    fn from(message: MessageV2) -> Self {
+        Message {
+            content: message.content.into(),
+            tags: message.tags,
+            timestamp: message.timestamp,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct MessageV3 {
+    content: MessageContentV3,
+    tags: Vec<Tag>,
+    timestamp: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub(crate) enum MessageContentV3 {
+    PointCloud(PointCloudV3),
+    Empty,
+    DeviceStatus(DeviceStatus),
+    Heartbeat(Heartbeat),
+    Event(EventV5),
+    TrackedObjects(TrackedObjectsV4),
+    Spectrum(Spectrum),
+    Clusters(Clusters),
+    OccupancyGrid(OccupancyGrid),
+    VitalSigns(VitalSigns),
+    Predictions(Predictions),
+    Pose(Pose),
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PointCloudV3 {
+    time: DateTime<Utc>,
+    x: Vec<f32>,
+    y: Vec<f32>,
+    z: Vec<f32>,
+    v: Vec<f32>,
+    l: Vec<String>,
+    snr: Vec<f32>,
+    noise: Vec<f32>,
+    intensity: Vec<f32>,
+    track_id: Vec<u32>,
+    keypoint: Vec<u16>,
+    source: Vec<Id>,
+    origins: Vec<SensorOrigin>,
+}
+
+impl From<MessageContentV3> for MessageContent {
+    fn from(content: MessageContentV3) -> Self {
+        match content {
+            MessageContentV3::PointCloud(pc) => MessageContent::PointCloud(PointCloud {
+                time: pc.time,
+                points: pc
+                    .x
+                    .into_iter()
+                    .zip(pc.y)
+                    .zip(pc.z)
+                    .zip(pc.v)
+                    .map(|(((x, y), z), v)| Point { x, y, z, v })
+                    .collect(),
+                labels: pc.l,
+                attributes: PointAttributesV3 {
+                    snr: pc.snr,
+                    noise: pc.noise,
+                    intensity: pc.intensity,
+                    track_id: pc.track_id,
+                    keypoint: pc.keypoint,
+                    source: pc.source,
+                }
+                .into(),
+                origins: pc.origins,
+            }),
+            MessageContentV3::Empty => MessageContent::Empty,
+            MessageContentV3::DeviceStatus(status) => MessageContent::DeviceStatus(status),
+            MessageContentV3::Heartbeat(heartbeat) => MessageContent::Heartbeat(heartbeat),
+            MessageContentV3::Event(event) => MessageContent::Event(event.into()),
+            MessageContentV3::TrackedObjects(tracks) => {
+                MessageContent::TrackedObjects(tracks.into())
+            }
+            MessageContentV3::Spectrum(spectrum) => MessageContent::Spectrum(spectrum),
+            MessageContentV3::Clusters(clusters) => MessageContent::Clusters(clusters),
+            MessageContentV3::OccupancyGrid(grid) => MessageContent::OccupancyGrid(grid),
+            MessageContentV3::VitalSigns(vitals) => MessageContent::VitalSigns(vitals),
+            MessageContentV3::Predictions(predictions) => MessageContent::Predictions(predictions),
+            MessageContentV3::Pose(pose) => MessageContent::Pose(pose),
+        }
+    }
+}
+
+impl From<MessageV3> for Message {
+    fn from(message: MessageV3) -> Self {
+        Message {
+            content: message.content.into(),
+            tags: message.tags,
+            timestamp: message.timestamp,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct MessageV4 {
+    content: MessageContentV4,
+    tags: Vec<Tag>,
+    timestamp: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub(crate) enum MessageContentV4 {
+    PointCloud(PointCloud),
+    Empty,
+    DeviceStatus(DeviceStatus),
+    Heartbeat(Heartbeat),
+    Event(EventV5),
+    TrackedObjects(TrackedObjectsV4),
+    Spectrum(Spectrum),
+    Clusters(Clusters),
+    OccupancyGrid(OccupancyGrid),
+    VitalSigns(VitalSigns),
+    Predictions(Predictions),
+    Pose(Pose),
+}
+
+/// Tracks as they were before carrying their source, which is recovered from
+/// the tracks themselves when there are any
+#[derive(Deserialize)]
+pub(crate) struct TrackedObjectsV4 {
+    objects: Vec<TrackedObject>,
+}
+
+impl From<TrackedObjectsV4> for TrackedObjects {
+    fn from(tracks: TrackedObjectsV4) -> Self {
+        TrackedObjects {
+            source: tracks.objects.first().and_then(|object| object.source),
+            objects: tracks.objects,
+        }
+    }
+}
+
+impl From<MessageContentV4> for MessageContent {
+    fn from(content: MessageContentV4) -> Self {
+        match content {
+            MessageContentV4::PointCloud(pointcloud) => MessageContent::PointCloud(pointcloud),
+            MessageContentV4::Empty => MessageContent::Empty,
+            MessageContentV4::DeviceStatus(status) => MessageContent::DeviceStatus(status),
+            MessageContentV4::Heartbeat(heartbeat) => MessageContent::Heartbeat(heartbeat),
+            MessageContentV4::Event(event) => MessageContent::Event(event.into()),
+            MessageContentV4::TrackedObjects(tracks) => {
+                MessageContent::TrackedObjects(tracks.into())
+            }
+            MessageContentV4::Spectrum(spectrum) => MessageContent::Spectrum(spectrum),
+            MessageContentV4::Clusters(clusters) => MessageContent::Clusters(clusters),
+            MessageContentV4::OccupancyGrid(grid) => MessageContent::OccupancyGrid(grid),
+            MessageContentV4::VitalSigns(vitals) => MessageContent::VitalSigns(vitals),
+            MessageContentV4::Predictions(predictions) => MessageContent::Predictions(predictions),
+            MessageContentV4::Pose(pose) => MessageContent::Pose(pose),
+        }
+    }
+}
+
+impl From<MessageV4> for Message {
+    fn from(message: MessageV4) -> Self {
+        Message {
+            content: message.content.into(),
+            tags: message.tags,
+            timestamp: message.timestamp,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct MessageV5 {
+    content: MessageContentV5,
+    tags: Vec<Tag>,
+    timestamp: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub(crate) enum MessageContentV5 {
+    PointCloud(PointCloud),
+    Empty,
+    DeviceStatus(DeviceStatus),
+    Heartbeat(Heartbeat),
+    Event(EventV5),
+    TrackedObjects(TrackedObjects),
+    Spectrum(Spectrum),
+    Clusters(Clusters),
+    OccupancyGrid(OccupancyGrid),
+    VitalSigns(VitalSigns),
+    Predictions(Predictions),
+    Pose(Pose),
+}
+
+/// Events as they were before zone and posture events carried the source of
+/// their object
+#[derive(Deserialize)]
+pub(crate) struct EventV5 {
+    kind: EventKindV5,
+    severity: Severity,
+    description: String,
+    confidence: Option<f32>,
+    position: Option<[f32; 3]>,
+}
+
+#[derive(Deserialize)]
+enum EventKindV5 {
+    DeviceError,
+    Custom(String),
+    ZoneEntered {
+        zone: String,
+        object: Option<u64>,
+    },
+    ZoneExited {
+        zone: String,
+        object: Option<u64>,
+        duration: f32,
+    },
+    ZoneDwell {
+        zone: String,
+        object: Option<u64>,
+        duration: f32,
+    },
+    ZoneOccupancy {
+        zone: String,
+        count: u32,
+    },
+    PostureChanged {
+        object: u64,
+        posture: Posture,
+    },
+    Fall {
+        object: u64,
+    },
+}
+
+impl From<EventKindV5> for EventKind {
+    fn from(kind: EventKindV5) -> Self {
+        match kind {
+            EventKindV5::DeviceError => EventKind::DeviceError,
+            EventKindV5::Custom(name) => EventKind::Custom(name),
+            EventKindV5::ZoneEntered { zone, object } => EventKind::ZoneEntered {
+                zone,
+                object,
+                source: None,
+            },
+            EventKindV5::ZoneExited {
+                zone,
+                object,
+                duration,
+            } => EventKind::ZoneExited {
+                zone,
+                object,
+                source: None,
+                duration,
+            },
+            EventKindV5::ZoneDwell {
+                zone,
+                object,
+                duration,
+            } => EventKind::ZoneDwell {
+                zone,
+                object,
+                source: None,
+                duration,
+            },
+            EventKindV5::ZoneOccupancy { zone, count } => EventKind::ZoneOccupancy { zone, count },
+            EventKindV5::PostureChanged { object, posture } => EventKind::PostureChanged {
+                object,
+                source: None,
+                posture,
+            },
+            EventKindV5::Fall { object } => EventKind::Fall {
+                object,
+                source: None,
+            },
+        }
+    }
+}
+
+impl From<EventV5> for Event {
+    fn from(event: EventV5) -> Self {
+        Event {
+            kind: event.kind.into(),
+            severity: event.severity,
+            description: event.description,
+            confidence: event.confidence,
+            position: event.position,
+        }
+    }
+}
+
+impl From<MessageContentV5> for MessageContent {
+    fn from(content: MessageContentV5) -> Self {
+        match content {
+            MessageContentV5::PointCloud(pointcloud) => MessageContent::PointCloud(pointcloud),
+            MessageContentV5::Empty => MessageContent::Empty,
+            MessageContentV5::DeviceStatus(status) => MessageContent::DeviceStatus(status),
+            MessageContentV5::Heartbeat(heartbeat) => MessageContent::Heartbeat(heartbeat),
+            MessageContentV5::Event(event) => MessageContent::Event(event.into()),
+            MessageContentV5::TrackedObjects(tracks) => MessageContent::TrackedObjects(tracks),
+            MessageContentV5::Spectrum(spectrum) => MessageContent::Spectrum(spectrum),
+            MessageContentV5::Clusters(clusters) => MessageContent::Clusters(clusters),
+            MessageContentV5::OccupancyGrid(grid) => MessageContent::OccupancyGrid(grid),
+            MessageContentV5::VitalSigns(vitals) => MessageContent::VitalSigns(vitals),
+            MessageContentV5::Predictions(predictions) => MessageContent::Predictions(predictions),
+            MessageContentV5::Pose(pose) => MessageContent::Pose(pose),
+        }
+    }
+}
+
+impl From<MessageV5> for Message {
+    fn from(message: MessageV5) -> Self {
+        Message {
+            content: message.content.into(),
+            tags: message.tags,
+            timestamp: message.timestamp,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_nats::HeaderMap;
+
+    use super::{SCHEMA_HEADER, SCHEMA_VERSION};
+    use crate::{
+        message::{Id, Message, MessageContent, Tag},
+        telemetry::{EventKind, Severity},
+        wire::{decode_with_headers, headers, Encoding, WireError},
+    };
+
+    // Fixtures were published by the builds introducing each schema version
+    const V0_POINTCLOUD: &[u8] = include_bytes!("../tests/fixtures/v0_pointcloud.bin");
+    const V0_EMPTY: &[u8] = include_bytes!("../tests/fixtures/v0_empty.bin");
+    const V1_POINTCLOUD: &[u8] = include_bytes!("../tests/fixtures/v1_pointcloud.bin");
+    const V1_COMPACT: &[u8] = include_bytes!("../tests/fixtures/v1_pointcloud_compact.bin");
+    const V2_COMPACT: &[u8] = include_bytes!("../tests/fixtures/v2_pointcloud_compact.bin");
+    const V2_STATUS: &[u8] = include_bytes!("../tests/fixtures/v2_status.bin");
+    const V3_POINTCLOUD: &[u8] = include_bytes!("../tests/fixtures/v3_pointcloud.bin");
+    const V3_COMPACT: &[u8] = include_bytes!("../tests/fixtures/v3_pointcloud_compact.bin");
+    const V3_POSE: &[u8] = include_bytes!("../tests/fixtures/v3_pose_compact.bin");
+    const V4_TRACKS: &[u8] = include_bytes!("../tests/fixtures/v4_tracks.bin");
+    const V4_COMPACT: &[u8] = include_bytes!("../tests/fixtures/v4_tracks_compact.bin");
+    const V5_ZONE: &[u8] = include_bytes!("../tests/fixtures/v5_zone_event.bin");
+    const V5_FALL: &[u8] = include_bytes!("../tests/fixtures/v5_fall_event_compact.bin");
+
+    fn check_pointcloud(message: Message) {
+        assert_eq!(
+            message.tags,
+            vec![Tag::Pointcloud, Tag::FromId(Id::Device(1, 0))]
+        );
+        let MessageContent::PointCloud(pointcloud) = message.content else {
+            panic!("expected a pointcloud");
+        };
+        assert_eq!(
+            pointcloud.points,
+            vec![[1.0, 2.0, 0.5, -0.25].into(), [-1.5, 3.0, 0.0, 0.75].into()]
+        );
+    }
+
+    #[test]
+    pub fn test_decode_unversioned_v0() {
+        let message = decode_with_headers(None, V0_POINTCLOUD).unwrap();
+        let MessageContent::PointCloud(pointcloud) = &message.content else {
+            panic!("expected a pointcloud");
+        };
+        assert_eq!(pointcloud.labels, vec!["1:0", "1:0"]);
+        check_pointcloud(message);
+
+        let message = decode_with_headers(None, V0_EMPTY).unwrap();
+        assert!(matches!(message.content, MessageContent::Empty));
+        assert_eq!(message.tags, vec![Tag::FromId(Id::Machine(2))]);
+    }
+
+    #[test]
+    pub fn test_decode_unversioned_v1() {
+        for payload in [V1_POINTCLOUD, V1_COMPACT] {
+            let message = decode_with_headers(None, payload).unwrap();
+            let MessageContent::PointCloud(pointcloud) = &message.content else {
+                panic!("expected a pointcloud");
+            };
+            assert_eq!(pointcloud.attributes.snr, vec![12.5, 8.0]);
+            assert_eq!(pointcloud.attributes.source, vec![Id::Device(1, 0); 2]);
+            check_pointcloud(message);
+        }
+    }
+
+    #[test]
+    pub fn test_decode_explicit_version() {
+        let mut headers = HeaderMap::new();
+        headers.insert(SCHEMA_HEADER, "0");
+        check_pointcloud(decode_with_headers(Some(&headers), V0_POINTCLOUD).unwrap());
+
+        // A v0 payload claiming to be v1 must not be misread
+        headers.insert(SCHEMA_HEADER, "1");
+        assert!(decode_with_headers(Some(&headers), V0_POINTCLOUD).is_err());
+    }
+
+    #[test]
+    pub fn test_decode_v2() {
+        let mut headers = HeaderMap::new();
+        headers.insert(SCHEMA_HEADER, "2");
+        for payload in [V1_POINTCLOUD, V2_COMPACT] {
+            let message = decode_with_headers(Some(&headers), payload).unwrap();
+            let MessageContent::PointCloud(pointcloud) = &message.content else {
+                panic!("expected a pointcloud");
+            };
+            assert!(pointcloud.origins.is_empty());
+            check_pointcloud(message);
+        }
+
+        let message = decode_with_headers(Some(&headers), V2_STATUS).unwrap();
+        let MessageContent::DeviceStatus(status) = message.content else {
+            panic!("expected a status");
+        };
+        assert_eq!(status.frame_rate, Some(10.0));
+    }
+
+    #[test]
+    pub fn test_decode_v3() {
+        let mut headers = HeaderMap::new();
+        headers.insert(SCHEMA_HEADER, "3");
+        for payload in [V3_POINTCLOUD, V3_COMPACT] {
+            let message = decode_with_headers(Some(&headers), payload).unwrap();
+            let MessageContent::PointCloud(pointcloud) = &message.content else {
+                panic!("expected a pointcloud");
+            };
+            assert_eq!(pointcloud.origin(0), Some([0.0, 0.0, 1.5]));
+            assert_eq!(pointcloud.attributes.snr, vec![12.5, 8.0]);
+            assert!(pointcloud.attributes.height.is_empty());
+            check_pointcloud(message);
+        }
+
+        let message = decode_with_headers(Some(&headers), V3_POSE).unwrap();
+        let MessageContent::Pose(pose) = message.content else {
+            panic!("expected a pose");
+        };
+        assert_eq!(pose.velocity, Some([0.5, 0.0, 0.0]));
+    }
+
+    #[test]
+    pub fn test_decode_v4() {
+        let mut headers = HeaderMap::new();
+        headers.insert(SCHEMA_HEADER, "4");
+        for payload in [V4_TRACKS, V4_COMPACT] {
+            let message = decode_with_headers(Some(&headers), payload).unwrap();
+            assert_eq!(
+                message.tags,
+                vec![Tag::Tracks, Tag::FromId(Id::Device(4, 2))]
+            );
+            let MessageContent::TrackedObjects(tracks) = message.content else {
+                panic!("expected tracks");
+            };
+            assert_eq!(tracks.objects[0].id, 3);
+            assert_eq!(tracks.source, Some(Id::Device(2, 0)));
+        }
+    }
+
+    #[test]
+    pub fn test_decode_v5() {
+        let mut headers = HeaderMap::new();
+        headers.insert(SCHEMA_HEADER, "5");
+        let mut kinds = Vec::new();
+        for payload in [V5_ZONE, V5_FALL] {
+            let message = decode_with_headers(Some(&headers), payload).unwrap();
+            assert_eq!(
+                message.tags,
+                vec![Tag::Event, Tag::FromId(Id::Device(5, 1))]
+            );
+            let MessageContent::Event(event) = message.content else {
+                panic!("expected an event");
+            };
+            assert_eq!(event.severity, Severity::Critical);
+            assert_eq!(event.confidence, Some(0.75));
+            kinds.push(event.kind);
+        }
+        assert_eq!(
+            kinds,
+            vec![
+                EventKind::ZoneEntered {
+                    zone: String::from("bed"),
+                    object: Some(3),
+                    source: None,
+                },
+                EventKind::Fall {
+                    object: 7,
+                    source: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_reject_newer_version() {
+        let message = decode_with_headers(None, V1_POINTCLOUD).unwrap();
+        let mut headers = headers(&message, Encoding::Bincode);
+        let newer = (SCHEMA_VERSION + 1).to_string();
+        headers.insert(SCHEMA_HEADER, newer.as_str());
+        assert!(matches!(
+            decode_with_headers(Some(&headers), V1_POINTCLOUD),
+            Err(WireError::Schema(super::SchemaError::TooNew(_)))
+        ));
+    }
+}
@@ -0,0 +1,239 @@
+use crate::pointcloud::PointCloud;
+
+/// A kd-tree over 3d points for neighbour queries, e.g. over the points of a
+/// pointcloud. Building it is a sort (n log n), so it is cheap enough to
+/// rebuild for every frame; `rebuild` reuses its buffer to do so. Queries
+/// return indices into the points it was built from. Points that are not
+/// finite, e.g. keypoints a camera could not place, are left out.
+#[derive(Debug, Clone, Default)]
+pub struct KdTree {
+    // Each point with its index, balanced and stored implicitly: the median
+    // of each range is its node, splitting on x, y and z in turn with depth
+    nodes: Vec<([f32; 3], usize)>,
+}
+
+impl KdTree {
+    pub fn new(points: &[[f32; 3]]) -> Self {
+        let mut tree = Self::default();
+        tree.rebuild(points.iter().copied());
+        tree
+    }
+
+    /// Replaces the points of the tree
+    pub fn rebuild(&mut self, points: impl IntoIterator<Item = [f32; 3]>) {
+        self.nodes.clear();
+        self.nodes.extend(
+            points
+                .into_iter()
+                .enumerate()
+                .filter(|(_, point)| point.iter().all(|x| x.is_finite()))
+                .map(|(i, point)| (point, i)),
+        );
+        build(&mut self.nodes, 0);
+    }
+
+    /// Number of points in the tree, which excludes those that are not finite
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// The points no further than radius from centre, in no particular order
+    pub fn within(&self, centre: [f32; 3], radius: f32) -> Vec<usize> {
+        let mut found = Vec::new();
+        self.visit_within(0, self.len(), 0, centre, radius, &mut found);
+        found
+    }
+
+    /// The k points nearest to point with their distances, nearest first
+    pub fn nearest(&self, point: [f32; 3], k: usize) -> Vec<(usize, f32)> {
+        let mut found = Vec::with_capacity(k + 1);
+        if k > 0 {
+            self.visit_nearest(0, self.len(), 0, point, k, &mut found);
+        }
+        found
+            .into_iter()
+            .map(|(squared, i)| (i, f32::sqrt(squared)))
+            .collect()
+    }
+
+    /// The points inside the axis aligned box between min and max, inclusive
+    pub fn in_box(&self, min: [f32; 3], max: [f32; 3]) -> Vec<usize> {
+        let mut found = Vec::new();
+        self.visit_box(0, self.len(), 0, min, max, &mut found);
+        found
+    }
+
+    fn visit_within(
+        &self,
+        start: usize,
+        end: usize,
+        depth: usize,
+        centre: [f32; 3],
+        radius: f32,
+        found: &mut Vec<usize>,
+    ) {
+        if start >= end {
+            return;
+        }
+        let mid = (start + end) / 2;
+        let (point, index) = self.nodes[mid];
+        if squared_distance(point, centre) <= radius * radius {
+            found.push(index);
+        }
+        let offset = centre[depth % 3] - point[depth % 3];
+        if offset <= radius {
+            self.visit_within(start, mid, depth + 1, centre, radius, found);
+        }
+        if offset >= -radius {
+            self.visit_within(mid + 1, end, depth + 1, centre, radius, found);
+        }
+    }
+
+    /// found holds the squared distances and indices of the nearest points
+    /// so far, nearest first
+    fn visit_nearest(
+        &self,
+        start: usize,
+        end: usize,
+        depth: usize,
+        target: [f32; 3],
+        k: usize,
+        found: &mut Vec<(f32, usize)>,
+    ) {
+        if start >= end {
+            return;
+        }
+        let mid = (start + end) / 2;
+        let (point, index) = self.nodes[mid];
+        let squared = squared_distance(point, target);
+        if found.len() < k || squared < found[found.len() - 1].0 {
+            let at = found.partition_point(|&(d, _)| d <= squared);
+            found.insert(at, (squared, index));
+            found.truncate(k);
+        }
+
+        // The side the target is on first, as it is the likelier to hold them
+        let offset = target[depth % 3] - point[depth % 3];
+        let (near, far) = if offset <= 0.0 {
+            ((start, mid), (mid + 1, end))
+        } else {
+            ((mid + 1, end), (start, mid))
+        };
+        self.visit_nearest(near.0, near.1, depth + 1, target, k, found);
+        if found.len() < k || offset * offset < found[found.len() - 1].0 {
+            self.visit_nearest(far.0, far.1, depth + 1, target, k, found);
+        }
+    }
+
+    fn visit_box(
+        &self,
+        start: usize,
+        end: usize,
+        depth: usize,
+        min: [f32; 3],
+        max: [f32; 3],
+        found: &mut Vec<usize>,
+    ) {
+        if start >= end {
+            return;
+        }
+        let mid = (start + end) / 2;
+        let (point, index) = self.nodes[mid];
+        if (0..3).all(|k| min[k] <= point[k] && point[k] <= max[k]) {
+            found.push(index);
+        }
+        let axis = depth % 3;
+        if min[axis] <= point[axis] {
+            self.visit_box(start, mid, depth + 1, min, max, found);
+        }
+        if max[axis] >= point[axis] {
+            self.visit_box(mid + 1, end, depth + 1, min, max, found);
+        }
+    }
+}
+
+impl From<&PointCloud> for KdTree {
+    fn from(pointcloud: &PointCloud) -> Self {
+        let mut tree = Self::default();
+        tree.rebuild(pointcloud.points.iter().map(|&p| p.into()));
+        tree
+    }
+}
+
+/// Orders nodes so the median of every range splits it along the axis of its
+/// depth
+fn build(nodes: &mut [([f32; 3], usize)], depth: usize) {
+    if nodes.len() <= 1 {
+        return;
+    }
+    let mid = nodes.len() / 2;
+    let axis = depth % 3;
+    nodes.select_nth_unstable_by(mid, |a, b| a.0[axis].total_cmp(&b.0[axis]));
+    let (left, right) = nodes.split_at_mut(mid);
+    build(left, depth + 1);
+    build(&mut right[1..], depth + 1);
+}
+
+fn squared_distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{squared_distance, KdTree};
+
+    #[test]
+    pub fn test_queries_match_brute_force() {
+        let mut seed = 12345u32;
+        let mut random = || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 8) as f32 / (1 << 24) as f32 * 10.0 - 5.0
+        };
+        let mut points: Vec<[f32; 3]> = (0..500).map(|_| [random(), random(), random()]).collect();
+        points.extend([[1.0, 1.0, 1.0]; 3]);
+        // Unplaced points are skipped, without hiding the points around them
+        for i in (0..points.len()).step_by(7) {
+            points[i][i % 3] = f32::NAN;
+        }
+        points.push([f32::INFINITY, 0.0, 0.0]);
+        let tree = KdTree::new(&points);
+        let finite = points.iter().filter(|p| p.iter().all(|x| x.is_finite()));
+        assert_eq!(tree.len(), finite.count());
+
+        for _ in 0..20 {
+            let centre = [random(), random(), random()];
+
+            let mut within = tree.within(centre, 1.5);
+            within.sort_unstable();
+            let expected: Vec<usize> = (0..points.len())
+                .filter(|&i| squared_distance(points[i], centre) <= 1.5 * 1.5)
+                .collect();
+            assert_eq!(within, expected);
+
+            let nearest = tree.nearest(centre, 5);
+            let mut expected: Vec<f32> = points
+                .iter()
+                .map(|&p| squared_distance(p, centre).sqrt())
+                .collect();
+            expected.sort_by(f32::total_cmp);
+            let distances: Vec<f32> = nearest.iter().map(|&(_, d)| d).collect();
+            assert_eq!(distances, expected[..5]);
+
+            let (min, max) = (centre.map(|x| x - 1.0), centre.map(|x| x + 2.0));
+            let mut in_box = tree.in_box(min, max);
+            in_box.sort_unstable();
+            let expected: Vec<usize> = (0..points.len())
+                .filter(|&i| (0..3).all(|k| min[k] <= points[i][k] && points[i][k] <= max[k]))
+                .collect();
+            assert_eq!(in_box, expected);
+        }
+
+        // Points on top of each other are all found
+        assert_eq!(tree.within([1.0, 1.0, 1.0], 0.0).len(), 3);
+        assert!(KdTree::new(&[]).nearest([0.0; 3], 3).is_empty());
+    }
+}
@@ -0,0 +1,196 @@
+//! Nats subjects are laid out as `mmwave.<machine>.<device>.<kind>`, so that
+//! consumers can use ordinary nats wildcards to listen to one machine
+//! (`mmwave.1.*.*`), one device (`mmwave.1.0.*`) or one kind of data from
+//! everywhere (`mmwave.*.*.pointcloud`).
+
+use async_nats::subject::ToSubject;
+use thiserror::Error;
+
+use crate::message::{Id, Tag};
+
+pub const ROOT: &str = "mmwave";
+
+/// Stands in for a part of the subject the message does not have, such as
+/// the device of a message sent on behalf of a whole machine.
+const NONE: &str = "_";
+const WILDCARD: &str = "*";
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SubjectParseError {
+    #[error("Subject {0:?} is not of the form {ROOT}.<machine>.<device>.<kind>")]
+    InvalidFormat(String),
+    #[error("Invalid id in subject: {0}")]
+    InvalidId(#[from] std::num::ParseIntError),
+    #[error("Unknown kind {0:?}")]
+    UnknownKind(String),
+}
+
+/// Builds the subject a message with the given tags is published on
+pub fn subject(tags: &[Tag]) -> String {
+    let id = tags.iter().find_map(|tag| match tag {
+        Tag::FromId(id) => Some(*id),
+        _ => None,
+    });
+    let (machine, device) = match id {
+        Some(Id::Device(m, d)) => (m.to_string(), d.to_string()),
+        Some(Id::Machine(m)) => (m.to_string(), NONE.to_owned()),
+        None => (NONE.to_owned(), NONE.to_owned()),
+    };
+    let kind = tags.iter().find_map(Tag::kind).unwrap_or(NONE);
+    format!("{ROOT}.{machine}.{device}.{kind}")
+}
+
+/// Recovers the tags a message was published with from its subject
+pub fn parse(subject: &str) -> Result<Vec<Tag>, SubjectParseError> {
+    let invalid = || SubjectParseError::InvalidFormat(subject.to_owned());
+    let [root, machine, device, kind] = subject
+        .split('.')
+        .collect::<Vec<_>>()
+        .try_into()
+        .map_err(|_| invalid())?;
+    if root != ROOT {
+        return Err(invalid());
+    }
+
+    let mut tags = Vec::new();
+    if kind != NONE {
+        let tag =
+            Tag::from_kind(kind).ok_or_else(|| SubjectParseError::UnknownKind(kind.into()))?;
+        tags.push(tag);
+    }
+    match (machine, device) {
+        (NONE, NONE) => {}
+        (NONE, _) => return Err(invalid()),
+        (m, NONE) => tags.push(Tag::FromId(Id::Machine(m.parse()?))),
+        (m, d) => tags.push(Tag::FromId(Id::Device(m.parse()?, d.parse()?))),
+    }
+    Ok(tags)
+}
+
+/// A subscription to some subset of subjects, anything left unset matches all.
+/// Implements `ToSubject`, so it can be handed straight to `Client::subscribe`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SubjectFilter {
+    pub machine: Option<usize>,
+    pub device: Option<usize>,
+    pub kind: Option<Tag>,
+}
+
+impl SubjectFilter {
+    /// Matches every message
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// Matches one kind of message from every device
+    pub fn kind(kind: Tag) -> Self {
+        Self {
+            kind: Some(kind),
+            ..Default::default()
+        }
+    }
+
+    /// Restricts the filter to messages from a single machine
+    pub fn machine(mut self, machine: usize) -> Self {
+        self.machine = Some(machine);
+        self
+    }
+
+    /// Restricts the filter to messages from a single device (or machine)
+    pub fn id(mut self, id: Id) -> Self {
+        match id {
+            Id::Device(m, d) => {
+                self.machine = Some(m);
+                self.device = Some(d);
+            }
+            Id::Machine(m) => self.machine = Some(m),
+        }
+        self
+    }
+
+    pub fn to_subject_string(&self) -> String {
+        let token = |value: Option<usize>| value.map_or(WILDCARD.to_owned(), |v| v.to_string());
+        let kind = self.kind.as_ref().and_then(Tag::kind).unwrap_or(WILDCARD);
+        format!(
+            "{ROOT}.{}.{}.{kind}",
+            token(self.machine),
+            token(self.device)
+        )
+    }
+}
+
+impl ToSubject for SubjectFilter {
+    fn to_subject(&self) -> async_nats::Subject {
+        self.to_subject_string().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, subject, SubjectFilter, SubjectParseError};
+    use crate::message::{Id, Tag};
+
+    #[test]
+    pub fn test_subject_roundtrip() {
+        for tags in [
+            vec![Tag::Pointcloud, Tag::FromId(Id::Device(1, 0))],
+            vec![Tag::Pointcloud, Tag::FromId(Id::Machine(3))],
+            vec![Tag::Pointcloud],
+            vec![Tag::FromId(Id::Device(12, 7))],
+            vec![Tag::Heartbeat, Tag::FromId(Id::Machine(2))],
+            vec![Tag::Event, Tag::FromId(Id::Device(2, 3))],
+            vec![Tag::Fused, Tag::FromId(Id::Device(4, 1))],
+            vec![Tag::Clusters, Tag::FromId(Id::Device(4, 2))],
+            vec![Tag::Occupancy, Tag::FromId(Id::Device(4, 3))],
+            vec![Tag::Vitals, Tag::FromId(Id::Device(1, 1))],
+            vec![Tag::Predictions, Tag::FromId(Id::Device(4, 4))],
+            vec![Tag::Pose, Tag::FromId(Id::Device(5, 0))],
+            vec![Tag::Filtered, Tag::FromId(Id::Device(4, 5))],
+        ] {
+            assert_eq!(parse(&subject(&tags)).unwrap(), tags);
+        }
+        assert_eq!(
+            subject(&[Tag::FromId(Id::Device(1, 2)), Tag::Pointcloud]),
+            "mmwave.1.2.pointcloud"
+        );
+        assert_eq!(
+            subject(&[Tag::Pointcloud, Tag::FromId(Id::Machine(4))]),
+            "mmwave.4._.pointcloud"
+        );
+    }
+
+    #[test]
+    pub fn test_parse_invalid() {
+        assert!(matches!(
+            parse("Pointcloud.FromId(1:0)"),
+            Err(SubjectParseError::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            parse("mmwave.1.x.pointcloud"),
+            Err(SubjectParseError::InvalidId(_))
+        ));
+        assert!(matches!(
+            parse("mmwave.1.0.teapot"),
+            Err(SubjectParseError::UnknownKind(_))
+        ));
+    }
+
+    #[test]
+    pub fn test_filters() {
+        assert_eq!(SubjectFilter::all().to_subject_string(), "mmwave.*.*.*");
+        assert_eq!(
+            SubjectFilter::kind(Tag::Pointcloud).to_subject_string(),
+            "mmwave.*.*.pointcloud"
+        );
+        assert_eq!(
+            SubjectFilter::all().machine(2).to_subject_string(),
+            "mmwave.2.*.*"
+        );
+        assert_eq!(
+            SubjectFilter::kind(Tag::Pointcloud)
+                .id(Id::Device(2, 1))
+                .to_subject_string(),
+            "mmwave.2.1.pointcloud"
+        );
+    }
+}
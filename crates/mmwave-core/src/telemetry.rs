@@ -0,0 +1,213 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{message::Id, transform::Quaternion};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeviceState {
+    Starting,
+    Running,
+    Degraded, // Running, but with recoverable errors (e.g. dropped frames)
+    Stopped,
+    Error,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DeviceStatus {
+    pub state: DeviceState,
+    pub title: String,           // Title of the device descriptor
+    pub detail: Option<String>,  // Human readable reason for the state
+    pub frame_rate: Option<f32>, // Frames published per second, if applicable
+}
+
+/// Sent periodically by every machine so consumers can tell it is alive
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Heartbeat {
+    pub sequence: u64,
+    pub uptime: Duration,
+    pub devices: usize, // Number of devices running on the machine
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+    Critical,
+}
+
+/// What happened. New kinds of events should be appended to the end.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum EventKind {
+    /// A device ran into an error it may or may not recover from
+    DeviceError,
+    /// Anything not covered by a dedicated kind, named for filtering
+    Custom(String),
+    /// Something entered a zone. The object is the track id and the source
+    /// the device the track was observed by, None when the zone is monitored
+    /// with points (and the source None too if the track was fused)
+    ZoneEntered {
+        zone: String,
+        object: Option<u64>,
+        source: Option<Id>,
+    },
+    /// Something left a zone after the given number of seconds inside
+    ZoneExited {
+        zone: String,
+        object: Option<u64>,
+        source: Option<Id>,
+        duration: f32,
+    },
+    /// Something has stayed in a zone for its dwell time
+    ZoneDwell {
+        zone: String,
+        object: Option<u64>,
+        source: Option<Id>,
+        duration: f32,
+    },
+    /// The number of occupants of a zone changed
+    ZoneOccupancy { zone: String, count: u32 },
+    /// The posture of a tracked object (or skeleton) changed. The source is
+    /// the device the object was observed by, or the tracker if it was fused
+    PostureChanged {
+        object: u64,
+        source: Option<Id>,
+        posture: Posture,
+    },
+    /// A tracked object (or skeleton) fell and is lying down
+    Fall { object: u64, source: Option<Id> },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Posture {
+    Standing,
+    Sitting,
+    Lying,
+}
+
+/// Something noteworthy that happened at a point in time. Events with a
+/// severity of warning or above are alerts and should be surfaced to users.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Event {
+    pub kind: EventKind,
+    pub severity: Severity,
+    pub description: String,
+    pub confidence: Option<f32>, // 0 to 1, for events that are inferred
+    pub position: Option<[f32; 3]>, // World frame position the event refers to
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TrackState {
+    Tentative, // Not seen for long enough to be trusted
+    Confirmed,
+    Coasting, // Temporarily lost, predicted forward
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TrackedObject {
+    pub id: u64, // Stable for the lifetime of the track
+    pub state: TrackState,
+    pub position: [f32; 3],
+    pub velocity: [f32; 3],
+    pub covariance: Vec<f32>, // Row major 6x6 over position then velocity, empty if unknown
+    pub source: Option<Id>,   // Device the track was observed by, None if fused
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct TrackedObjects {
+    pub objects: Vec<TrackedObject>,
+    pub source: Option<Id>, // Device the tracks were observed by, None if fused
+}
+
+/// A group of points presumed to belong to one object
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Cluster {
+    pub id: u32, // Index within the frame, not stable between frames
+    pub centroid: [f32; 3],
+    pub min: [f32; 3], // Corners of the axis aligned bounding box
+    pub max: [f32; 3],
+    pub points: u32,
+    pub velocity: f32, // Mean radial velocity of the points
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Clusters {
+    pub time: DateTime<Utc>, // Time of the pointcloud that was clustered
+    pub source: Option<Id>,  // Device whose pointcloud was clustered
+    pub clusters: Vec<Cluster>,
+}
+
+/// How much has been seen in each cell of a world frame grid over time,
+/// with x varying fastest, then y, then z.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct OccupancyGrid {
+    pub time: DateTime<Utc>, // Time of the last observation added
+    pub origin: [f32; 3],    // World frame position of the minimum corner
+    pub cell_size: [f32; 3], // Edge lengths of a cell along x, y and z in metres
+    pub shape: [u32; 3],     // Cells along x, y and z
+    pub values: Vec<f32>,
+}
+
+/// Breathing and heart rate of a stationary person, as reported by the
+/// vital signs firmware of an AWR
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VitalSign {
+    pub id: u32,                // Target the estimate is for
+    pub range_bin: u32,         // Range bin the firmware found the target in
+    pub breathing_rate: f32,    // Breaths per minute
+    pub heart_rate: f32,        // Beats per minute
+    pub breathing_quality: f32, // 0 (unusable) to 1 (clean)
+    pub heart_quality: f32,     // 0 (unusable) to 1 (clean)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct VitalSigns {
+    pub time: DateTime<Utc>,
+    pub targets: Vec<VitalSign>,
+}
+
+/// How strongly a classifier believes a window shows something
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Score {
+    pub label: String,
+    pub score: f32,
+}
+
+/// What a classifier made of the latest window of pointclouds from a device
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Predictions {
+    pub time: DateTime<Utc>, // Time of the newest pointcloud in the window
+    pub source: Option<Id>,  // Device whose pointclouds were classified
+    pub classifier: String,  // Name of the classifier
+    pub scores: Vec<Score>,  // Best first
+}
+
+/// Where a moving platform (e.g. a robot) is in its parent frame, from
+/// odometry or localisation
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Pose {
+    pub time: DateTime<Utc>,
+    pub position: [f32; 3],
+    pub orientation: Quaternion,            // Platform into parent frame
+    pub velocity: Option<[f32; 3]>,         // Parent frame, m/s
+    pub angular_velocity: Option<[f32; 3]>, // Parent frame, rad/s
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SpectrumKind {
+    RangeProfile,
+    NoiseProfile,
+    AzimuthHeatmap,
+    RangeDopplerHeatmap,
+}
+
+/// Dense per-bin data from a sensor, row major with the given shape
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Spectrum {
+    pub kind: SpectrumKind,
+    pub shape: [u32; 2], // rows, columns (1 row for profiles)
+    pub resolution: f32, // Size of a column in its physical unit, 0 if unknown
+    pub values: Vec<f32>,
+}
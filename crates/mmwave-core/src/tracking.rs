@@ -0,0 +1,360 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    message::Id,
+    telemetry::{TrackState, TrackedObject, TrackedObjects},
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct TrackerParams {
+    pub process_noise: f32, // Standard deviation of unmodelled acceleration in m/s^2
+    pub measurement_noise: f32, // Standard deviation of detected positions in m
+    pub gate: f32,          // Largest squared mahalanobis distance of an association
+    pub confirm_hits: u32,  // Detections a tentative track needs to be confirmed
+    pub max_misses: u32,    // Frames a confirmed track may coast for before deletion
+}
+
+impl Default for TrackerParams {
+    fn default() -> Self {
+        Self {
+            process_noise: 2.0,
+            measurement_noise: 0.2,
+            gate: 11.34, // 99% of a chi squared distribution with 3 degrees of freedom
+            confirm_hits: 3,
+            max_misses: 10,
+        }
+    }
+}
+
+/// A constant velocity kalman filter. The axes are independent, so each
+/// keeps its own position and velocity along with their 2x2 covariance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Track {
+    pub id: u64,
+    pub state: TrackState,
+    pub position: [f32; 3],
+    pub velocity: [f32; 3],
+    covariance: [[[f32; 2]; 2]; 3], // Per axis, over position then velocity
+    hits: u32,
+    misses: u32,
+}
+
+impl Track {
+    fn new(id: u64, position: [f32; 3], params: &TrackerParams) -> Self {
+        // Nothing is known about the velocity of a new track, beyond it being a person or so
+        let r = params.measurement_noise.powi(2);
+        Track {
+            id,
+            state: TrackState::Tentative,
+            position,
+            velocity: [0.0; 3],
+            covariance: [[[r, 0.0], [0.0, 4.0]]; 3],
+            hits: 1,
+            misses: 0,
+        }
+    }
+
+    fn predict(&mut self, dt: f32, params: &TrackerParams) {
+        // Discretised white noise acceleration
+        let q = params.process_noise.powi(2);
+        let (dt2, dt3, dt4) = (dt * dt, dt * dt * dt, dt * dt * dt * dt);
+        for k in 0..3 {
+            self.position[k] += self.velocity[k] * dt;
+            let [[pp, pv], [_, vv]] = self.covariance[k];
+            let pp = pp + 2.0 * dt * pv + dt2 * vv + q * dt4 / 4.0;
+            let pv = pv + dt * vv + q * dt3 / 2.0;
+            let vv = vv + q * dt2;
+            self.covariance[k] = [[pp, pv], [pv, vv]];
+        }
+    }
+
+    /// Squared mahalanobis distance from the predicted position to a detection
+    fn distance(&self, detection: [f32; 3], params: &TrackerParams) -> f32 {
+        let r = params.measurement_noise.powi(2);
+        (0..3)
+            .map(|k| (detection[k] - self.position[k]).powi(2) / (self.covariance[k][0][0] + r))
+            .sum()
+    }
+
+    fn update(&mut self, detection: [f32; 3], params: &TrackerParams) {
+        let r = params.measurement_noise.powi(2);
+        for (k, z) in detection.into_iter().enumerate() {
+            let [[pp, pv], [_, vv]] = self.covariance[k];
+            let s = pp + r;
+            let (gain_p, gain_v) = (pp / s, pv / s);
+            let innovation = z - self.position[k];
+            self.position[k] += gain_p * innovation;
+            self.velocity[k] += gain_v * innovation;
+            self.covariance[k] = [
+                [(1.0 - gain_p) * pp, (1.0 - gain_p) * pv],
+                [(1.0 - gain_p) * pv, vv - gain_v * pv],
+            ];
+        }
+        self.hits += 1;
+        self.misses = 0;
+        if self.state == TrackState::Coasting
+            || (self.state == TrackState::Tentative && self.hits >= params.confirm_hits)
+        {
+            self.state = TrackState::Confirmed;
+        }
+    }
+
+    /// Whether the track should be kept after missing a frame
+    fn miss(&mut self, params: &TrackerParams) -> bool {
+        self.misses += 1;
+        match self.state {
+            TrackState::Tentative => false,
+            _ => {
+                self.state = TrackState::Coasting;
+                self.misses <= params.max_misses
+            }
+        }
+    }
+
+    /// Row major 6x6 covariance over position then velocity
+    pub fn covariance(&self) -> Vec<f32> {
+        let mut covariance = vec![0.0; 36];
+        for (k, [[pp, pv], [vp, vv]]) in self.covariance.into_iter().enumerate() {
+            covariance[k * 6 + k] = pp;
+            covariance[k * 6 + k + 3] = pv;
+            covariance[(k + 3) * 6 + k] = vp;
+            covariance[(k + 3) * 6 + k + 3] = vv;
+        }
+        covariance
+    }
+}
+
+/// Tracks objects across frames of detections from a single stream
+#[derive(Debug, Clone)]
+pub struct Tracker {
+    params: TrackerParams,
+    tracks: Vec<Track>,
+    next_id: u64,
+    time: Option<DateTime<Utc>>,
+}
+
+impl Tracker {
+    pub fn new(params: TrackerParams) -> Self {
+        Tracker {
+            params,
+            tracks: Vec::new(),
+            next_id: 0,
+            time: None,
+        }
+    }
+
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    /// Advances the tracks to time and associates them with the detections
+    /// made then. Frames older than the last one are ignored.
+    pub fn update(&mut self, time: DateTime<Utc>, detections: &[[f32; 3]]) {
+        if let Some(previous) = self.time {
+            if time < previous {
+                return;
+            }
+            let dt = (time - previous).num_microseconds().unwrap_or(0) as f32 / 1e6;
+            for track in self.tracks.iter_mut() {
+                track.predict(dt, &self.params);
+            }
+        }
+        self.time = Some(time);
+
+        // Pairs outside the gate all cost the same, so they cannot outweigh
+        // the pairs inside it when minimising the total
+        let outside = self.params.gate * 2.0;
+        let costs: Vec<Vec<f32>> = self
+            .tracks
+            .iter()
+            .map(|track| {
+                detections
+                    .iter()
+                    .map(|&detection| track.distance(detection, &self.params).min(outside))
+                    .collect()
+            })
+            .collect();
+        let mut detected = vec![false; detections.len()];
+        let mut keep = vec![true; self.tracks.len()];
+        for (i, assignment) in assign(&costs).into_iter().enumerate() {
+            match assignment.filter(|&j| costs[i][j] <= self.params.gate) {
+                Some(j) => {
+                    self.tracks[i].update(detections[j], &self.params);
+                    detected[j] = true;
+                }
+                None => keep[i] = self.tracks[i].miss(&self.params),
+            }
+        }
+        let mut keep = keep.into_iter();
+        self.tracks.retain(|_| keep.next().unwrap_or(true));
+
+        for (&detection, _) in detections.iter().zip(detected).filter(|(_, d)| !d) {
+            self.tracks
+                .push(Track::new(self.next_id, detection, &self.params));
+            self.next_id += 1;
+        }
+    }
+
+    /// The current tracks, as published
+    pub fn tracked_objects(&self, source: Option<Id>) -> TrackedObjects {
+        TrackedObjects {
+            objects: self
+                .tracks
+                .iter()
+                .map(|track| TrackedObject {
+                    id: track.id,
+                    state: track.state,
+                    position: track.position,
+                    velocity: track.velocity,
+                    covariance: track.covariance(),
+                    source,
+                })
+                .collect(),
+            source,
+        }
+    }
+}
+
+/// Assigns each row to a distinct column minimising the total cost with the
+/// hungarian algorithm. Rows left without a column (when there are more rows
+/// than columns) are None.
+pub fn assign(costs: &[Vec<f32>]) -> Vec<Option<usize>> {
+    let rows = costs.len();
+    let columns = costs.first().map_or(0, |row| row.len());
+    if rows == 0 || columns == 0 {
+        return vec![None; rows];
+    }
+    if rows > columns {
+        // The algorithm needs at least as many columns as rows
+        let transposed: Vec<Vec<f32>> = (0..columns)
+            .map(|j| (0..rows).map(|i| costs[i][j]).collect())
+            .collect();
+        let mut assignment = vec![None; rows];
+        for (j, i) in assign(&transposed).into_iter().enumerate() {
+            if let Some(i) = i {
+                assignment[i] = Some(j);
+            }
+        }
+        return assignment;
+    }
+
+    // Potentials and matching are 1 indexed, with 0 as a sentinel
+    let cost = |i: usize, j: usize| costs[i - 1][j - 1] as f64;
+    let (mut u, mut v) = (vec![0.0; rows + 1], vec![0.0; columns + 1]);
+    let mut matched = vec![0; columns + 1]; // Row matched to each column
+    let mut way = vec![0; columns + 1];
+    for i in 1..=rows {
+        matched[0] = i;
+        let mut j0 = 0;
+        let mut min = vec![f64::INFINITY; columns + 1];
+        let mut used = vec![false; columns + 1];
+        loop {
+            used[j0] = true;
+            let i0 = matched[j0];
+            let (mut delta, mut j1) = (f64::INFINITY, 0);
+            for j in 1..=columns {
+                if used[j] {
+                    continue;
+                }
+                let reduced = cost(i0, j) - u[i0] - v[j];
+                if reduced < min[j] {
+                    min[j] = reduced;
+                    way[j] = j0;
+                }
+                if min[j] < delta {
+                    delta = min[j];
+                    j1 = j;
+                }
+            }
+            for j in 0..=columns {
+                if used[j] {
+                    u[matched[j]] += delta;
+                    v[j] -= delta;
+                } else {
+                    min[j] -= delta;
+                }
+            }
+            j0 = j1;
+            if matched[j0] == 0 {
+                break;
+            }
+        }
+        while j0 != 0 {
+            let j1 = way[j0];
+            matched[j0] = matched[j1];
+            j0 = j1;
+        }
+    }
+
+    let mut assignment = vec![None; rows];
+    for j in 1..=columns {
+        if matched[j] != 0 {
+            assignment[matched[j] - 1] = Some(j - 1);
+        }
+    }
+    assignment
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{assign, Tracker, TrackerParams};
+    use crate::telemetry::TrackState;
+
+    #[test]
+    pub fn test_assign() {
+        let costs = vec![
+            vec![4.0, 1.0, 3.0],
+            vec![2.0, 0.0, 5.0],
+            vec![3.0, 2.0, 2.0],
+        ];
+        assert_eq!(assign(&costs), vec![Some(1), Some(0), Some(2)]);
+        let costs = vec![vec![1.0], vec![0.5]];
+        assert_eq!(assign(&costs), vec![None, Some(0)]);
+        assert_eq!(assign(&[]), vec![]);
+    }
+
+    #[test]
+    pub fn test_tracks_crossing_targets() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let mut tracker = Tracker::new(TrackerParams::default());
+        // Two people walking towards each other along x, 1m apart in y
+        for frame in 0..20 {
+            let t = frame as f32 * 0.1;
+            tracker.update(
+                start + Duration::milliseconds(frame * 100),
+                &[[-2.0 + t, 2.0, 0.0], [2.0 - t, 3.0, 0.0]],
+            );
+        }
+        let tracks = tracker.tracks();
+        assert_eq!(tracks.len(), 2);
+        assert!(tracks.iter().all(|t| t.state == TrackState::Confirmed));
+        let first = tracks.iter().find(|t| t.id == 0).unwrap();
+        assert!((first.velocity[0] - 1.0).abs() < 0.1);
+        assert!((first.position[1] - 2.0).abs() < 0.1);
+
+        // Once they disappear, the tracks coast and are eventually deleted
+        for frame in 20..40 {
+            tracker.update(start + Duration::milliseconds(frame * 100), &[]);
+            if frame == 20 {
+                assert!(tracker
+                    .tracks()
+                    .iter()
+                    .all(|t| t.state == TrackState::Coasting));
+            }
+        }
+        assert!(tracker.tracks().is_empty());
+    }
+
+    #[test]
+    pub fn test_tentative_tracks_need_confirmation() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let mut tracker = Tracker::new(TrackerParams::default());
+        tracker.update(start, &[[0.0, 3.0, 0.0]]);
+        assert_eq!(tracker.tracks()[0].state, TrackState::Tentative);
+        tracker.update(start + Duration::milliseconds(100), &[]);
+        assert!(tracker.tracks().is_empty());
+    }
+}
@@ -1,25 +1,239 @@
-use std::fmt::Display;
+use std::{f32::consts::FRAC_PI_2, fmt::Display, ops::Mul};
 
 use egui::Ui;
-use ndarray::array;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
+use crate::{point::Point, pointcloud::PointCloud};
+
+/// A rigid transform from a sensor's frame into its parent frame. The rotation
+/// is yaw about z, then pitch about x, then roll about y (the sensor's
+/// boresight), i.e. `R = Rz(yaw) * Rx(pitch) * Ry(roll)`, applied before the
+/// translation.
 #[derive(PartialEq, Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Transform {
     pub translation: [f32; 3], // Translation of the camera in meters, x, y, z
-    pub orientation: [f32; 2], // Orientation of the camera in radians, yaw, pitch
+    #[serde(deserialize_with = "deserialize_orientation")]
+    pub orientation: [f32; 3], // Orientation of the camera in radians, yaw, pitch, roll
+}
+
+/// Configs written before roll was supported only have yaw and pitch
+fn deserialize_orientation<'de, D>(deserializer: D) -> Result<[f32; 3], D::Error>
+where
+    D: Deserializer<'de>,
+{
+    if !deserializer.is_human_readable() {
+        return <[f32; 3]>::deserialize(deserializer);
+    }
+    match Vec::<f32>::deserialize(deserializer)?.as_slice() {
+        &[yaw, pitch] => Ok([yaw, pitch, 0.0]),
+        &[yaw, pitch, roll] => Ok([yaw, pitch, roll]),
+        other => Err(serde::de::Error::invalid_length(
+            other.len(),
+            &"an orientation of [yaw, pitch] or [yaw, pitch, roll]",
+        )),
+    }
 }
 
 impl Display for Transform {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let Self {
             translation: [x, y, z],
-            orientation: [yaw, pitch],
+            orientation: [yaw, pitch, roll],
         } = self;
-        write! {f, "(x({}), y({}), z({}), yaw({}), pitch({}))", x,y,z,yaw,pitch}
+        write! {f, "(x({}), y({}), z({}), yaw({}), pitch({}), roll({}))", x,y,z,yaw,pitch,roll}
+    }
+}
+
+/// A unit quaternion, w + xi + yj + zk
+#[derive(PartialEq, Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Quaternion {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Default for Quaternion {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Quaternion {
+    pub const IDENTITY: Quaternion = Quaternion {
+        w: 1.0,
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+
+    /// Rotation of angle radians about a unit axis
+    pub fn from_axis_angle(axis: [f32; 3], angle: f32) -> Self {
+        let (sin, cos) = (angle / 2.0).sin_cos();
+        Self {
+            w: cos,
+            x: axis[0] * sin,
+            y: axis[1] * sin,
+            z: axis[2] * sin,
+        }
+    }
+
+    /// Same convention as `Transform::orientation`
+    pub fn from_euler([yaw, pitch, roll]: [f32; 3]) -> Self {
+        Self::from_axis_angle([0.0, 0.0, 1.0], yaw)
+            * Self::from_axis_angle([1.0, 0.0, 0.0], pitch)
+            * Self::from_axis_angle([0.0, 1.0, 0.0], roll)
+    }
+
+    pub fn to_euler(self) -> [f32; 3] {
+        matrix_to_euler(&self.to_matrix())
+    }
+
+    pub fn conjugate(self) -> Self {
+        Self {
+            w: self.w,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+
+    pub fn normalized(self) -> Self {
+        let norm = (self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z).sqrt();
+        if norm == 0.0 {
+            return Self::IDENTITY;
+        }
+        Self {
+            w: self.w / norm,
+            x: self.x / norm,
+            y: self.y / norm,
+            z: self.z / norm,
+        }
+    }
+
+    pub fn to_matrix(self) -> [[f32; 3]; 3] {
+        let Self { w, x, y, z } = self.normalized();
+        [
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - w * z),
+                2.0 * (x * z + w * y),
+            ],
+            [
+                2.0 * (x * y + w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - w * x),
+            ],
+            [
+                2.0 * (x * z - w * y),
+                2.0 * (y * z + w * x),
+                1.0 - 2.0 * (x * x + y * y),
+            ],
+        ]
+    }
+
+    pub fn rotate(self, point: [f32; 3]) -> [f32; 3] {
+        mat_vec(&self.to_matrix(), point)
+    }
+
+    /// Spherical interpolation from self (t = 0) to other (t = 1), along the
+    /// shorter arc
+    pub fn slerp(self, other: Self, t: f32) -> Self {
+        let (a, mut b) = (self.normalized(), other.normalized());
+        let mut dot = a.w * b.w + a.x * b.x + a.y * b.y + a.z * b.z;
+        if dot < 0.0 {
+            b = Self {
+                w: -b.w,
+                x: -b.x,
+                y: -b.y,
+                z: -b.z,
+            };
+            dot = -dot;
+        }
+        // Nearly the same rotation, where lerping is as good and stable
+        let (ka, kb) = if dot > 0.9995 {
+            (1.0 - t, t)
+        } else {
+            let theta = dot.min(1.0).acos();
+            let sin = theta.sin();
+            (((1.0 - t) * theta).sin() / sin, (t * theta).sin() / sin)
+        };
+        Self {
+            w: ka * a.w + kb * b.w,
+            x: ka * a.x + kb * b.x,
+            y: ka * a.y + kb * b.y,
+            z: ka * a.z + kb * b.z,
+        }
+        .normalized()
     }
 }
 
+impl Mul for Quaternion {
+    type Output = Quaternion;
+
+    /// Hamilton product, `a * b` rotates by b and then by a
+    fn mul(self, rhs: Self) -> Self {
+        let (a, b) = (self, rhs);
+        Self {
+            w: a.w * b.w - a.x * b.x - a.y * b.y - a.z * b.z,
+            x: a.w * b.x + a.x * b.w + a.y * b.z - a.z * b.y,
+            y: a.w * b.y - a.x * b.z + a.y * b.w + a.z * b.x,
+            z: a.w * b.z + a.x * b.y - a.y * b.x + a.z * b.w,
+        }
+    }
+}
+
+fn mat_vec(m: &[[f32; 3]; 3], v: [f32; 3]) -> [f32; 3] {
+    [
+        m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+        m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+        m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2],
+    ]
+}
+
+fn transpose(m: &[[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    [
+        [m[0][0], m[1][0], m[2][0]],
+        [m[0][1], m[1][1], m[2][1]],
+        [m[0][2], m[1][2], m[2][2]],
+    ]
+}
+
+// Vector arithmetic on points and directions, shared by the modules that
+// work with planes and motion
+
+pub(crate) fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+pub(crate) fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+pub(crate) fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+pub(crate) fn norm(v: [f32; 3]) -> f32 {
+    dot(v, v).sqrt()
+}
+
+/// Recovers [yaw, pitch, roll] from `Rz(yaw) * Rx(pitch) * Ry(roll)`. At
+/// +-90 degrees of pitch yaw and roll are indistinguishable, so roll is 0.
+fn matrix_to_euler(m: &[[f32; 3]; 3]) -> [f32; 3] {
+    let pitch = m[2][1].clamp(-1.0, 1.0).asin();
+    if (FRAC_PI_2 - pitch.abs()) < 1e-4 {
+        return [m[1][0].atan2(m[0][0]), pitch, 0.0];
+    }
+    let yaw = (-m[0][1]).atan2(m[1][1]);
+    let roll = (-m[2][0]).atan2(m[2][2]);
+    [yaw, pitch, roll]
+}
+
 impl Transform {
     pub fn ui(&mut self, ui: &mut Ui) {
         ui.horizontal(|ui| {
@@ -30,87 +244,167 @@ impl Transform {
         });
         ui.horizontal(|ui| {
             ui.label("Orientation");
-            for i in 0..2 {
-                ui.drag_angle(&mut self.orientation[i]);
+            for (i, name) in ["yaw", "pitch", "roll"].into_iter().enumerate() {
+                ui.drag_angle(&mut self.orientation[i]).on_hover_text(name);
             }
         });
     }
 
+    pub fn from_quaternion(translation: [f32; 3], rotation: Quaternion) -> Self {
+        Self {
+            translation,
+            orientation: rotation.to_euler(),
+        }
+    }
+
+    pub fn quaternion(&self) -> Quaternion {
+        Quaternion::from_euler(self.orientation)
+    }
+
+    pub fn rotation_matrix(&self) -> [[f32; 3]; 3] {
+        let [yaw, pitch, roll] = self.orientation;
+        let (sy, cy) = yaw.sin_cos();
+        let (sp, cp) = pitch.sin_cos();
+        let (sr, cr) = roll.sin_cos();
+        [
+            [cy * cr - sy * sp * sr, -sy * cp, cy * sr + sy * sp * cr],
+            [sy * cr + cy * sp * sr, cy * cp, sy * sr - cy * sp * cr],
+            [-cp * sr, sp, cp * cr],
+        ]
+    }
+
+    /// Precomputes the rotation, use this when transforming many points
+    pub fn matrix(&self) -> TransformMatrix {
+        TransformMatrix {
+            rotation: self.rotation_matrix(),
+            translation: self.translation,
+        }
+    }
+
     pub fn apply(&self, point: [f32; 3]) -> [f32; 3] {
-        let yaw = self.orientation[0];
-        let pitch = self.orientation[1];
-
-        // Yaw rotation matrix (rotate around Z-axis)
-        let yaw_matrix = array![
-            [yaw.cos(), -yaw.sin(), 0.0],
-            [yaw.sin(), yaw.cos(), 0.0],
-            [0.0, 0.0, 1.0]
-        ];
-        // Pitch rotation matrix (rotate around X-axis)
-        let pitch_matrix = array![
-            [1.0, 0.0, 0.0],
-            [0.0, pitch.cos(), -pitch.sin()],
-            [0.0, pitch.sin(), pitch.cos()]
-        ];
+        self.matrix().apply(point)
+    }
+
+    pub fn unapply(&self, point: [f32; 3]) -> [f32; 3] {
+        self.matrix().unapply(point)
+    }
+
+    /// Transforms every point of the cloud in place, leaving velocities as is.
+    /// Sensor origins are moved too, so radial velocities can still be resolved.
+    pub fn apply_to_cloud(&self, pointcloud: &mut PointCloud) {
+        self.matrix().apply_to_cloud(pointcloud);
+    }
+
+    /// The transform that applies `other` first and then `self`, e.g.
+    /// `world_from_mount.compose(&mount_from_sensor)` is `world_from_sensor`
+    pub fn compose(&self, other: &Transform) -> Transform {
+        self.matrix().compose(&other.matrix()).into()
+    }
+
+    /// The transform that undoes this one, `apply` of the inverse is `unapply`
+    pub fn inverse(&self) -> Transform {
+        self.matrix().inverse().into()
+    }
+}
+
+/// A `Transform` with its rotation matrix precomputed, so applying it is a
+/// handful of multiply-adds per point.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct TransformMatrix {
+    pub rotation: [[f32; 3]; 3], // Row major
+    pub translation: [f32; 3],
+}
+
+impl Default for TransformMatrix {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
 
-        // Combine rotations (first yaw, then pitch)
-        let combined_rotation = yaw_matrix.dot(&pitch_matrix);
+impl From<&Transform> for TransformMatrix {
+    fn from(transform: &Transform) -> Self {
+        transform.matrix()
+    }
+}
 
-        // Apply rotation
-        let rotated_point = combined_rotation.dot(&array![point[0], point[1], point[2]]);
+impl From<TransformMatrix> for Transform {
+    fn from(matrix: TransformMatrix) -> Self {
+        Transform {
+            translation: matrix.translation,
+            orientation: matrix_to_euler(&matrix.rotation),
+        }
+    }
+}
 
-        // Apply translation
-        let translated_point = array![rotated_point[0], rotated_point[1], rotated_point[2]]
-            + array![
-                self.translation[0],
-                self.translation[1],
-                self.translation[2]
-            ];
+impl TransformMatrix {
+    pub const IDENTITY: TransformMatrix = TransformMatrix {
+        rotation: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        translation: [0.0, 0.0, 0.0],
+    };
 
-        translated_point
-            .to_owned()
-            .into_raw_vec()
-            .try_into()
-            .unwrap_or_else(|_| [0.0, 0.0, 0.0])
+    #[inline]
+    pub fn apply(&self, point: [f32; 3]) -> [f32; 3] {
+        let [x, y, z] = mat_vec(&self.rotation, point);
+        [
+            x + self.translation[0],
+            y + self.translation[1],
+            z + self.translation[2],
+        ]
     }
 
+    #[inline]
     pub fn unapply(&self, point: [f32; 3]) -> [f32; 3] {
-        let yaw = self.orientation[0];
-        let pitch = self.orientation[1];
-
-        // Inverse yaw rotation matrix (rotate around Z-axis)
-        let inverse_yaw_matrix = array![
-            [yaw.cos(), yaw.sin(), 0.0],
-            [-yaw.sin(), yaw.cos(), 0.0],
-            [0.0, 0.0, 1.0]
-        ];
-        // Inverse pitch rotation matrix (rotate around X-axis)
-        let inverse_pitch_matrix = array![
-            [1.0, 0.0, 0.0],
-            [0.0, pitch.cos(), pitch.sin()],
-            [0.0, -pitch.sin(), pitch.cos()]
+        let translated = [
+            point[0] - self.translation[0],
+            point[1] - self.translation[1],
+            point[2] - self.translation[2],
         ];
+        mat_vec(&transpose(&self.rotation), translated)
+    }
 
-        let combined_rotation = inverse_pitch_matrix.dot(&inverse_yaw_matrix);
+    /// Transforms the points in place, leaving velocities as is
+    pub fn apply_to_points(&self, points: &mut [Point]) {
+        let [[r00, r01, r02], [r10, r11, r12], [r20, r21, r22]] = self.rotation;
+        let [tx, ty, tz] = self.translation;
+        for point in points.iter_mut() {
+            let Point { x, y, z, .. } = *point;
+            point.x = r00 * x + r01 * y + r02 * z + tx;
+            point.y = r10 * x + r11 * y + r12 * z + ty;
+            point.z = r20 * x + r21 * y + r22 * z + tz;
+        }
+    }
 
-        let translated_point = array![point[0], point[1], point[2]]
-            - array![
-                self.translation[0],
-                self.translation[1],
-                self.translation[2]
-            ];
+    /// Transforms the points and sensor origins of the cloud in place
+    pub fn apply_to_cloud(&self, pointcloud: &mut PointCloud) {
+        self.apply_to_points(&mut pointcloud.points);
+        for origin in pointcloud.origins.iter_mut() {
+            origin.position = self.apply(origin.position);
+        }
+    }
 
-        let rotated_point = combined_rotation.dot(&array![
-            translated_point[0],
-            translated_point[1],
-            translated_point[2]
-        ]);
+    /// The transform that applies `other` first and then `self`
+    pub fn compose(&self, other: &TransformMatrix) -> TransformMatrix {
+        let mut rotation = [[0.0; 3]; 3];
+        for (i, row) in rotation.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..3)
+                    .map(|k| self.rotation[i][k] * other.rotation[k][j])
+                    .sum();
+            }
+        }
+        TransformMatrix {
+            rotation,
+            translation: self.apply(other.translation),
+        }
+    }
 
-        rotated_point
-            .to_owned()
-            .into_raw_vec()
-            .try_into()
-            .unwrap_or_else(|_| [0.0, 0.0, 0.0])
+    pub fn inverse(&self) -> TransformMatrix {
+        let rotation = transpose(&self.rotation);
+        let [x, y, z] = mat_vec(&rotation, self.translation);
+        TransformMatrix {
+            rotation,
+            translation: [-x, -y, -z],
+        }
     }
 }
 
@@ -118,7 +412,8 @@ impl Transform {
 mod tests {
     use std::f32::consts::PI;
 
-    use super::Transform;
+    use super::{Quaternion, Transform};
+    use crate::pointcloud::PointCloud;
 
     fn are_close(v1: [f32; 3], v2: [f32; 3], tol: f32) -> bool {
         v1.iter().zip(v2.iter()).all(|(a, b)| (a - b).abs() < tol)
@@ -129,7 +424,7 @@ mod tests {
         let point = [1., 2., 3.];
         let transform = Transform {
             translation: [0., 0., 0.],
-            orientation: [0., 0.],
+            orientation: [0., 0., 0.],
         };
         let transformed_point = transform.apply(point);
         assert!(are_close(transformed_point, point, 1e-6));
@@ -140,7 +435,7 @@ mod tests {
         let point = [1., 2., 3.];
         let transform = Transform {
             translation: [0., 0., 0.],
-            orientation: [0., 0.],
+            orientation: [0., 0., 0.],
         };
         let transformed_point = transform.unapply(point);
         assert!(are_close(transformed_point, point, 1e-6));
@@ -151,7 +446,7 @@ mod tests {
         let point = [1., 0., 0.];
         let transform = Transform {
             translation: [0., 0., 0.],
-            orientation: [PI / 2., 0.],
+            orientation: [PI / 2., 0., 0.],
         };
         let transformed_point = transform.apply(point);
         assert!(are_close(transformed_point, [0., 1., 0.], 1e-6));
@@ -162,7 +457,7 @@ mod tests {
         let point = [0., 1., 0.];
         let transform = Transform {
             translation: [0., 0., 0.],
-            orientation: [PI / 2., 0.],
+            orientation: [PI / 2., 0., 0.],
         };
         let transformed_point = transform.unapply(point);
         assert!(are_close(transformed_point, [1., 0., 0.], 1e-6));
@@ -173,7 +468,7 @@ mod tests {
         let point = [1., 0., 0.];
         let transform = Transform {
             translation: [-1., 1., 0.],
-            orientation: [0., 0.],
+            orientation: [0., 0., 0.],
         };
         let transformed_point = transform.apply(point);
         assert!(are_close(transformed_point, [0., 1., 0.], 1e-6));
@@ -184,7 +479,7 @@ mod tests {
         let point = [0., 1., 0.];
         let transform = Transform {
             translation: [-1., 1., 0.],
-            orientation: [0., 0.],
+            orientation: [0., 0., 0.],
         };
         let transformed_point = transform.unapply(point);
         dbg!(transformed_point);
@@ -197,7 +492,7 @@ mod tests {
 
         let transform = Transform {
             translation: [1.0, 0.0, 0.0],
-            orientation: [PI / 4., PI / 6.],
+            orientation: [PI / 4., PI / 6., 0.],
         };
 
         let transformed_point = transform.apply(original_point);
@@ -211,4 +506,116 @@ mod tests {
             original_point
         );
     }
+
+    #[test]
+    pub fn test_roll() {
+        // Roll is about the sensor's boresight (y), applied before yaw and pitch
+        let transform = Transform {
+            translation: [0., 0., 0.],
+            orientation: [0., 0., PI / 2.],
+        };
+        assert!(are_close(
+            transform.apply([1., 0., 0.]),
+            [0., 0., -1.],
+            1e-6
+        ));
+        assert!(are_close(transform.apply([0., 1., 0.]), [0., 1., 0.], 1e-6));
+
+        let transform = Transform {
+            translation: [0., 0., 0.],
+            orientation: [PI / 2., 0., PI / 2.],
+        };
+        assert!(are_close(transform.apply([0., 0., 1.]), [0., 1., 0.], 1e-6));
+    }
+
+    #[test]
+    pub fn test_quaternion_matches_euler() {
+        let transform = Transform {
+            translation: [0.5, -1.0, 2.0],
+            orientation: [0.3, -0.7, 1.1],
+        };
+        let point = [3.0, -2.0, 1.0];
+        let rotated = transform.quaternion().rotate(point);
+        let expected = transform.apply(point);
+        assert!(are_close(
+            [rotated[0] + 0.5, rotated[1] - 1.0, rotated[2] + 2.0],
+            expected,
+            1e-5
+        ));
+
+        let roundtrip = Transform::from_quaternion(transform.translation, transform.quaternion());
+        assert!(are_close(
+            roundtrip.orientation,
+            transform.orientation,
+            1e-5
+        ));
+        assert_eq!(Quaternion::from_euler([0., 0., 0.]), Quaternion::IDENTITY);
+    }
+
+    #[test]
+    pub fn test_compose_and_inverse() {
+        let a = Transform {
+            translation: [1.0, 2.0, 0.5],
+            orientation: [PI / 3., 0.2, -0.4],
+        };
+        let b = Transform {
+            translation: [-0.5, 0.0, 1.5],
+            orientation: [-0.8, PI / 5., 0.9],
+        };
+        let point = [3.0, -2.0, 1.0];
+        assert!(are_close(
+            a.compose(&b).apply(point),
+            a.apply(b.apply(point)),
+            1e-5
+        ));
+        assert!(are_close(a.inverse().apply(point), a.unapply(point), 1e-5));
+        assert!(are_close(a.compose(&a.inverse()).apply(point), point, 1e-5));
+    }
+
+    #[test]
+    pub fn test_deserialize_two_element_orientation() {
+        let transform: Transform =
+            serde_json::from_str(r#"{"translation":[1.0,2.0,3.0],"orientation":[0.5,0.25]}"#)
+                .unwrap();
+        assert_eq!(transform.orientation, [0.5, 0.25, 0.0]);
+
+        let transform: Transform =
+            serde_json::from_str(r#"{"translation":[1.0,2.0,3.0],"orientation":[0.5,0.25,0.125]}"#)
+                .unwrap();
+        assert_eq!(transform.orientation, [0.5, 0.25, 0.125]);
+        assert!(serde_json::from_str::<Transform>(
+            r#"{"translation":[1.0,2.0,3.0],"orientation":[0.5]}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    pub fn test_apply_to_cloud() {
+        let transform = Transform {
+            translation: [1.0, -2.0, 0.5],
+            orientation: [PI / 3., -0.4, 0.7],
+        };
+        let mut pointcloud = PointCloud {
+            points: vec![[3.0, -2.0, 1.0, 0.5].into(), [0.0, 4.0, -1.0, -1.5].into()],
+            ..Default::default()
+        };
+        let original = pointcloud.points.clone();
+        transform.apply_to_cloud(&mut pointcloud);
+        for (point, original) in pointcloud.points.iter().zip(original) {
+            assert!(are_close(
+                (*point).into(),
+                transform.apply(original.into()),
+                1e-5
+            ));
+            assert_eq!(point.v, original.v);
+        }
+
+        let matrix = transform.matrix();
+        let point = [0.5, 0.25, -3.0];
+        assert!(are_close(
+            matrix.compose(&matrix.inverse()).apply(point),
+            point,
+            1e-5
+        ));
+    }
 }
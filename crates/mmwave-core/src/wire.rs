@@ -0,0 +1,698 @@
+use std::{collections::HashMap, hash::Hash, io::Read};
+
+use async_nats::{Client, HeaderMap, PublishError};
+use bincode::Options;
+use chrono::{DateTime, Utc};
+use egui::Ui;
+use half::f16;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    message::{Id, Message, MessageContent, Tag},
+    point::Point,
+    pointcloud::{PointAttributes, PointCloud, SensorOrigin},
+    schema::{
+        self, MessageContentV2, MessageContentV3, MessageContentV4, MessageContentV5,
+        PointAttributesV3, SchemaError, CONTENT_HEADER, CONTENT_TYPE_HEADER, SCHEMA_HEADER,
+        SCHEMA_VERSION,
+    },
+};
+
+/// Every compact payload starts with this, plain bincode messages never do
+/// (they start with the little endian variant index of their content).
+const MAGIC: &[u8; 4] = b"MMWC";
+
+const COMPRESSION_MASK: u8 = 0b011;
+
+/// Largest a message may be once decompressed, so that a corrupt or hostile
+/// payload cannot make a receiver allocate without bound
+pub const MAX_DECODED_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Debug, Clone, Copy, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+}
+
+/// How a device encodes the messages it publishes. Receivers detect the
+/// encoding from the payload, so devices may be configured independently.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Debug, Clone, Copy, Default)]
+pub enum Encoding {
+    /// bincode of the `Message`, the original format
+    #[default]
+    Bincode,
+    /// Columnar pointclouds with dictionary encoded labels and sources
+    Compact {
+        half_precision: bool, // Quantize x, y, z, v to f16
+        compression: Compression,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum WireError {
+    #[error("Serialization error: {0}")]
+    Bincode(#[from] bincode::Error),
+    #[error("Compression error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Truncated payload")]
+    Truncated,
+    #[error("Unknown compression {0}")]
+    UnknownCompression(u8),
+    #[error("Payload decodes to more than {MAX_DECODED_SIZE} bytes")]
+    TooLarge,
+    #[error("Dictionary index {0} is out of range")]
+    InvalidIndex(u32),
+    #[error("Columns of a pointcloud differ in length")]
+    ColumnLength,
+    #[error("Incompatible schema: {0}")]
+    Schema(#[from] SchemaError),
+    #[error("Publish error: {0}")]
+    Publish(#[from] PublishError),
+}
+
+#[derive(Serialize, Deserialize)]
+struct CompactMessage {
+    content: CompactContent,
+    tags: Vec<Tag>,
+    timestamp: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum CompactContent {
+    PointCloud(CompactPointCloud),
+    Other(MessageContent),
+}
+
+#[derive(Serialize, Deserialize)]
+enum Columns {
+    Full([Vec<f32>; 4]),
+    Half([Vec<u16>; 4]),
+}
+
+#[derive(Serialize, Deserialize)]
+struct CompactPointCloud {
+    time: DateTime<Utc>,
+    columns: Columns, // x, y, z, v
+    labels: Dictionary<String>,
+    sources: Dictionary<Id>,
+    attributes: PointAttributes, // Everything but the sources
+    origins: Vec<SensorOrigin>,
+}
+
+/// Compact messages as published before pointclouds carried sensor origins
+#[derive(Deserialize)]
+struct CompactMessageV2 {
+    content: CompactContentV2,
+    tags: Vec<Tag>,
+    timestamp: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+enum CompactContentV2 {
+    PointCloud(CompactPointCloudV2),
+    Other(MessageContentV2),
+}
+
+#[derive(Deserialize)]
+struct CompactPointCloudV2 {
+    time: DateTime<Utc>,
+    columns: Columns,
+    labels: Dictionary<String>,
+    sources: Dictionary<Id>,
+    attributes: PointAttributesV3,
+}
+
+impl From<CompactMessageV2> for CompactMessage {
+    fn from(message: CompactMessageV2) -> Self {
+        let content = match message.content {
+            CompactContentV2::PointCloud(pc) => CompactContent::PointCloud(CompactPointCloud {
+                time: pc.time,
+                columns: pc.columns,
+                labels: pc.labels,
+                sources: pc.sources,
+                attributes: pc.attributes.into(),
+                origins: Vec::new(),
+            }),
+            CompactContentV2::Other(content) => CompactContent::Other(content.into()),
+        };
+        CompactMessage {
+            content,
+            tags: message.tags,
+            timestamp: message.timestamp,
+        }
+    }
+}
+
+/// Compact messages as published before points carried their height
+#[derive(Deserialize)]
+struct CompactMessageV3 {
+    content: CompactContentV3,
+    tags: Vec<Tag>,
+    timestamp: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+enum CompactContentV3 {
+    PointCloud(CompactPointCloudV3),
+    Other(MessageContentV3),
+}
+
+#[derive(Deserialize)]
+struct CompactPointCloudV3 {
+    time: DateTime<Utc>,
+    columns: Columns,
+    labels: Dictionary<String>,
+    sources: Dictionary<Id>,
+    attributes: PointAttributesV3,
+    origins: Vec<SensorOrigin>,
+}
+
+impl From<CompactMessageV3> for CompactMessage {
+    fn from(message: CompactMessageV3) -> Self {
+        let content = match message.content {
+            CompactContentV3::PointCloud(pc) => CompactContent::PointCloud(CompactPointCloud {
+                time: pc.time,
+                columns: pc.columns,
+                labels: pc.labels,
+                sources: pc.sources,
+                attributes: pc.attributes.into(),
+                origins: pc.origins,
+            }),
+            CompactContentV3::Other(content) => CompactContent::Other(content.into()),
+        };
+        CompactMessage {
+            content,
+            tags: message.tags,
+            timestamp: message.timestamp,
+        }
+    }
+}
+
+/// Compact messages as published before tracks carried their source
+#[derive(Deserialize)]
+struct CompactMessageV4 {
+    content: CompactContentV4,
+    tags: Vec<Tag>,
+    timestamp: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+enum CompactContentV4 {
+    PointCloud(CompactPointCloud),
+    Other(MessageContentV4),
+}
+
+impl From<CompactMessageV4> for CompactMessage {
+    fn from(message: CompactMessageV4) -> Self {
+        let content = match message.content {
+            CompactContentV4::PointCloud(pointcloud) => CompactContent::PointCloud(pointcloud),
+            CompactContentV4::Other(content) => CompactContent::Other(content.into()),
+        };
+        CompactMessage {
+            content,
+            tags: message.tags,
+            timestamp: message.timestamp,
+        }
+    }
+}
+
+/// Compact messages as published before zone and posture events carried the
+/// source of their object
+#[derive(Deserialize)]
+struct CompactMessageV5 {
+    content: CompactContentV5,
+    tags: Vec<Tag>,
+    timestamp: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+enum CompactContentV5 {
+    PointCloud(CompactPointCloud),
+    Other(MessageContentV5),
+}
+
+impl From<CompactMessageV5> for CompactMessage {
+    fn from(message: CompactMessageV5) -> Self {
+        let content = match message.content {
+            CompactContentV5::PointCloud(pointcloud) => CompactContent::PointCloud(pointcloud),
+            CompactContentV5::Other(content) => CompactContent::Other(content.into()),
+        };
+        CompactMessage {
+            content,
+            tags: message.tags,
+            timestamp: message.timestamp,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Dictionary<T> {
+    values: Vec<T>,
+    indices: Vec<u32>,
+}
+
+impl<T: Eq + Hash + Clone> Dictionary<T> {
+    fn encode(items: Vec<T>) -> Self {
+        let mut lookup = HashMap::new();
+        let mut values = Vec::new();
+        let indices = items
+            .into_iter()
+            .map(|item| {
+                *lookup.entry(item.clone()).or_insert_with(|| {
+                    values.push(item);
+                    (values.len() - 1) as u32
+                })
+            })
+            .collect();
+        Self { values, indices }
+    }
+
+    fn decode(self) -> Result<Vec<T>, WireError> {
+        self.indices
+            .into_iter()
+            .map(|i| {
+                self.values
+                    .get(i as usize)
+                    .cloned()
+                    .ok_or(WireError::InvalidIndex(i))
+            })
+            .collect()
+    }
+}
+
+impl CompactPointCloud {
+    fn new(pointcloud: PointCloud, half_precision: bool) -> Self {
+        let mut columns: [Vec<f32>; 4] = Default::default();
+        for point in pointcloud.points {
+            let values: [f32; 4] = point.into();
+            for (column, value) in columns.iter_mut().zip(values) {
+                column.push(value);
+            }
+        }
+        let columns = if half_precision {
+            Columns::Half(
+                columns.map(|c| c.into_iter().map(|x| f16::from_f32(x).to_bits()).collect()),
+            )
+        } else {
+            Columns::Full(columns)
+        };
+
+        let mut attributes = pointcloud.attributes;
+        let sources = Dictionary::encode(std::mem::take(&mut attributes.source));
+        Self {
+            time: pointcloud.time,
+            columns,
+            labels: Dictionary::encode(pointcloud.labels),
+            sources,
+            attributes,
+            origins: pointcloud.origins,
+        }
+    }
+}
+
+impl TryFrom<CompactPointCloud> for PointCloud {
+    type Error = WireError;
+
+    fn try_from(compact: CompactPointCloud) -> Result<Self, WireError> {
+        let [x, y, z, v] = match compact.columns {
+            Columns::Full(columns) => columns,
+            Columns::Half(columns) => {
+                columns.map(|c| c.into_iter().map(|x| f16::from_bits(x).to_f32()).collect())
+            }
+        };
+        let len = x.len();
+        let labels = compact.labels.decode()?;
+        let mut attributes = compact.attributes;
+        attributes.source = compact.sources.decode()?;
+        if [&y, &z, &v].iter().any(|column| column.len() != len)
+            || !(labels.is_empty() || labels.len() == len)
+            || !attributes.lines_up(len)
+        {
+            return Err(WireError::ColumnLength);
+        }
+        let points = x
+            .into_iter()
+            .zip(y)
+            .zip(z)
+            .zip(v)
+            .map(|(((x, y), z), v)| Point { x, y, z, v })
+            .collect();
+        Ok(PointCloud {
+            time: compact.time,
+            points,
+            labels,
+            attributes,
+            origins: compact.origins,
+        })
+    }
+}
+
+impl Encoding {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Encoding::Bincode => "application/x-mmwave-bincode",
+            Encoding::Compact { .. } => "application/x-mmwave-compact",
+        }
+    }
+
+    pub fn is_content_type(content_type: &str) -> bool {
+        content_type == Encoding::Bincode.content_type()
+            || content_type
+                == Encoding::Compact {
+                    half_precision: false,
+                    compression: Compression::None,
+                }
+                .content_type()
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Encoding:");
+            let mut compact = matches!(self, Encoding::Compact { .. });
+            ui.checkbox(&mut compact, "compact");
+            match (compact, &mut *self) {
+                (true, Encoding::Bincode) => {
+                    *self = Encoding::Compact {
+                        half_precision: false,
+                        compression: Compression::None,
+                    }
+                }
+                (false, Encoding::Compact { .. }) => *self = Encoding::Bincode,
+                (
+                    true,
+                    Encoding::Compact {
+                        half_precision,
+                        compression,
+                    },
+                ) => {
+                    ui.checkbox(half_precision, "f16");
+                    egui::ComboBox::from_id_source(ui.make_persistent_id("encoding_compression"))
+                        .selected_text(format!("{:?}", compression))
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(compression, Compression::None, "None");
+                            ui.selectable_value(compression, Compression::Lz4, "Lz4");
+                            ui.selectable_value(compression, Compression::Zstd, "Zstd");
+                        });
+                }
+                (false, Encoding::Bincode) => {}
+            }
+        });
+    }
+}
+
+/// Encodes a message for publishing with the given encoding
+pub fn encode(message: &Message, encoding: Encoding) -> Result<Vec<u8>, WireError> {
+    let Encoding::Compact {
+        half_precision,
+        compression,
+    } = encoding
+    else {
+        return Ok(bincode::serialize(message)?);
+    };
+
+    let content = match message.content.clone() {
+        MessageContent::PointCloud(pointcloud) => {
+            CompactContent::PointCloud(CompactPointCloud::new(pointcloud, half_precision))
+        }
+        other => CompactContent::Other(other),
+    };
+    let body = bincode::options().serialize(&CompactMessage {
+        content,
+        tags: message.tags.clone(),
+        timestamp: message.timestamp,
+    })?;
+
+    let flags = compression as u8;
+    let mut payload = Vec::with_capacity(body.len() + MAGIC.len() + 1);
+    payload.extend_from_slice(MAGIC);
+    payload.push(flags);
+    match compression {
+        Compression::None => payload.extend(body),
+        Compression::Lz4 => payload.extend(lz4_flex::compress_prepend_size(&body)),
+        Compression::Zstd => payload.extend(zstd::encode_all(body.as_slice(), 0)?),
+    }
+    Ok(payload)
+}
+
+/// Decodes a payload of the current schema, detecting which encoding it was sent with
+pub fn decode(payload: &[u8]) -> Result<Message, WireError> {
+    match payload.strip_prefix(MAGIC) {
+        Some(rest) => decode_compact(SCHEMA_VERSION, rest),
+        None => Ok(schema::convert(SCHEMA_VERSION, payload)?),
+    }
+}
+
+/// Decodes a payload along with the headers it was published with, converting
+/// messages of older schema versions. Messages without a version header
+/// predate versioning and are tried against each unversioned schema in turn.
+pub fn decode_with_headers(
+    headers: Option<&HeaderMap>,
+    payload: &[u8],
+) -> Result<Message, WireError> {
+    if let Some(content_type) = headers.and_then(|h| h.get(CONTENT_TYPE_HEADER)) {
+        if !Encoding::is_content_type(content_type.as_str()) {
+            return Err(SchemaError::UnknownContentType(content_type.to_string()).into());
+        }
+    }
+    let version = headers
+        .and_then(|h| h.get(SCHEMA_HEADER))
+        .map(|v| schema::parse_version(v.as_str()))
+        .transpose()?;
+
+    match (version, payload.strip_prefix(MAGIC)) {
+        (Some(version), _) if version > SCHEMA_VERSION => Err(SchemaError::TooNew(version).into()),
+        // Compact messages were introduced with schema 1
+        (version, Some(rest)) => decode_compact(version.unwrap_or(1), rest),
+        (Some(version), None) => Ok(schema::convert(version, payload)?),
+        (None, None) => {
+            Ok(schema::convert(1, payload)
+                .or_else(|e| schema::convert(0, payload).map_err(|_| e))?)
+        }
+    }
+}
+
+/// Decodes a message received from nats
+pub fn receive(message: &async_nats::Message) -> Result<Message, WireError> {
+    decode_with_headers(message.headers.as_ref(), &message.payload)
+}
+
+/// The headers describing a message published with the given encoding
+pub fn headers(message: &Message, encoding: Encoding) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(SCHEMA_HEADER, SCHEMA_VERSION.to_string().as_str());
+    headers.insert(CONTENT_TYPE_HEADER, encoding.content_type());
+    headers.insert(CONTENT_HEADER, message.content.to_string().as_str());
+    headers
+}
+
+/// Encodes and publishes a message along with its schema headers
+pub async fn publish(
+    client: &Client,
+    subject: String,
+    message: &Message,
+    encoding: Encoding,
+) -> Result<(), WireError> {
+    let payload = encode(message, encoding)?;
+    client
+        .publish_with_headers(subject, headers(message, encoding), payload.into())
+        .await?;
+    Ok(())
+}
+
+fn decode_compact(version: u16, rest: &[u8]) -> Result<Message, WireError> {
+    let (&flags, body) = rest.split_first().ok_or(WireError::Truncated)?;
+
+    let body = match flags & COMPRESSION_MASK {
+        0 => body.to_vec(),
+        1 => {
+            // The size is checked before it is trusted to allocate
+            let size = body.first_chunk::<4>().ok_or(WireError::Truncated)?;
+            let size = u32::from_le_bytes(*size);
+            if size as u64 > MAX_DECODED_SIZE {
+                return Err(WireError::TooLarge);
+            }
+            lz4_flex::decompress_size_prepended(body)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?
+        }
+        2 => {
+            let mut decoded = Vec::new();
+            zstd::Decoder::new(body)?
+                .take(MAX_DECODED_SIZE + 1)
+                .read_to_end(&mut decoded)?;
+            if decoded.len() as u64 > MAX_DECODED_SIZE {
+                return Err(WireError::TooLarge);
+            }
+            decoded
+        }
+        other => return Err(WireError::UnknownCompression(other)),
+    };
+
+    let options = bincode::options().with_limit(MAX_DECODED_SIZE);
+    let compact: CompactMessage = match version {
+        ..=2 => options.deserialize::<CompactMessageV2>(&body)?.into(),
+        3 => options.deserialize::<CompactMessageV3>(&body)?.into(),
+        4 => options.deserialize::<CompactMessageV4>(&body)?.into(),
+        5 => options.deserialize::<CompactMessageV5>(&body)?.into(),
+        _ => options.deserialize(&body)?,
+    };
+    Ok(Message {
+        content: match compact.content {
+            CompactContent::PointCloud(pointcloud) => {
+                MessageContent::PointCloud(pointcloud.try_into()?)
+            }
+            CompactContent::Other(content) => content,
+        },
+        tags: compact.tags,
+        timestamp: compact.timestamp,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use bincode::Options;
+
+    use super::{
+        decode, encode, Columns, CompactContent, CompactMessage, CompactPointCloud, Compression,
+        Dictionary, Encoding, WireError, MAGIC,
+    };
+    use crate::{
+        message::{Id, Message, MessageContent, Tag},
+        pointcloud::{PointAttributes, PointCloud},
+    };
+
+    fn message() -> Message {
+        let mut pointcloud = PointCloud::from(
+            (0..100)
+                .map(|i| [i as f32 * 0.1, 1.0, -0.5, 0.25].into())
+                .collect::<Vec<_>>(),
+        )
+        .with_source(Id::Device(1, 0))
+        .with_origin(Some(Id::Device(1, 0)));
+        pointcloud.labels = vec!["zedbody:0".to_owned(); 100];
+        pointcloud.attributes.snr = vec![12.5; 100];
+        Message {
+            content: MessageContent::PointCloud(pointcloud),
+            tags: vec![Tag::Pointcloud, Tag::FromId(Id::Device(1, 0))],
+            timestamp: chrono::Utc::now(),
+        }
+    }
+
+    fn pointcloud(message: Message) -> PointCloud {
+        match message.content {
+            MessageContent::PointCloud(pointcloud) => pointcloud,
+            _ => panic!("expected a pointcloud"),
+        }
+    }
+
+    #[test]
+    pub fn test_roundtrip_all_encodings() {
+        let original = message();
+        for compression in [Compression::None, Compression::Lz4, Compression::Zstd] {
+            let encoding = Encoding::Compact {
+                half_precision: false,
+                compression,
+            };
+            let decoded = decode(&encode(&original, encoding).unwrap()).unwrap();
+            assert_eq!(decoded.tags, original.tags);
+            let (a, b) = (pointcloud(decoded), pointcloud(original.clone()));
+            assert_eq!(a.points, b.points);
+            assert_eq!(a.labels, b.labels);
+            assert_eq!(a.attributes, b.attributes);
+            assert_eq!(a.origins, b.origins);
+        }
+
+        let decoded = decode(&encode(&original, Encoding::Bincode).unwrap()).unwrap();
+        assert_eq!(pointcloud(decoded).points, pointcloud(original).points);
+    }
+
+    #[test]
+    pub fn test_compact_is_smaller() {
+        let original = message();
+        let bincode = encode(&original, Encoding::Bincode).unwrap();
+        let compact = encode(
+            &original,
+            Encoding::Compact {
+                half_precision: true,
+                compression: Compression::Zstd,
+            },
+        )
+        .unwrap();
+        assert!(compact.len() * 4 < bincode.len());
+
+        let decoded = pointcloud(decode(&compact).unwrap());
+        for (a, b) in decoded.points.iter().zip(pointcloud(original).points) {
+            assert!((a.x - b.x).abs() < 1e-2);
+        }
+    }
+
+    #[test]
+    pub fn test_reject_malformed() {
+        // An lz4 payload claiming to decompress to 4 GiB
+        let mut payload = MAGIC.to_vec();
+        payload.push(Compression::Lz4 as u8);
+        payload.extend(u32::MAX.to_le_bytes());
+        payload.extend([0; 16]);
+        assert!(matches!(decode(&payload), Err(WireError::TooLarge)));
+
+        let pointcloud = || CompactPointCloud {
+            time: chrono::Utc::now(),
+            columns: Columns::Full([vec![0.0], vec![0.0], vec![0.0], vec![0.0]]),
+            labels: Dictionary::encode(vec![String::new()]),
+            sources: Dictionary::encode(vec![Id::Device(1, 0)]),
+            attributes: Default::default(),
+            origins: Vec::new(),
+        };
+        let payload = |pointcloud: CompactPointCloud| {
+            let compact = CompactMessage {
+                content: CompactContent::PointCloud(pointcloud),
+                tags: vec![Tag::Pointcloud],
+                timestamp: chrono::Utc::now(),
+            };
+            let mut payload = MAGIC.to_vec();
+            payload.push(Compression::None as u8);
+            payload.extend(bincode::options().serialize(&compact).unwrap());
+            payload
+        };
+        assert!(decode(&payload(pointcloud())).is_ok());
+
+        // Sources that do not point into their dictionary
+        let sources = Dictionary {
+            values: vec![Id::Device(1, 0)],
+            indices: vec![3],
+        };
+        let malformed = payload(CompactPointCloud {
+            sources,
+            ..pointcloud()
+        });
+        assert!(matches!(
+            decode(&malformed),
+            Err(WireError::InvalidIndex(3))
+        ));
+
+        // Columns that do not line up with the points
+        let columns = Columns::Full([vec![0.0; 2], vec![0.0], vec![0.0; 2], vec![0.0; 2]]);
+        let short = payload(CompactPointCloud {
+            columns,
+            ..pointcloud()
+        });
+        assert!(matches!(decode(&short), Err(WireError::ColumnLength)));
+        let columns = Columns::Half([vec![0; 2], vec![0; 2], vec![0; 2], vec![0; 2]]);
+        let long = payload(CompactPointCloud {
+            columns,
+            ..pointcloud()
+        });
+        assert!(matches!(decode(&long), Err(WireError::ColumnLength)));
+        let attributes = PointAttributes {
+            snr: vec![1.0; 3],
+            ..Default::default()
+        };
+        let misaligned = payload(CompactPointCloud {
+            attributes,
+            ..pointcloud()
+        });
+        assert!(matches!(decode(&misaligned), Err(WireError::ColumnLength)));
+    }
+}
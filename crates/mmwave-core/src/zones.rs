@@ -0,0 +1,569 @@
+//! Named regions of interest, such as doors, beds or desks. Like devices,
+//! every zone is defined relative to a frame of the configuration. The
+//! `ZoneMonitor` follows what is inside each zone over time and describes
+//! changes as events.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use egui::Ui;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Configuration,
+    frames::{world, FrameError},
+    message::Id,
+    telemetry::{Event, EventKind, Severity, TrackState, TrackedObjects},
+    transform::TransformMatrix,
+};
+
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub enum ZoneShape {
+    Box {
+        min: [f32; 3],
+        max: [f32; 3],
+    },
+    /// A prism over a polygon in the xy plane
+    Polygon {
+        vertices: Vec<[f32; 2]>,
+        min_z: f32,
+        max_z: f32,
+    },
+}
+
+impl Default for ZoneShape {
+    fn default() -> Self {
+        ZoneShape::Box {
+            min: [-0.5, -0.5, -1.0],
+            max: [0.5, 0.5, 1.0],
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct Zone {
+    pub name: String, // Identifies the zone in events
+    #[serde(default = "world")]
+    pub frame: String, // Frame the shape is defined in
+    pub shape: ZoneShape,
+    #[serde(default)]
+    pub dwell_s: f32, // Time inside before a dwell event, 0 for none
+}
+
+impl Default for Zone {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            frame: world(),
+            shape: ZoneShape::default(),
+            dwell_s: 0.0,
+        }
+    }
+}
+
+impl Zone {
+    /// Whether a position in the zone's frame is inside it
+    pub fn contains(&self, position: [f32; 3]) -> bool {
+        let [x, y, z] = position;
+        match &self.shape {
+            ZoneShape::Box { min, max } => (0..3).all(|k| (min[k]..=max[k]).contains(&position[k])),
+            ZoneShape::Polygon {
+                vertices,
+                min_z,
+                max_z,
+            } => {
+                if z < *min_z || z > *max_z {
+                    return false;
+                }
+                // Even-odd rule, counting the edges crossed by a ray along +x
+                let mut inside = false;
+                for (i, &[x1, y1]) in vertices.iter().enumerate() {
+                    let [x2, y2] = vertices[(i + 1) % vertices.len()];
+                    if (y1 > y) != (y2 > y) && x < x1 + (y - y1) / (y2 - y1) * (x2 - x1) {
+                        inside = !inside;
+                    }
+                }
+                inside
+            }
+        }
+    }
+
+    /// Corners of the zone's footprint in its own frame, at its lowest height
+    pub fn outline(&self) -> Vec<[f32; 3]> {
+        match &self.shape {
+            ZoneShape::Box { min, max } => vec![
+                [min[0], min[1], min[2]],
+                [max[0], min[1], min[2]],
+                [max[0], max[1], min[2]],
+                [min[0], max[1], min[2]],
+            ],
+            ZoneShape::Polygon {
+                vertices, min_z, ..
+            } => vertices.iter().map(|&[x, y]| [x, y, *min_z]).collect(),
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Name");
+            ui.text_edit_singleline(&mut self.name);
+        });
+        ui.horizontal(|ui| {
+            ui.label("Frame");
+            ui.text_edit_singleline(&mut self.frame);
+        });
+        ui.horizontal(|ui| {
+            ui.label("Dwell (s)");
+            ui.add(
+                egui::DragValue::new(&mut self.dwell_s)
+                    .speed(0.1)
+                    .clamp_range(0.0..=f32::MAX),
+            );
+        });
+        ui.horizontal(|ui| {
+            let is_box = matches!(self.shape, ZoneShape::Box { .. });
+            if ui.selectable_label(is_box, "Box").clicked() && !is_box {
+                self.shape = ZoneShape::default();
+            }
+            if ui.selectable_label(!is_box, "Polygon").clicked() && is_box {
+                self.shape = ZoneShape::Polygon {
+                    vertices: self.outline().iter().map(|&[x, y, _]| [x, y]).collect(),
+                    min_z: -1.0,
+                    max_z: 1.0,
+                };
+            }
+        });
+        match &mut self.shape {
+            ZoneShape::Box { min, max } => {
+                for (label, corner) in [("Min", min), ("Max", max)] {
+                    ui.horizontal(|ui| {
+                        ui.label(label);
+                        for x in corner.iter_mut() {
+                            ui.add(egui::DragValue::new(x).speed(0.05));
+                        }
+                    });
+                }
+            }
+            ZoneShape::Polygon {
+                vertices,
+                min_z,
+                max_z,
+            } => {
+                let mut removed = None;
+                for (i, vertex) in vertices.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("Vertex {}", i));
+                        ui.add(egui::DragValue::new(&mut vertex[0]).speed(0.05));
+                        ui.add(egui::DragValue::new(&mut vertex[1]).speed(0.05));
+                        if ui.button("remove").clicked() {
+                            removed = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = removed {
+                    vertices.remove(i);
+                }
+                if ui.button("add vertex").clicked() {
+                    vertices.push(vertices.last().copied().unwrap_or_default());
+                }
+                ui.horizontal(|ui| {
+                    ui.label("Height");
+                    ui.add(egui::DragValue::new(min_z).speed(0.05));
+                    ui.add(egui::DragValue::new(max_z).speed(0.05));
+                });
+            }
+        }
+    }
+}
+
+/// What occupies a zone: the device a track was observed by and its id, as
+/// track ids are only unique within a source. None for presence from points.
+type Object = (Option<Id>, Option<u64>);
+
+#[derive(Debug, Clone)]
+struct Occupant {
+    entered: DateTime<Utc>,
+    position: [f32; 3], // Last world frame position inside the zone
+    dwelled: bool,
+}
+
+#[derive(Debug, Clone)]
+struct ZoneState {
+    zone: Zone,
+    world_to_zone: TransformMatrix,
+    occupants: HashMap<Object, Occupant>,
+}
+
+/// Follows the tracks (or points) inside each zone of a configuration,
+/// producing events when something enters, exits or dwells in a zone and
+/// when the number of occupants changes.
+#[derive(Debug, Clone, Default)]
+pub struct ZoneMonitor {
+    zones: Vec<ZoneState>,
+}
+
+impl ZoneMonitor {
+    pub fn new(configuration: &Configuration) -> Result<Self, FrameError> {
+        let zones = configuration
+            .zones
+            .iter()
+            .map(|zone| {
+                let zone_to_world = configuration.frames.to_world(&zone.frame)?;
+                Ok(ZoneState {
+                    zone: zone.clone(),
+                    world_to_zone: zone_to_world.matrix().inverse(),
+                    occupants: HashMap::new(),
+                })
+            })
+            .collect::<Result<_, FrameError>>()?;
+        Ok(ZoneMonitor { zones })
+    }
+
+    /// Number of occupants of each zone, by name
+    pub fn counts(&self) -> Vec<(&str, usize)> {
+        self.zones
+            .iter()
+            .map(|state| (state.zone.name.as_str(), state.occupants.len()))
+            .collect()
+    }
+
+    /// Updates the zones with the confirmed and coasting tracks at a time,
+    /// each of which occupies the zone it is in. Only the occupants tracked
+    /// by the same source as the tracks can leave.
+    pub fn update_tracks(&mut self, time: DateTime<Utc>, tracks: &TrackedObjects) -> Vec<Event> {
+        let objects: Vec<(Object, [f32; 3])> = tracks
+            .objects
+            .iter()
+            .filter(|object| object.state != TrackState::Tentative)
+            .map(|object| ((tracks.source, Some(object.id)), object.position))
+            .collect();
+        let inside = self
+            .zones
+            .iter()
+            .map(|state| {
+                objects
+                    .iter()
+                    .filter(|(_, position)| {
+                        state.zone.contains(state.world_to_zone.apply(*position))
+                    })
+                    .copied()
+                    .collect()
+            })
+            .collect();
+        self.step(time, tracks.source, inside)
+    }
+
+    /// Updates the zones with world frame points at a time. Points carry no
+    /// identity, so a zone has a single anonymous occupant whenever at least
+    /// min_points are inside it.
+    pub fn update_points(
+        &mut self,
+        time: DateTime<Utc>,
+        points: &[[f32; 3]],
+        min_points: usize,
+    ) -> Vec<Event> {
+        let inside = self
+            .zones
+            .iter()
+            .map(|state| {
+                let inside: Vec<[f32; 3]> = points
+                    .iter()
+                    .filter(|&&point| state.zone.contains(state.world_to_zone.apply(point)))
+                    .copied()
+                    .collect();
+                if inside.is_empty() || inside.len() < min_points {
+                    return HashMap::new();
+                }
+                let n = inside.len() as f32;
+                let centroid = inside
+                    .iter()
+                    .fold([0.0; 3], |sum, p| [0, 1, 2].map(|k| sum[k] + p[k] / n));
+                HashMap::from([((None, None), centroid)])
+            })
+            .collect();
+        self.step(time, None, inside)
+    }
+
+    /// Moves the occupants of source to those inside each zone
+    fn step(
+        &mut self,
+        time: DateTime<Utc>,
+        source: Option<Id>,
+        inside: Vec<HashMap<Object, [f32; 3]>>,
+    ) -> Vec<Event> {
+        let mut events = Vec::new();
+        for (state, inside) in self.zones.iter_mut().zip(inside) {
+            let zone = state.zone.name.clone();
+            let before = state.occupants.len();
+
+            state.occupants.retain(|&(from, object), occupant| {
+                if from != source || inside.contains_key(&(from, object)) {
+                    return true;
+                }
+                let duration = seconds(time - occupant.entered);
+                events.push(zone_event(
+                    EventKind::ZoneExited {
+                        zone: zone.clone(),
+                        object,
+                        source: from,
+                        duration,
+                    },
+                    format!(
+                        "{} exited zone {} after {:.1}s",
+                        describe(object),
+                        zone,
+                        duration
+                    ),
+                    Some(occupant.position),
+                ));
+                false
+            });
+
+            // Sorted so events come out in a stable order
+            let mut inside: Vec<_> = inside.into_iter().collect();
+            inside.sort_by_key(|(object, _)| *object);
+            for ((from, object), position) in inside {
+                let occupant = state.occupants.entry((from, object)).or_insert_with(|| {
+                    events.push(zone_event(
+                        EventKind::ZoneEntered {
+                            zone: zone.clone(),
+                            object,
+                            source: from,
+                        },
+                        format!("{} entered zone {}", describe(object), zone),
+                        Some(position),
+                    ));
+                    Occupant {
+                        entered: time,
+                        position,
+                        dwelled: false,
+                    }
+                });
+                occupant.position = position;
+                let duration = seconds(time - occupant.entered);
+                if state.zone.dwell_s > 0.0 && !occupant.dwelled && duration >= state.zone.dwell_s {
+                    occupant.dwelled = true;
+                    events.push(zone_event(
+                        EventKind::ZoneDwell {
+                            zone: zone.clone(),
+                            object,
+                            source: from,
+                            duration,
+                        },
+                        format!(
+                            "{} has been in zone {} for {:.1}s",
+                            describe(object),
+                            zone,
+                            duration
+                        ),
+                        Some(position),
+                    ));
+                }
+            }
+
+            let count = state.occupants.len();
+            if count != before {
+                events.push(zone_event(
+                    EventKind::ZoneOccupancy {
+                        zone: zone.clone(),
+                        count: count as u32,
+                    },
+                    format!("Zone {} has {} occupants", zone, count),
+                    None,
+                ));
+            }
+        }
+        events
+    }
+}
+
+fn seconds(duration: chrono::Duration) -> f32 {
+    duration.num_milliseconds() as f32 / 1000.0
+}
+
+fn describe(object: Option<u64>) -> String {
+    match object {
+        Some(id) => format!("Track {}", id),
+        None => String::from("Something"),
+    }
+}
+
+fn zone_event(kind: EventKind, description: String, position: Option<[f32; 3]>) -> Event {
+    Event {
+        kind,
+        severity: Severity::Info,
+        description,
+        confidence: None,
+        position,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{Zone, ZoneMonitor, ZoneShape};
+    use crate::{
+        config::Configuration,
+        frames::Frame,
+        message::Id,
+        telemetry::{EventKind, TrackState, TrackedObject, TrackedObjects},
+        transform::Transform,
+    };
+
+    fn track(id: u64, position: [f32; 3]) -> TrackedObject {
+        TrackedObject {
+            id,
+            state: TrackState::Confirmed,
+            position,
+            velocity: [0.0; 3],
+            covariance: Vec::new(),
+            source: None,
+        }
+    }
+
+    #[test]
+    pub fn test_contains() {
+        let triangle = Zone {
+            shape: ZoneShape::Polygon {
+                vertices: vec![[0.0, 0.0], [2.0, 0.0], [0.0, 2.0]],
+                min_z: 0.0,
+                max_z: 2.0,
+            },
+            ..Default::default()
+        };
+        assert!(triangle.contains([0.5, 0.5, 1.0]));
+        assert!(!triangle.contains([1.5, 1.5, 1.0]));
+        assert!(!triangle.contains([0.5, 0.5, 3.0]));
+        assert!(Zone::default().contains([0.0, 0.0, 0.0]));
+        assert!(!Zone::default().contains([0.0, 0.6, 0.0]));
+    }
+
+    #[test]
+    pub fn test_zone_events() {
+        let configuration = Configuration {
+            frames: crate::frames::FrameTree {
+                frames: vec![Frame {
+                    name: String::from("room"),
+                    transform: Transform {
+                        translation: [10.0, 0.0, 0.0],
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }],
+            },
+            zones: vec![Zone {
+                name: String::from("bed"),
+                frame: String::from("room"),
+                dwell_s: 1.0,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let mut monitor = ZoneMonitor::new(&configuration).unwrap();
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let at = |ms| start + Duration::milliseconds(ms);
+        let frame = |objects| TrackedObjects {
+            objects,
+            source: None,
+        };
+
+        // Outside the zone in the world frame, but inside it in the room frame
+        let events = monitor.update_tracks(
+            at(0),
+            &frame(vec![track(1, [10.0, 0.0, 0.0]), track(2, [0.0, 0.0, 0.0])]),
+        );
+        let kinds: Vec<EventKind> = events.into_iter().map(|e| e.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                EventKind::ZoneEntered {
+                    zone: String::from("bed"),
+                    object: Some(1),
+                    source: None,
+                },
+                EventKind::ZoneOccupancy {
+                    zone: String::from("bed"),
+                    count: 1
+                },
+            ]
+        );
+
+        let events = monitor.update_tracks(at(1500), &frame(vec![track(1, [10.1, 0.0, 0.0])]));
+        assert!(matches!(events[0].kind, EventKind::ZoneDwell { duration, .. } if duration == 1.5));
+        assert_eq!(events.len(), 1);
+        assert!(monitor
+            .update_tracks(at(1600), &frame(vec![track(1, [10.1, 0.0, 0.0])]))
+            .is_empty());
+
+        let events = monitor.update_tracks(at(2000), &frame(vec![]));
+        assert!(
+            matches!(events[0].kind, EventKind::ZoneExited { object: Some(1), duration, .. } if duration == 2.0)
+        );
+        assert_eq!(monitor.counts(), vec![("bed", 0)]);
+
+        // Points only count once there are enough of them
+        assert!(monitor
+            .update_points(at(3000), &[[10.0, 0.0, 0.0]], 2)
+            .is_empty());
+        let events = monitor.update_points(at(3100), &[[10.0, 0.0, 0.0], [10.2, 0.0, 0.0]], 2);
+        assert!(matches!(
+            events[0].kind,
+            EventKind::ZoneEntered { object: None, .. }
+        ));
+        assert_eq!(events[0].position, Some([10.1, 0.0, 0.0]));
+    }
+
+    #[test]
+    pub fn test_interleaved_sources() {
+        let configuration = Configuration {
+            zones: vec![Zone {
+                name: String::from("door"),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let mut monitor = ZoneMonitor::new(&configuration).unwrap();
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let at = |ms| start + Duration::milliseconds(ms);
+        // Both sources number their tracks from 0
+        let frame = |source, objects| TrackedObjects {
+            objects,
+            source: Some(Id::Device(source, 0)),
+        };
+
+        let first = monitor.update_tracks(at(0), &frame(1, vec![track(0, [0.0; 3])]));
+        let second = monitor.update_tracks(at(50), &frame(2, vec![track(0, [0.1, 0.0, 0.0])]));
+        for (source, events) in [(1, first), (2, second)] {
+            assert!(matches!(
+                events[0].kind,
+                EventKind::ZoneEntered {
+                    object: Some(0),
+                    source: Some(id),
+                    ..
+                } if id == Id::Device(source, 0)
+            ));
+        }
+        assert_eq!(monitor.counts(), vec![("door", 2)]);
+
+        for i in 1..10 {
+            let source = 1 + i % 2;
+            let events =
+                monitor.update_tracks(at(i as i64 * 100), &frame(source, vec![track(0, [0.0; 3])]));
+            assert!(events.is_empty(), "{:?}", events);
+        }
+
+        // An empty frame only ends the tracks of its own source
+        let events = monitor.update_tracks(at(1000), &frame(1, vec![]));
+        assert_eq!(events.len(), 2);
+        assert!(matches!(
+            events[0].kind,
+            EventKind::ZoneExited {
+                object: Some(0),
+                source: Some(Id::Device(1, 0)),
+                ..
+            }
+        ));
+        assert_eq!(monitor.counts(), vec![("door", 1)]);
+    }
+}
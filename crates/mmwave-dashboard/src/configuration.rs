@@ -9,11 +9,22 @@ use mmwave_awr::AwrDescriptor;
 use mmwave_core::{
     config::Configuration,
     devices::{DeviceConfig, EmptyDeviceDescriptor},
+    frames::Frame,
+    ground::Plane,
     message::Id,
     transform::Transform,
+    zones::Zone,
 };
 use mmwave_playback::{PlaybackDescriptor};
 use mmwave_recorder::RecordingDescriptor;
+use mmwave_fusion::FusionDescriptor;
+use mmwave_filter::FilterDescriptor;
+use mmwave_cluster::ClusterDescriptor;
+use mmwave_tracker::TrackerDescriptor;
+use mmwave_occupancy::OccupancyDescriptor;
+use mmwave_zones::ZoneDescriptor;
+use mmwave_posture::PostureDescriptor;
+use mmwave_classification::ClassificationDescriptor;
 use mmwave_zed::ZedDescriptor;
 use tracing::info;
 
@@ -30,6 +41,12 @@ impl ConfigWidget {
     pub fn ui(&mut self, ui: &mut egui::Ui) {
         self.render_header(ui);
         ui.separator();
+        self.render_frames(ui);
+        ui.separator();
+        self.render_zones(ui);
+        ui.separator();
+        self.render_floor(ui);
+        ui.separator();
         self.render_descriptors(ui);
     }
 
@@ -43,34 +60,69 @@ impl ConfigWidget {
         ui.separator();
         ui.horizontal(|ui| {
             if ui.button("new awr").clicked() {
-                self.config.descriptors.push(DeviceConfig {
-                    id: Id::Device(0, 0),
-                    device_descriptor: Box::new(AwrDescriptor::default()),
-                });
+                self.config
+                    .descriptors
+                    .push(DeviceConfig::new(Id::Device(0, 0), Box::new(AwrDescriptor::default())));
             }
             if ui.button("new recorder").clicked() {
-                self.config.descriptors.push(DeviceConfig {
-                    id: Id::Device(0, 0),
-                    device_descriptor: Box::new(RecordingDescriptor::default()),
-                });
+                self.config
+                    .descriptors
+                    .push(DeviceConfig::new(Id::Device(0, 0), Box::new(RecordingDescriptor::default())));
             }
             if ui.button("new zed").clicked() {
-                self.config.descriptors.push(DeviceConfig {
-                    id: Id::Device(0, 0),
-                    device_descriptor: Box::new(ZedDescriptor::default()),
-                });
+                self.config
+                    .descriptors
+                    .push(DeviceConfig::new(Id::Device(0, 0), Box::new(ZedDescriptor::default())));
             }
             if ui.button("new playback").clicked() {
-                self.config.descriptors.push(DeviceConfig {
-                    id: Id::Device(0, 0),
-                    device_descriptor: Box::new(PlaybackDescriptor::default()),
-                });
+                self.config
+                    .descriptors
+                    .push(DeviceConfig::new(Id::Device(0, 0), Box::new(PlaybackDescriptor::default())));
+            }
+            if ui.button("new fusion").clicked() {
+                self.config
+                    .descriptors
+                    .push(DeviceConfig::new(Id::Device(0, 0), Box::new(FusionDescriptor::default())));
+            }
+            if ui.button("new filter").clicked() {
+                self.config
+                    .descriptors
+                    .push(DeviceConfig::new(Id::Device(0, 0), Box::new(FilterDescriptor::default())));
+            }
+            if ui.button("new cluster").clicked() {
+                self.config
+                    .descriptors
+                    .push(DeviceConfig::new(Id::Device(0, 0), Box::new(ClusterDescriptor::default())));
+            }
+            if ui.button("new tracker").clicked() {
+                self.config
+                    .descriptors
+                    .push(DeviceConfig::new(Id::Device(0, 0), Box::new(TrackerDescriptor::default())));
+            }
+            if ui.button("new occupancy").clicked() {
+                self.config
+                    .descriptors
+                    .push(DeviceConfig::new(Id::Device(0, 0), Box::new(OccupancyDescriptor::default())));
+            }
+            if ui.button("new zones").clicked() {
+                self.config
+                    .descriptors
+                    .push(DeviceConfig::new(Id::Device(0, 0), Box::new(ZoneDescriptor::default())));
+            }
+            if ui.button("new posture").clicked() {
+                self.config
+                    .descriptors
+                    .push(DeviceConfig::new(Id::Device(0, 0), Box::new(PostureDescriptor::default())));
+            }
+            if ui.button("new classification").clicked() {
+                self.config
+                    .descriptors
+                    .push(DeviceConfig::new(Id::Device(0, 0), Box::new(ClassificationDescriptor::default())));
             }
             if ui.button("new empty").clicked() {
-                self.config.descriptors.push(DeviceConfig {
-                    id: Id::Device(0, 0),
-                    device_descriptor: Box::new(EmptyDeviceDescriptor),
-                });
+                self.config
+                    .descriptors
+                    .push(DeviceConfig::new(Id::Device(0, 0), Box::new(EmptyDeviceDescriptor)));
             }
         });
     }
@@ -169,6 +221,68 @@ impl ConfigWidget {
         }
     }
 
+    fn render_frames(&mut self, ui: &mut egui::Ui) {
+        let mut removals = Vec::new();
+        egui::CollapsingHeader::new("Frames")
+            .id_source(ui.make_persistent_id("frames"))
+            .show(ui, |ui| {
+                if ui.button("new frame").clicked() {
+                    self.config.frames.frames.push(Frame::default());
+                }
+                if let Err(e) = self.config.frames.validate() {
+                    ui.label(RichText::new(e.to_string()).color(Color32::RED));
+                }
+                for (i, frame) in self.config.frames.frames.iter_mut().enumerate() {
+                    ui.push_id(i, |ui| {
+                        ui.separator();
+                        if ui.button("delete").clicked() {
+                            removals.push(i);
+                        }
+                        frame.ui(ui);
+                    });
+                }
+            });
+
+        for i in removals.iter().rev() {
+            self.config.frames.frames.remove(*i);
+        }
+    }
+
+    fn render_zones(&mut self, ui: &mut egui::Ui) {
+        let mut removals = Vec::new();
+        egui::CollapsingHeader::new("Zones")
+            .id_source(ui.make_persistent_id("zones"))
+            .show(ui, |ui| {
+                if ui.button("new zone").clicked() {
+                    self.config.zones.push(Zone::default());
+                }
+                for (i, zone) in self.config.zones.iter_mut().enumerate() {
+                    ui.push_id(i, |ui| {
+                        ui.separator();
+                        if ui.button("delete").clicked() {
+                            removals.push(i);
+                        }
+                        zone.ui(ui);
+                    });
+                }
+            });
+
+        for i in removals.iter().rev() {
+            self.config.zones.remove(*i);
+        }
+    }
+
+    fn render_floor(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Floor")
+            .id_source(ui.make_persistent_id("floor"))
+            .show(ui, |ui| {
+                self.config.floor.ui(ui);
+                if ui.button("reset").clicked() {
+                    self.config.floor = Plane::default();
+                }
+            });
+    }
+
     fn render_descriptor_color_edit(
         colors: &mut HashMap<Id, [f32; 3]>,
         ui: &mut egui::Ui,
@@ -16,15 +16,15 @@ use mmwave_awr::AwrDescriptor;
 use mmwave_zed::ZedDescriptor;
 use mmwave_core::config::Configuration;
 use mmwave_core::logging::enable_tracing;
-use mmwave_core::message::{Id, Tag};
+use mmwave_core::message::Id;
 use mmwave_core::nats::get_store;
 use mmwave_core::point::Point;
 use mmwave_core::pointcloud::PointCloud;
+use mmwave_core::pubsub::Subscription;
+use mmwave_core::subject::SubjectFilter;
+use mmwave_core::telemetry::OccupancyGrid;
 use mmwave_core::transform::Transform;
-use mmwave_core::{
-    address::ServerAddress,
-    message::{Message, MessageContent},
-};
+use mmwave_core::address::ServerAddress;
 use std::collections::HashMap;
 use std::net::IpAddr;
 use std::time::{Duration, Instant};
@@ -52,10 +52,12 @@ pub struct Args {
 }
 
 struct MyApp {
-    ptc_rx: mpsc::Receiver<(Vec<Tag>, Vec<Point>)>,
+    ptc_rx: mpsc::Receiver<(Id, Vec<Point>)>,
+    occ_rx: mpsc::Receiver<(Id, OccupancyGrid)>,
     cfg_in_rx: mpsc::Receiver<Configuration>,
     cfg_out_tx: mpsc::Sender<Configuration>,
     pointcloud: HashMap<Id, (Instant, Vec<Point>)>,
+    occupancy: HashMap<Id, OccupancyGrid>,
     config_widget: ConfigWidget,
     global_transform: Transform
 }
@@ -92,8 +94,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             // Listen for pointclouds and forward them to rx
             let frame = cc.egui_ctx.clone();
             let (ptc_tx, ptc_rx) = mpsc::channel(100);
+            tokio::spawn({
+                let client = client.clone();
+                async move {
+                    listen_for_pointcloud(frame, client, ptc_tx).await.unwrap();
+                }
+            });
+
+            // Listen for occupancy grids and forward them to rx
+            let frame = cc.egui_ctx.clone();
+            let (occ_tx, occ_rx) = mpsc::channel(10);
             tokio::spawn(async move {
-                listen_for_pointcloud(frame, client, ptc_tx).await.unwrap();
+                listen_for_occupancy(frame, client, occ_tx).await.unwrap();
             });
 
             // listen for configs and forward them
@@ -117,8 +129,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
             Box::new(MyApp {
                 pointcloud: HashMap::new(),
+                occupancy: HashMap::new(),
                 config_widget: ConfigWidget::default(),
                 ptc_rx,
+                occ_rx,
                 cfg_in_rx,
                 cfg_out_tx,
                 global_transform
@@ -197,21 +211,34 @@ async fn update_config(frame: Context, store: Store, mut rx: mpsc::Receiver<Conf
 async fn listen_for_pointcloud(
     frame: Context,
     client: Client,
-    tx: mpsc::Sender<(Vec<Tag>, Vec<Point>)>,
+    tx: mpsc::Sender<(Id, Vec<Point>)>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut subscription = client.subscribe("Pointcloud.*").await?;
-
-    while let Some(message) = subscription.next().await {
-        let message: Message = bincode::deserialize(&message.payload)?;
-        if let MessageContent::PointCloud(pointcloud) = message.content {
-            let _ = tx
-                .send((
-                    message.tags,
-                    pointcloud.points.iter().map(|&p| p.into()).collect(),
-                ))
-                .await;
-            frame.request_repaint();
-        }
+    let mut subscription = Subscription::<PointCloud>::new(&client, SubjectFilter::all()).await?;
+
+    while let Some(received) = subscription.next().await {
+        let Some(id) = received.from else {
+            continue;
+        };
+        let _ = tx.send((id, received.content.points)).await;
+        frame.request_repaint();
+    }
+
+    Ok(())
+}
+
+async fn listen_for_occupancy(
+    frame: Context,
+    client: Client,
+    tx: mpsc::Sender<(Id, OccupancyGrid)>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut subscription = Subscription::<OccupancyGrid>::new(&client, SubjectFilter::all()).await?;
+
+    while let Some(received) = subscription.next().await {
+        let Some(id) = received.from else {
+            continue;
+        };
+        let _ = tx.send((id, received.content)).await;
+        frame.request_repaint();
     }
 
     Ok(())
@@ -219,15 +246,12 @@ async fn listen_for_pointcloud(
 
 impl eframe::App for MyApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        if let Ok((tags, new_points)) = self.ptc_rx.try_recv() {
-            for tag in tags {
-                let Tag::FromId(id) = tag else {
-                    continue;
-                };
-
-                self.pointcloud.insert(id, (Instant::now(), new_points));
-                break;
-            }
+        if let Ok((id, new_points)) = self.ptc_rx.try_recv() {
+            self.pointcloud.insert(id, (Instant::now(), new_points));
+        };
+
+        if let Ok((id, grid)) = self.occ_rx.try_recv() {
+            self.occupancy.insert(id, grid);
         };
 
         if let Ok(config) = self.cfg_in_rx.try_recv() {
@@ -262,39 +286,56 @@ impl eframe::App for MyApp {
                         CoordinatesFormatter::default(),
                     )
                     .show(ui, |plot_ui| {
+                        // Occupancy grids are drawn top down underneath everything else
+                        let matrix = self.global_transform.matrix();
+                        for grid in self.occupancy.values() {
+                            let projected = grid.project();
+                            let max = projected.iter().copied().fold(f32::MIN_POSITIVE, f32::max);
+                            let [dx, dy, _] = grid.cell_size;
+                            for (i, &value) in projected.iter().enumerate() {
+                                let alpha = (value / max * 160.0) as u8;
+                                if alpha == 0 {
+                                    continue;
+                                }
+                                let [x, y, z] = grid.centre(i);
+                                let corners = [[-0.5, -0.5], [0.5, -0.5], [0.5, 0.5], [-0.5, 0.5]];
+                                plot_ui.polygon(
+                                    Polygon::new(PlotPoints::Owned(
+                                        corners
+                                            .iter()
+                                            .map(|[cx, cy]| {
+                                                let p = matrix.apply([x + cx * dx, y + cy * dy, z]);
+                                                PlotPoint {
+                                                    x: p[0] as f64,
+                                                    y: p[1] as f64,
+                                                }
+                                            })
+                                            .collect(),
+                                    ))
+                                    .fill_color(Color32::from_rgba_unmultiplied(255, 96, 0, alpha))
+                                    .stroke(Stroke::NONE),
+                                );
+                            }
+                        }
+
                         for (id, (time, pointcloud)) in &self.pointcloud {
-                            let (old_transform, new_transform) = {
-                                (
-                                    if let Some(cfg) = self
-                                        .config_widget
-                                        .config_original
-                                        .descriptors
-                                        .iter()
-                                        .find(|&d| d.id == *id)
-                                    {
-                                        cfg.device_descriptor.transform().unwrap_or_default()
-                                    } else {
-                                        Transform::default()
-                                    },
-                                    if let Some(cfg) = self
-                                        .config_widget
-                                        .config
-                                        .descriptors
-                                        .iter()
-                                        .find(|&d| d.id == *id)
-                                    {
-                                        cfg.device_descriptor.transform().unwrap_or_default()
-                                    } else {
-                                        Transform::default()
-                                    },
-                                )
+                            // Points arrive in the world frame of the applied config, so
+                            // undo that and redo it with the config being edited
+                            let world_transform = |config: &Configuration| {
+                                config.sensor_to_world_or(*id, &Transform::default())
                             };
+                            let old_transform = world_transform(&self.config_widget.config_original);
+                            let new_transform = world_transform(&self.config_widget.config);
+                            let matrix = self
+                                .global_transform
+                                .matrix()
+                                .compose(&new_transform.matrix())
+                                .compose(&old_transform.matrix().inverse());
                             let points = PlotPoints::Owned(
                                 pointcloud
                                     .iter()
                                     .map(|&p| {
-                                        let p =
-                                            self.global_transform.apply(new_transform.apply(old_transform.unapply(p.into())));
+                                        let p = matrix.apply(p.into());
                                         PlotPoint {
                                             x: p[0] as f64,
                                             y: p[1] as f64,
@@ -319,8 +360,43 @@ impl eframe::App for MyApp {
                             plot_ui.points(points);
                         }
 
+                        for zone in self.config_widget.config.zones.iter() {
+                            let Ok(zone_to_world) = self.config_widget.config.frames.to_world(&zone.frame) else {
+                                continue;
+                            };
+                            let matrix = self.global_transform.matrix().compose(&zone_to_world.matrix());
+                            let outline: Vec<PlotPoint> = zone
+                                .outline()
+                                .into_iter()
+                                .map(|p| {
+                                    let p = matrix.apply(p);
+                                    PlotPoint {
+                                        x: p[0] as f64,
+                                        y: p[1] as f64,
+                                    }
+                                })
+                                .collect();
+                            let Some(&label) = outline.first() else {
+                                continue;
+                            };
+                            plot_ui.polygon(
+                                Polygon::new(PlotPoints::Owned(outline))
+                                    .fill_color(Color32::from_rgba_unmultiplied(0, 160, 255, 24))
+                                    .stroke(Stroke {
+                                        color: Color32::from_rgb(0, 160, 255),
+                                        width: 1.0,
+                                    }),
+                            );
+                            plot_ui.text(
+                                Text::new(label, zone.name.clone())
+                                    .color(Color32::from_rgb(0, 160, 255))
+                                    .anchor(egui::Align2::LEFT_BOTTOM),
+                            );
+                        }
+
                         for cfg in self.config_widget.config.descriptors.iter() {
-                            if let Some(transform) = cfg.device_descriptor.transform() {
+                            if let Ok(Some(transform)) = self.config_widget.config.sensor_to_world(cfg.id) {
+                                let matrix = self.global_transform.matrix().compose(&transform.matrix());
                                 let rgb = self
                                     .config_widget
                                     .colors
@@ -357,7 +433,7 @@ impl eframe::App for MyApp {
                                                     p[1] *= 0.05;
                                                     p[2] *= 0.05;
 
-                                                    let p = self.global_transform.apply(transform.apply(*p));
+                                                    let p = matrix.apply(*p);
                                                     PlotPoint {
                                                         x: p[0] as f64,
                                                         y: p[1] as f64,
@@ -372,7 +448,7 @@ impl eframe::App for MyApp {
                                     );
                                 }
 
-                                let origin = self.global_transform.apply(transform.apply([0.0, 0.0, 0.0]));
+                                let origin = matrix.apply([0.0, 0.0, 0.0]);
                                 plot_ui.text(
                                     Text::new(
                                         PlotPoint {
@@ -0,0 +1,247 @@
+mod stages;
+
+use async_nats::{
+    connection::State,
+    jetstream::{
+        self,
+        kv::{Entry, Watch},
+    },
+    Client,
+};
+use async_trait::async_trait;
+use egui::Ui;
+use futures::StreamExt;
+use mmwave_core::{
+    address::ServerAddress,
+    config::Configuration,
+    devices::DeviceDescriptor,
+    message::{Id, Tag},
+    nats::get_store,
+    pointcloud::PointCloud,
+    pubsub::{Publisher, Subscription},
+    subject::SubjectFilter,
+    telemetry::DeviceState,
+    wire::Encoding,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    time::Duration,
+};
+use tokio::{select, task::yield_now};
+use tracing::{error, info, instrument, warn};
+
+pub use stages::{Pipeline, Stage};
+
+/// Runs the pointclouds of the source devices through a chain of stages,
+/// republishing each filtered cloud under the filter's own id and the
+/// filtered kind (`mmwave.<m>.<d>.filtered`), so consumers of the raw clouds
+/// do not receive them twice.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct FilterDescriptor {
+    pub sources: Vec<Id>,   // Devices whose pointclouds are filtered
+    pub stages: Vec<Stage>, // Applied in order
+    pub encoding: Encoding, // Encoding of published messages
+}
+
+impl Eq for FilterDescriptor {}
+
+impl Display for FilterDescriptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sources: Vec<String> = self.sources.iter().map(|id| id.to_string()).collect();
+        write!(f, "Filter ({})", sources.join(", "))
+    }
+}
+
+#[typetag::serde]
+#[async_trait]
+impl DeviceDescriptor for FilterDescriptor {
+    #[instrument(skip_all, fields(self=%self, id=%id))]
+    async fn init(self: Box<Self>, id: Id, address: ServerAddress) {
+        if let Err(e) = start_filter(*self, id, address).await {
+            error!(error=?e, "Filter closed with error");
+        }
+    }
+
+    fn clone_boxed(&self) -> Box<dyn DeviceDescriptor> {
+        Box::new(self.clone())
+    }
+
+    fn title(&self) -> String {
+        format!("{}", self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.label("Sources:");
+        let mut removed = None;
+        for (i, source) in self.sources.iter_mut().enumerate() {
+            ui.push_id(("source", i), |ui| {
+                ui.horizontal(|ui| {
+                    source.ui(ui);
+                    if ui.button("remove").clicked() {
+                        removed = Some(i);
+                    }
+                });
+            });
+        }
+        if let Some(i) = removed {
+            self.sources.remove(i);
+        }
+        if ui.button("add source").clicked() {
+            self.sources.push(Id::Device(0, 0));
+        }
+
+        ui.label("Stages:");
+        let mut removed = None;
+        for (i, stage) in self.stages.iter_mut().enumerate() {
+            ui.push_id(("stage", i), |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(stage.name());
+                    if ui.button("remove").clicked() {
+                        removed = Some(i);
+                    }
+                });
+                stage.ui(ui);
+            });
+        }
+        if let Some(i) = removed {
+            self.stages.remove(i);
+        }
+        ui.horizontal(|ui| {
+            for stage in Stage::defaults() {
+                if ui.button(format!("add {}", stage.name())).clicked() {
+                    self.stages.push(stage);
+                }
+            }
+        });
+        self.encoding.ui(ui);
+    }
+}
+
+#[instrument(skip_all)]
+async fn start_filter(
+    mut descriptor: FilterDescriptor,
+    id: Id,
+    address: ServerAddress,
+) -> Result<(), Box<dyn Error>> {
+    // Connect to the NATS server
+    let client = async_nats::connect(address.address().to_string()).await?;
+    let jetstream = jetstream::new(client.clone());
+
+    // Listen for config updates on a separate task
+    let store = get_store(jetstream).await?;
+    let mut entries = store.watch("config").await?;
+
+    let mut interval = tokio::time::interval(Duration::from_millis(5000));
+    loop {
+        // Verify the client connection state
+        if client.connection_state() == State::Disconnected {
+            return Err(String::from("Lost connection to NATS").into());
+        }
+
+        let result = run_filter(&client, &mut entries, &mut descriptor, id)
+            .await
+            .map_err(|e| e.to_string());
+        if let Err(e) = result {
+            error!(error=%e, "Filter stopped running");
+            let publisher = Publisher::new(client.clone(), id);
+            if let Err(e) = publisher.error(descriptor.title(), e).await {
+                warn!(error=%e, "Failed to report filter error");
+            }
+        }
+        interval.tick().await;
+    }
+}
+
+#[instrument(skip_all)]
+async fn run_filter(
+    client: &Client,
+    entries: &mut Watch,
+    descriptor: &mut FilterDescriptor,
+    id: Id,
+) -> Result<(), Box<dyn Error>> {
+    let mut publisher = Publisher::new(client.clone(), id).with_encoding(descriptor.encoding);
+    publisher
+        .status(DeviceState::Running, descriptor.title(), None)
+        .await?;
+    if descriptor.sources.is_empty() {
+        warn!("Filter has no sources");
+    }
+
+    let mut subscription = Subscription::<PointCloud>::new(client, SubjectFilter::all()).await?;
+    // Stages like clutter removal learn from each stream separately
+    let mut pipelines: HashMap<Id, Pipeline> = HashMap::new();
+
+    loop {
+        yield_now().await;
+        select! {
+            Some(config) = entries.next() => {
+                let previous = descriptor.clone();
+                maintain_config(config?, descriptor, id)?;
+                publisher.set_encoding(descriptor.encoding);
+                if descriptor.stages != previous.stages {
+                    info!("Resetting filter pipelines with new stages");
+                    pipelines.clear();
+                }
+            }
+            received = subscription.next() => {
+                let Some(received) = received else {
+                    return Err("Pointcloud subscription closed".into());
+                };
+                let Some(from) = received.from.filter(|from| *from != id && descriptor.sources.contains(from)) else {
+                    continue;
+                };
+                let pipeline = pipelines
+                    .entry(from)
+                    .or_insert_with(|| Pipeline::new(descriptor.stages.clone()));
+                let pointcloud = pipeline.apply(&received.content);
+                publisher.publish_as(pointcloud, Tag::Filtered).await?;
+            }
+        }
+    }
+}
+
+fn maintain_config(
+    entry: Entry,
+    descriptor: &mut FilterDescriptor,
+    id: Id,
+) -> Result<(), Box<dyn Error>> {
+    let Ok(configuration) = serde_json::from_slice::<Configuration>(&entry.value) else {
+        return Ok(());
+    };
+
+    for device_config in configuration.descriptors {
+        if device_config.id != id {
+            continue;
+        }
+
+        let erased_desc = device_config.device_descriptor.as_any();
+
+        let updated_desc = match erased_desc.downcast_ref::<FilterDescriptor>() {
+            Some(filter_desc) => filter_desc,
+            None => {
+                tracing::error!(
+                    "Failed to downcast: actual type id = {:?}, expected type id = {:?}",
+                    erased_desc.type_id(),
+                    TypeId::of::<Box<FilterDescriptor>>()
+                );
+                continue;
+            }
+        };
+
+        if descriptor != updated_desc {
+            info!("Updated filter descriptor");
+            *descriptor = updated_desc.clone();
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,382 @@
+use std::collections::{HashMap, VecDeque};
+
+use egui::{DragValue, Ui};
+use mmwave_core::{pointcloud::PointCloud, spatial::KdTree};
+use serde::{Deserialize, Serialize};
+
+/// One step of a filter chain. Stages run in order, each on the output of the
+/// previous one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Stage {
+    /// Keeps points inside an axis aligned box in the world frame
+    Crop { min: [f32; 3], max: [f32; 3] },
+    /// Keeps points with at least this snr (dB), clouds without snr pass
+    Snr { min: f32 },
+    /// Keeps points with a radial speed between min and max (m/s)
+    Velocity { min: f32, max: f32 },
+    /// Removes stationary points in voxels that were occupied in at least
+    /// `persistence` (0 to 1) of the last `frames` frames
+    StaticClutter {
+        voxel_size: f32,
+        max_speed: f32,
+        frames: usize,
+        persistence: f32,
+    },
+    /// Removes points whose mean distance to their nearest neighbours is more
+    /// than `std_ratio` standard deviations above the cloud's average
+    Outliers { neighbours: usize, std_ratio: f32 },
+    /// Replaces the points in each voxel with their centroid
+    Downsample { voxel_size: f32 },
+}
+
+impl Stage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Crop { .. } => "Crop",
+            Stage::Snr { .. } => "Snr",
+            Stage::Velocity { .. } => "Velocity",
+            Stage::StaticClutter { .. } => "StaticClutter",
+            Stage::Outliers { .. } => "Outliers",
+            Stage::Downsample { .. } => "Downsample",
+        }
+    }
+
+    /// Every kind of stage, with reasonable defaults
+    pub fn defaults() -> Vec<Stage> {
+        vec![
+            Stage::Crop {
+                min: [-5.0, 0.0, -1.0],
+                max: [5.0, 10.0, 3.0],
+            },
+            Stage::Snr { min: 10.0 },
+            Stage::Velocity {
+                min: 0.1,
+                max: 10.0,
+            },
+            Stage::StaticClutter {
+                voxel_size: 0.2,
+                max_speed: 0.05,
+                frames: 50,
+                persistence: 0.8,
+            },
+            Stage::Outliers {
+                neighbours: 4,
+                std_ratio: 2.0,
+            },
+            Stage::Downsample { voxel_size: 0.1 },
+        ]
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui) {
+        fn vector(ui: &mut Ui, label: &str, value: &mut [f32; 3]) {
+            ui.horizontal(|ui| {
+                ui.label(label);
+                for x in value.iter_mut() {
+                    ui.add(DragValue::new(x).speed(0.05));
+                }
+            });
+        }
+        fn scalar(ui: &mut Ui, label: &str, value: &mut f32) {
+            ui.label(label);
+            ui.add(DragValue::new(value).speed(0.01));
+        }
+
+        match self {
+            Stage::Crop { min, max } => {
+                vector(ui, "Min:", min);
+                vector(ui, "Max:", max);
+            }
+            Stage::Snr { min } => {
+                ui.horizontal(|ui| scalar(ui, "Min snr (dB):", min));
+            }
+            Stage::Velocity { min, max } => {
+                ui.horizontal(|ui| {
+                    scalar(ui, "Min speed:", min);
+                    scalar(ui, "Max speed:", max);
+                });
+            }
+            Stage::StaticClutter {
+                voxel_size,
+                max_speed,
+                frames,
+                persistence,
+            } => {
+                ui.horizontal(|ui| {
+                    scalar(ui, "Voxel size:", voxel_size);
+                    scalar(ui, "Max speed:", max_speed);
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Frames:");
+                    ui.add(DragValue::new(frames).clamp_range(1..=1000));
+                    scalar(ui, "Persistence:", persistence);
+                });
+            }
+            Stage::Outliers {
+                neighbours,
+                std_ratio,
+            } => {
+                ui.horizontal(|ui| {
+                    ui.label("Neighbours:");
+                    ui.add(DragValue::new(neighbours).clamp_range(1..=100));
+                    scalar(ui, "Std ratio:", std_ratio);
+                });
+            }
+            Stage::Downsample { voxel_size } => {
+                ui.horizontal(|ui| scalar(ui, "Voxel size:", voxel_size));
+            }
+        }
+    }
+}
+
+/// A chain of stages along with the state they keep between frames. Each
+/// device's stream needs its own pipeline.
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    stages: Vec<Stage>,
+    clutter: HashMap<usize, ClutterHistory>, // By index of the stage
+}
+
+#[derive(Debug, Clone, Default)]
+struct ClutterHistory {
+    frames: VecDeque<Vec<[i32; 3]>>, // Voxels occupied by stationary points in each frame
+    counts: HashMap<[i32; 3], usize>,
+}
+
+impl Pipeline {
+    pub fn new(stages: Vec<Stage>) -> Self {
+        Self {
+            stages,
+            clutter: HashMap::new(),
+        }
+    }
+
+    pub fn apply(&mut self, pointcloud: &PointCloud) -> PointCloud {
+        let mut pointcloud = pointcloud.clone();
+        for (i, stage) in self.stages.iter().enumerate() {
+            pointcloud = match *stage {
+                Stage::Crop { min, max } => retain(&pointcloud, |p, _| {
+                    let p: [f32; 3] = p.into();
+                    (0..3).all(|k| min[k] <= p[k] && p[k] <= max[k])
+                }),
+                Stage::Snr { min } => {
+                    if pointcloud.attributes.snr.len() != pointcloud.points.len() {
+                        continue;
+                    }
+                    retain(&pointcloud, |_, i| pointcloud.attributes.snr[i] >= min)
+                }
+                Stage::Velocity { min, max } => {
+                    retain(&pointcloud, |p, _| min <= p.v.abs() && p.v.abs() <= max)
+                }
+                Stage::StaticClutter {
+                    voxel_size,
+                    max_speed,
+                    frames,
+                    persistence,
+                } => self.clutter.entry(i).or_default().apply(
+                    &pointcloud,
+                    voxel_size,
+                    max_speed,
+                    frames,
+                    persistence,
+                ),
+                Stage::Outliers {
+                    neighbours,
+                    std_ratio,
+                } => remove_outliers(&pointcloud, neighbours, std_ratio),
+                Stage::Downsample { voxel_size } => downsample(&pointcloud, voxel_size),
+            };
+        }
+        pointcloud
+    }
+}
+
+impl ClutterHistory {
+    fn apply(
+        &mut self,
+        pointcloud: &PointCloud,
+        voxel_size: f32,
+        max_speed: f32,
+        frames: usize,
+        persistence: f32,
+    ) -> PointCloud {
+        let stationary = |v: f32| v.abs() <= max_speed;
+        let mut occupied: Vec<[i32; 3]> = pointcloud
+            .points
+            .iter()
+            .filter(|p| stationary(p.v))
+            .map(|&p| voxel(p.into(), voxel_size))
+            .collect();
+        occupied.sort_unstable();
+        occupied.dedup();
+
+        // Judge this frame against the history before adding it
+        let threshold = (persistence * frames as f32).ceil().max(1.0) as usize;
+        let filtered = retain(pointcloud, |p, _| {
+            !stationary(p.v)
+                || self
+                    .counts
+                    .get(&voxel(p.into(), voxel_size))
+                    .copied()
+                    .unwrap_or(0)
+                    < threshold
+        });
+
+        for &v in occupied.iter() {
+            *self.counts.entry(v).or_default() += 1;
+        }
+        self.frames.push_back(occupied);
+        while self.frames.len() > frames {
+            for v in self.frames.pop_front().unwrap_or_default() {
+                if let Some(count) = self.counts.get_mut(&v) {
+                    *count -= 1;
+                    if *count == 0 {
+                        self.counts.remove(&v);
+                    }
+                }
+            }
+        }
+        filtered
+    }
+}
+
+fn voxel(point: [f32; 3], voxel_size: f32) -> [i32; 3] {
+    point.map(|x| (x / voxel_size).floor() as i32)
+}
+
+/// The points (and their attributes) for which keep returns true
+fn retain(
+    pointcloud: &PointCloud,
+    mut keep: impl FnMut(mmwave_core::point::Point, usize) -> bool,
+) -> PointCloud {
+    let indices: Vec<usize> = (0..pointcloud.points.len())
+        .filter(|&i| keep(pointcloud.points[i], i))
+        .collect();
+    pointcloud.select(&indices)
+}
+
+fn remove_outliers(pointcloud: &PointCloud, neighbours: usize, std_ratio: f32) -> PointCloud {
+    let points: Vec<[f32; 3]> = pointcloud.points.iter().map(|&p| p.into()).collect();
+    if neighbours == 0 || points.len() <= neighbours {
+        return pointcloud.clone();
+    }
+    let tree = KdTree::new(&points);
+    let mean_distances: Vec<f32> = points
+        .iter()
+        .enumerate()
+        .map(|(i, &p)| {
+            // The point itself is among the nearest, unless others sit on it
+            tree.nearest(p, neighbours + 1)
+                .into_iter()
+                .filter(|&(j, _)| j != i)
+                .take(neighbours)
+                .map(|(_, d)| d)
+                .sum::<f32>()
+                / neighbours as f32
+        })
+        .collect();
+    let n = mean_distances.len() as f32;
+    let mean = mean_distances.iter().sum::<f32>() / n;
+    let std = (mean_distances
+        .iter()
+        .map(|d| (d - mean).powi(2))
+        .sum::<f32>()
+        / n)
+        .sqrt();
+    retain(pointcloud, |_, i| {
+        mean_distances[i] <= mean + std_ratio * std
+    })
+}
+
+fn downsample(pointcloud: &PointCloud, voxel_size: f32) -> PointCloud {
+    if voxel_size <= 0.0 {
+        return pointcloud.clone();
+    }
+    // The first point in each voxel stands in for the others, keeping its attributes
+    let mut voxels: HashMap<[i32; 3], ([f32; 4], usize)> = HashMap::new();
+    let mut first = Vec::new();
+    for (i, p) in pointcloud.points.iter().enumerate() {
+        let key = voxel((*p).into(), voxel_size);
+        let (sum, count) = voxels.entry(key).or_insert_with(|| {
+            first.push((i, key));
+            ([0.0; 4], 0)
+        });
+        *sum = [sum[0] + p.x, sum[1] + p.y, sum[2] + p.z, sum[3] + p.v];
+        *count += 1;
+    }
+    let indices: Vec<usize> = first.iter().map(|&(i, _)| i).collect();
+    let mut downsampled = pointcloud.select(&indices);
+    for (point, (_, key)) in downsampled.points.iter_mut().zip(&first) {
+        let (sum, count) = voxels[key];
+        *point = sum.map(|x| x / count as f32).into();
+    }
+    downsampled
+}
+
+#[cfg(test)]
+mod tests {
+    use mmwave_core::pointcloud::PointCloud;
+
+    use super::{Pipeline, Stage};
+
+    fn cloud(points: &[[f32; 4]]) -> PointCloud {
+        PointCloud::from(points.iter().map(|&p| p.into()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    pub fn test_crop_snr_velocity() {
+        let mut pointcloud = cloud(&[
+            [0.0, 1.0, 0.0, 1.0],
+            [0.0, 20.0, 0.0, 1.0],
+            [0.0, 2.0, 0.0, 0.0],
+            [0.0, 3.0, 0.0, -2.0],
+        ]);
+        pointcloud.attributes.snr = vec![20.0, 20.0, 20.0, 5.0];
+        let mut pipeline = Pipeline::new(vec![
+            Stage::Crop {
+                min: [-1.0, 0.0, -1.0],
+                max: [1.0, 10.0, 1.0],
+            },
+            Stage::Snr { min: 10.0 },
+            Stage::Velocity { min: 0.1, max: 5.0 },
+        ]);
+        let filtered = pipeline.apply(&pointcloud);
+        assert_eq!(filtered.points.len(), 1);
+        assert_eq!(filtered.attributes.snr, vec![20.0]);
+    }
+
+    #[test]
+    pub fn test_static_clutter() {
+        let mut pipeline = Pipeline::new(vec![Stage::StaticClutter {
+            voxel_size: 0.5,
+            max_speed: 0.1,
+            frames: 4,
+            persistence: 0.5,
+        }]);
+        let pointcloud = cloud(&[[1.0, 1.0, 0.0, 0.0], [2.0, 2.0, 0.0, 1.0]]);
+        assert_eq!(pipeline.apply(&pointcloud).points.len(), 2);
+        assert_eq!(pipeline.apply(&pointcloud).points.len(), 2);
+        // The stationary point has now been seen in 2 of the last 4 frames
+        assert_eq!(pipeline.apply(&pointcloud).points.len(), 1);
+    }
+
+    #[test]
+    pub fn test_outliers_and_downsample() {
+        let pointcloud = cloud(&[
+            [0.0, 1.0, 0.0, 0.0],
+            [0.1, 1.0, 0.0, 0.0],
+            [0.0, 1.1, 0.0, 0.0],
+            [0.1, 1.1, 0.0, 0.0],
+            [8.0, 8.0, 0.0, 0.0],
+        ]);
+        let mut pipeline = Pipeline::new(vec![Stage::Outliers {
+            neighbours: 2,
+            std_ratio: 1.0,
+        }]);
+        assert_eq!(pipeline.apply(&pointcloud).points.len(), 4);
+
+        let mut pipeline = Pipeline::new(vec![Stage::Downsample { voxel_size: 1.0 }]);
+        let downsampled = pipeline.apply(&pointcloud);
+        assert_eq!(downsampled.points.len(), 2);
+        assert!((downsampled.points[0].x - 0.05).abs() < 1e-6);
+    }
+}
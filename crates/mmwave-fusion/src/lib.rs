@@ -0,0 +1,302 @@
+use async_nats::{
+    connection::State,
+    jetstream::{
+        self,
+        kv::{Entry, Watch},
+    },
+    Client,
+};
+use async_trait::async_trait;
+use chrono::Utc;
+use egui::Ui;
+use futures::StreamExt;
+use mmwave_core::{
+    accumulator::{Accumulator, LatePolicy},
+    address::ServerAddress,
+    config::Configuration,
+    devices::DeviceDescriptor,
+    message::{Id, Tag},
+    nats::get_store,
+    pointcloud::PointCloud,
+    pubsub::{Publisher, Subscription},
+    subject::SubjectFilter,
+    telemetry::DeviceState,
+    wire::Encoding,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    time::Duration,
+};
+use tokio::{select, task::yield_now};
+use tracing::{error, info, instrument, warn};
+
+/// Fuses the pointclouds of every device into one world frame pointcloud per
+/// window, published under the fused kind (`mmwave.<m>.<d>.fused`).
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FusionDescriptor {
+    pub window_ms: u64,     // Length of the windows clouds are fused over
+    pub latency_ms: u64,    // How long to wait for clouds after a window ends
+    pub late: LatePolicy,   // What to do with clouds arriving after that
+    pub voxel_size: f32,    // Voxel size in metres for deduplication, 0 to disable
+    pub encoding: Encoding, // Encoding of published messages
+}
+
+impl Default for FusionDescriptor {
+    fn default() -> Self {
+        Self {
+            window_ms: 100,
+            latency_ms: 50,
+            late: LatePolicy::default(),
+            voxel_size: 0.1,
+            encoding: Encoding::default(),
+        }
+    }
+}
+
+impl Eq for FusionDescriptor {}
+
+impl Display for FusionDescriptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Fusion ({}ms)", self.window_ms)
+    }
+}
+
+impl FusionDescriptor {
+    fn accumulator(&self) -> Accumulator {
+        Accumulator::new(
+            chrono::Duration::milliseconds(self.window_ms as i64),
+            chrono::Duration::milliseconds(self.latency_ms as i64),
+        )
+        .with_late_policy(self.late)
+    }
+}
+
+#[typetag::serde]
+#[async_trait]
+impl DeviceDescriptor for FusionDescriptor {
+    #[instrument(skip_all, fields(self=%self, id=%id))]
+    async fn init(self: Box<Self>, id: Id, address: ServerAddress) {
+        if let Err(e) = start_fusion(*self, id, address).await {
+            error!(error=?e, "Fusion closed with error");
+        }
+    }
+
+    fn clone_boxed(&self) -> Box<dyn DeviceDescriptor> {
+        Box::new(self.clone())
+    }
+
+    fn title(&self) -> String {
+        format!("{}", self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Window (ms):");
+            ui.add(egui::DragValue::new(&mut self.window_ms).clamp_range(1..=10000));
+            ui.label("Latency (ms):");
+            ui.add(egui::DragValue::new(&mut self.latency_ms).clamp_range(0..=10000));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Late clouds:");
+            egui::ComboBox::from_id_source(ui.make_persistent_id("fusion_late"))
+                .selected_text(format!("{:?}", self.late))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.late, LatePolicy::Drop, "Drop");
+                    ui.selectable_value(&mut self.late, LatePolicy::NextWindow, "NextWindow");
+                    ui.selectable_value(&mut self.late, LatePolicy::Alone, "Alone");
+                });
+        });
+        ui.horizontal(|ui| {
+            ui.label("Voxel size (m):");
+            ui.add(
+                egui::DragValue::new(&mut self.voxel_size)
+                    .speed(0.01)
+                    .clamp_range(0.0..=10.0),
+            );
+        });
+        self.encoding.ui(ui);
+    }
+}
+
+#[instrument(skip_all)]
+async fn start_fusion(
+    mut descriptor: FusionDescriptor,
+    id: Id,
+    address: ServerAddress,
+) -> Result<(), Box<dyn Error>> {
+    // Connect to the NATS server
+    let client = async_nats::connect(address.address().to_string()).await?;
+    let jetstream = jetstream::new(client.clone());
+
+    // Listen for config updates on a separate task
+    let store = get_store(jetstream).await?;
+    let mut entries = store.watch("config").await?;
+
+    let mut interval = tokio::time::interval(Duration::from_millis(5000));
+    loop {
+        // Verify the client connection state
+        if client.connection_state() == State::Disconnected {
+            return Err(String::from("Lost connection to NATS").into());
+        }
+
+        let result = run_fusion(&client, &mut entries, &mut descriptor, id)
+            .await
+            .map_err(|e| e.to_string());
+        if let Err(e) = result {
+            error!(error=%e, "Fusion stopped running");
+            let publisher = Publisher::new(client.clone(), id);
+            if let Err(e) = publisher.error(descriptor.title(), e).await {
+                warn!(error=%e, "Failed to report fusion error");
+            }
+        }
+        interval.tick().await;
+    }
+}
+
+#[instrument(skip_all)]
+async fn run_fusion(
+    client: &Client,
+    entries: &mut Watch,
+    descriptor: &mut FusionDescriptor,
+    id: Id,
+) -> Result<(), Box<dyn Error>> {
+    let mut publisher = Publisher::new(client.clone(), id).with_encoding(descriptor.encoding);
+    publisher
+        .status(DeviceState::Running, descriptor.title(), None)
+        .await?;
+
+    // Fused clouds are published under their own kind, so this never sees them
+    let mut subscription = Subscription::<PointCloud>::new(client, SubjectFilter::all()).await?;
+    let mut accumulator = descriptor.accumulator();
+    let mut interval = tokio::time::interval(Duration::from_millis(descriptor.window_ms.max(1)));
+
+    loop {
+        yield_now().await;
+        select! {
+            Some(config) = entries.next() => {
+                let previous = descriptor.clone();
+                maintain_config(config?, descriptor, id)?;
+                publisher.set_encoding(descriptor.encoding);
+                if (descriptor.window_ms, descriptor.latency_ms, descriptor.late)
+                    != (previous.window_ms, previous.latency_ms, previous.late)
+                {
+                    info!("Restarting fusion with new windows");
+                    return Ok(());
+                }
+            }
+            received = subscription.next() => {
+                let Some(received) = received else {
+                    return Err("Pointcloud subscription closed".into());
+                };
+                if let Some(from) = received.from.filter(|&from| from != id) {
+                    if !accumulator.push(Utc::now(), from, received.content) {
+                        warn!(%from, dropped = accumulator.dropped(), "Dropped late or future pointcloud");
+                    }
+                }
+            }
+            _ = interval.tick() => {
+                for pointcloud in accumulator.pop_ready(Utc::now()) {
+                    let pointcloud = deduplicate(&pointcloud, descriptor.voxel_size);
+                    publisher.publish_as(pointcloud, Tag::Fused).await?;
+                }
+            }
+        }
+    }
+}
+
+/// Removes points seen by more than one device. Where the points of several
+/// devices fall in the same voxel, only those of the device with the strongest
+/// return there (or the first seen, without snr) are kept.
+pub fn deduplicate(pointcloud: &PointCloud, voxel_size: f32) -> PointCloud {
+    if voxel_size <= 0.0 || pointcloud.attributes.source.len() != pointcloud.points.len() {
+        return pointcloud.clone();
+    }
+    let voxel = |i: usize| {
+        let point = pointcloud.points[i];
+        [point.x, point.y, point.z].map(|x| (x / voxel_size).floor() as i32)
+    };
+    let strength = |i: usize| pointcloud.attributes.snr.get(i).copied().unwrap_or(0.0);
+
+    let mut owners: HashMap<[i32; 3], (Id, f32)> = HashMap::new();
+    for (i, &source) in pointcloud.attributes.source.iter().enumerate() {
+        let owner = owners.entry(voxel(i)).or_insert((source, strength(i)));
+        if strength(i) > owner.1 {
+            *owner = (source, strength(i));
+        }
+    }
+    let keep: Vec<usize> = (0..pointcloud.points.len())
+        .filter(|&i| owners[&voxel(i)].0 == pointcloud.attributes.source[i])
+        .collect();
+    pointcloud.select(&keep)
+}
+
+fn maintain_config(
+    entry: Entry,
+    descriptor: &mut FusionDescriptor,
+    id: Id,
+) -> Result<(), Box<dyn Error>> {
+    let Ok(configuration) = serde_json::from_slice::<Configuration>(&entry.value) else {
+        return Ok(());
+    };
+
+    for device_config in configuration.descriptors {
+        if device_config.id != id {
+            continue;
+        }
+
+        let erased_desc = device_config.device_descriptor.as_any();
+
+        let updated_desc = match erased_desc.downcast_ref::<FusionDescriptor>() {
+            Some(fusion_desc) => fusion_desc,
+            None => {
+                tracing::error!(
+                    "Failed to downcast: actual type id = {:?}, expected type id = {:?}",
+                    erased_desc.type_id(),
+                    TypeId::of::<Box<FusionDescriptor>>()
+                );
+                continue;
+            }
+        };
+
+        if descriptor != updated_desc {
+            info!("Updated fusion descriptor");
+            *descriptor = updated_desc.clone();
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use mmwave_core::{message::Id, pointcloud::PointCloud};
+
+    use super::deduplicate;
+
+    #[test]
+    pub fn test_deduplicate() {
+        let (a, b) = (Id::Device(1, 0), Id::Device(2, 0));
+        let mut pointcloud = PointCloud::from(vec![
+            [1.01, 2.01, 0.01, 0.0].into(),
+            [1.02, 2.02, 0.02, 0.0].into(),
+            [1.03, 2.03, 0.03, 0.0].into(),
+            [4.0, 2.0, 0.0, 0.0].into(),
+        ]);
+        pointcloud.attributes.source = vec![a, a, b, b];
+        pointcloud.attributes.snr = vec![10.0, 12.0, 20.0, 5.0];
+
+        let deduplicated = deduplicate(&pointcloud, 0.1);
+        assert_eq!(deduplicated.attributes.source, vec![b, b]);
+        assert_eq!(deduplicated.attributes.snr, vec![20.0, 5.0]);
+        assert_eq!(deduplicate(&pointcloud, 0.0).points.len(), 4);
+    }
+}
@@ -15,15 +15,34 @@ use mmwave_core::{
     logging::enable_tracing,
     message::Id,
     nats::get_store,
+    pubsub::Publisher,
+    telemetry::Heartbeat,
 };
+use mmwave_cluster::ClusterDescriptor;
+use mmwave_filter::FilterDescriptor;
+use mmwave_fusion::FusionDescriptor;
 use mmwave_recorder::RecordingDescriptor;
+use mmwave_tracker::TrackerDescriptor;
+use mmwave_occupancy::OccupancyDescriptor;
+use mmwave_zones::ZoneDescriptor;
+use mmwave_posture::PostureDescriptor;
+use mmwave_classification::ClassificationDescriptor;
 use std::{
     collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
     task::Context,
 };
-use std::{error::Error, time::Duration};
+use std::{
+    error::Error,
+    time::{Duration, Instant},
+};
 use tokio::{signal, sync::watch, task::JoinHandle};
-use tracing::{debug, error, info, instrument, Instrument};
+use tracing::{debug, error, info, instrument, warn, Instrument};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
@@ -51,12 +70,13 @@ async fn main() -> Result<(), Box<dyn Error>> {
 async fn handle_nats(address: ServerAddress, args: Args) -> Result<(), Box<dyn Error>> {
     // Connect to the NATS server
     let client = async_nats::connect(address.address().to_string()).await?;
-    let jetstream = jetstream::new(client);
+    let jetstream = jetstream::new(client.clone());
 
     let store = get_store(jetstream).await?;
 
     // Create a hashset of devices
     let mut devices: HashMap<DeviceConfig, JoinHandle<()>> = HashMap::new();
+    let device_count = Arc::new(AtomicUsize::new(0));
 
     if let Some(config) = store.get("config").await? {
         info!("Found initial config");
@@ -64,6 +84,7 @@ async fn handle_nats(address: ServerAddress, args: Args) -> Result<(), Box<dyn E
         match serde_json::from_slice(&config) {
             Ok(config) => {
                 update_devices(&mut devices, config, address.clone(), args.clone());
+                device_count.store(devices.len(), Ordering::Relaxed);
             }
             Err(e) => {
                 error!(error=?e, "Failed to parse config");
@@ -75,6 +96,11 @@ async fn handle_nats(address: ServerAddress, args: Args) -> Result<(), Box<dyn E
     let (shutdown_tx, mut shutdown_rx) = watch::channel(());
     let mut entries = store.watch("config").await?;
 
+    let heartbeat_task = tokio::spawn(send_heartbeats(
+        Publisher::new(client, args.machine_id.to_machine()),
+        device_count.clone(),
+    ));
+
     let mut config_task = tokio::spawn(async move {
         info!("Watching for config updates");
         while let Some(config) = entries.next().await {
@@ -95,6 +121,7 @@ async fn handle_nats(address: ServerAddress, args: Args) -> Result<(), Box<dyn E
                         }
                     };
                     update_devices(&mut devices, config, address.clone(), args.clone());
+                    device_count.store(devices.len(), Ordering::Relaxed);
                 }
             }
         }
@@ -117,11 +144,30 @@ async fn handle_nats(address: ServerAddress, args: Args) -> Result<(), Box<dyn E
 
     info!("Shutting down gracefully");
 
+    heartbeat_task.abort();
+
     shutdown_task.await?;
 
     Ok(())
 }
 
+/// Lets consumers know this machine is alive and how many devices it runs
+async fn send_heartbeats(publisher: Publisher, device_count: Arc<AtomicUsize>) {
+    let started = Instant::now();
+    let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+    for sequence in 0.. {
+        interval.tick().await;
+        let heartbeat = Heartbeat {
+            sequence,
+            uptime: started.elapsed(),
+            devices: device_count.load(Ordering::Relaxed),
+        };
+        if let Err(e) = publisher.publish(heartbeat).await {
+            warn!(error=%e, "Failed to send heartbeat");
+        }
+    }
+}
+
 fn update_devices(
     devices: &mut HashMap<DeviceConfig, JoinHandle<()>>,
     config: Configuration,
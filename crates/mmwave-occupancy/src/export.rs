@@ -0,0 +1,86 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use mmwave_core::telemetry::OccupancyGrid;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ExportError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("PNG encoding error: {0}")]
+    Png(#[from] png::EncodingError),
+}
+
+/// Writes every cell as a row of its centre and value
+pub fn write_csv(grid: &OccupancyGrid, writer: impl Write) -> Result<(), ExportError> {
+    let mut writer = BufWriter::new(writer);
+    writeln!(writer, "x,y,z,value")?;
+    for (i, value) in grid.values.iter().enumerate() {
+        let [x, y, z] = grid.centre(i);
+        writeln!(writer, "{},{},{},{}", x, y, z, value)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes a top down greyscale heatmap, one pixel per cell, brightest where
+/// the most was seen. North (positive y) is up.
+pub fn write_png(grid: &OccupancyGrid, writer: impl Write) -> Result<(), ExportError> {
+    let [width, height, _] = grid.shape;
+    let projected = grid.project();
+    let max = projected.iter().copied().fold(f32::MIN_POSITIVE, f32::max);
+    let mut pixels = Vec::with_capacity(projected.len());
+    for row in projected.chunks(width.max(1) as usize).rev() {
+        pixels.extend(row.iter().map(|&value| (value / max * 255.0).round() as u8));
+    }
+
+    let mut encoder = png::Encoder::new(BufWriter::new(writer), width, height);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&pixels)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Writes the grid to `<path>.csv` and `<path>.png`
+pub fn export(grid: &OccupancyGrid, path: impl AsRef<Path>) -> Result<(), ExportError> {
+    let path = path.as_ref();
+    write_csv(grid, File::create(path.with_extension("csv"))?)?;
+    write_png(grid, File::create(path.with_extension("png"))?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use mmwave_core::telemetry::OccupancyGrid;
+
+    use super::{write_csv, write_png};
+
+    #[test]
+    pub fn test_export() {
+        let mut grid = OccupancyGrid::new([0.0; 3], [1.0, 1.0, 2.0], [3, 2, 1]);
+        grid.add([0.5, 1.5, 0.0], 2.0);
+        grid.add([2.5, 0.5, 0.0], 1.0);
+
+        let mut csv = Vec::new();
+        write_csv(&grid, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 7);
+        assert_eq!(lines[0], "x,y,z,value");
+        assert_eq!(lines[3], "2.5,0.5,1,1");
+
+        let mut png = Vec::new();
+        write_png(&grid, &mut png).unwrap();
+        let mut reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        // The top row is the highest y
+        assert_eq!(pixels, vec![255, 0, 0, 0, 0, 128]);
+    }
+}
@@ -0,0 +1,378 @@
+mod export;
+
+use async_nats::{
+    connection::State,
+    jetstream::{
+        self,
+        kv::{Entry, Watch},
+    },
+    Client, SubscribeError,
+};
+use async_trait::async_trait;
+use egui::Ui;
+use futures::StreamExt;
+use mmwave_core::{
+    address::ServerAddress,
+    config::Configuration,
+    devices::DeviceDescriptor,
+    message::{Id, Tag},
+    nats::get_store,
+    pointcloud::PointCloud,
+    pubsub::{Content, Publisher, Received, Subscription},
+    subject::SubjectFilter,
+    telemetry::{DeviceState, OccupancyGrid, TrackedObjects},
+    wire::Encoding,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    any::{Any, TypeId},
+    error::Error,
+    fmt::Display,
+    time::{Duration, Instant},
+};
+use tokio::{
+    select,
+    task::{spawn_blocking, yield_now, JoinHandle},
+};
+use tracing::{error, info, instrument, warn};
+
+pub use export::{export, write_csv, write_png, ExportError};
+
+/// What is accumulated into the grid
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize, Default)]
+pub enum OccupancyInput {
+    #[default]
+    Pointclouds, // Points of ordinary devices
+    Fused,    // Points of fusion devices
+    Filtered, // Points of filter devices
+    Tracks,   // Positions of tracks, one per update
+}
+
+/// Accumulates world frame points (or tracks) into an occupancy grid that
+/// decays over time, publishing a snapshot of it periodically.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OccupancyDescriptor {
+    pub sources: Vec<Id>,      // Devices to accumulate, all of them if empty
+    pub input: OccupancyInput, // What to accumulate from them
+    pub origin: [f32; 3],      // World frame position of the minimum corner of the grid
+    pub cell_size: [f32; 3],   // Edge lengths of a cell in metres, a tall z makes a 2D grid
+    pub shape: [u32; 3],       // Cells along x, y and z
+    pub half_life_s: f32,      // Time for the grid to decay to half, 0 to never decay
+    pub snapshot_ms: u64,      // Time between published snapshots
+    pub export_path: String,   // Snapshots are written to <path>.csv and <path>.png, if set
+    pub encoding: Encoding,    // Encoding of published messages
+}
+
+impl Default for OccupancyDescriptor {
+    fn default() -> Self {
+        Self {
+            sources: Vec::new(),
+            input: OccupancyInput::default(),
+            origin: [-5.0, 0.0, -1.0],
+            cell_size: [0.2, 0.2, 4.0],
+            shape: [50, 50, 1],
+            half_life_s: 600.0,
+            snapshot_ms: 1000,
+            export_path: String::new(),
+            encoding: Encoding::default(),
+        }
+    }
+}
+
+impl Eq for OccupancyDescriptor {}
+
+impl Display for OccupancyDescriptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let [x, y, z] = self.shape;
+        write!(f, "Occupancy ({:?}, {}x{}x{})", self.input, x, y, z)
+    }
+}
+
+impl OccupancyDescriptor {
+    fn grid(&self) -> OccupancyGrid {
+        OccupancyGrid::new(self.origin, self.cell_size, self.shape)
+    }
+
+    fn half_life(&self) -> chrono::Duration {
+        chrono::Duration::milliseconds((self.half_life_s * 1000.0) as i64)
+    }
+}
+
+#[typetag::serde]
+#[async_trait]
+impl DeviceDescriptor for OccupancyDescriptor {
+    #[instrument(skip_all, fields(self=%self, id=%id))]
+    async fn init(self: Box<Self>, id: Id, address: ServerAddress) {
+        if let Err(e) = start_occupancy(*self, id, address).await {
+            error!(error=?e, "Occupancy closed with error");
+        }
+    }
+
+    fn clone_boxed(&self) -> Box<dyn DeviceDescriptor> {
+        Box::new(self.clone())
+    }
+
+    fn title(&self) -> String {
+        format!("{}", self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.label("Sources:");
+        let mut removed = None;
+        for (i, source) in self.sources.iter_mut().enumerate() {
+            ui.push_id(("source", i), |ui| {
+                ui.horizontal(|ui| {
+                    source.ui(ui);
+                    if ui.button("remove").clicked() {
+                        removed = Some(i);
+                    }
+                });
+            });
+        }
+        if let Some(i) = removed {
+            self.sources.remove(i);
+        }
+        if ui.button("add source").clicked() {
+            self.sources.push(Id::Device(0, 0));
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Input:");
+            egui::ComboBox::from_id_source(ui.make_persistent_id("occupancy_input"))
+                .selected_text(format!("{:?}", self.input))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(
+                        &mut self.input,
+                        OccupancyInput::Pointclouds,
+                        "Pointclouds",
+                    );
+                    ui.selectable_value(&mut self.input, OccupancyInput::Fused, "Fused");
+                    ui.selectable_value(&mut self.input, OccupancyInput::Filtered, "Filtered");
+                    ui.selectable_value(&mut self.input, OccupancyInput::Tracks, "Tracks");
+                });
+        });
+        ui.horizontal(|ui| {
+            ui.label("Origin (m):");
+            for x in self.origin.iter_mut() {
+                ui.add(egui::DragValue::new(x).speed(0.1));
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Cell size (m):");
+            for x in self.cell_size.iter_mut() {
+                ui.add(
+                    egui::DragValue::new(x)
+                        .speed(0.01)
+                        .clamp_range(0.01..=100.0),
+                );
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Cells:");
+            for n in self.shape.iter_mut() {
+                ui.add(egui::DragValue::new(n).clamp_range(1..=1000));
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Half life (s):");
+            ui.add(
+                egui::DragValue::new(&mut self.half_life_s)
+                    .speed(1.0)
+                    .clamp_range(0.0..=f32::MAX),
+            );
+            ui.label("Snapshot (ms):");
+            ui.add(egui::DragValue::new(&mut self.snapshot_ms).clamp_range(100..=600000));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Export path:");
+            ui.text_edit_singleline(&mut self.export_path);
+        });
+        self.encoding.ui(ui);
+    }
+}
+
+#[instrument(skip_all)]
+async fn start_occupancy(
+    mut descriptor: OccupancyDescriptor,
+    id: Id,
+    address: ServerAddress,
+) -> Result<(), Box<dyn Error>> {
+    // Connect to the NATS server
+    let client = async_nats::connect(address.address().to_string()).await?;
+    let jetstream = jetstream::new(client.clone());
+
+    // Listen for config updates on a separate task
+    let store = get_store(jetstream).await?;
+    let mut entries = store.watch("config").await?;
+
+    let mut interval = tokio::time::interval(Duration::from_millis(5000));
+    loop {
+        // Verify the client connection state
+        if client.connection_state() == State::Disconnected {
+            return Err(String::from("Lost connection to NATS").into());
+        }
+
+        let result = run_occupancy(&client, &mut entries, &mut descriptor, id)
+            .await
+            .map_err(|e| e.to_string());
+        if let Err(e) = result {
+            error!(error=%e, "Occupancy stopped running");
+            let publisher = Publisher::new(client.clone(), id);
+            if let Err(e) = publisher.error(descriptor.title(), e).await {
+                warn!(error=%e, "Failed to report occupancy error");
+            }
+        }
+        interval.tick().await;
+    }
+}
+
+#[instrument(skip_all)]
+async fn run_occupancy(
+    client: &Client,
+    entries: &mut Watch,
+    descriptor: &mut OccupancyDescriptor,
+    id: Id,
+) -> Result<(), Box<dyn Error>> {
+    let mut publisher = Publisher::new(client.clone(), id).with_encoding(descriptor.encoding);
+    publisher
+        .status(DeviceState::Running, descriptor.title(), None)
+        .await?;
+
+    let (mut tracks, mut points) = subscribe(client, descriptor.input).await?;
+    let mut grid = descriptor.grid();
+    let mut decayed = Instant::now();
+    let mut exporting: Option<JoinHandle<()>> = None;
+    let mut interval = tokio::time::interval(Duration::from_millis(descriptor.snapshot_ms.max(1)));
+
+    loop {
+        yield_now().await;
+        select! {
+            Some(config) = entries.next() => {
+                let previous = descriptor.clone();
+                maintain_config(config?, descriptor, id)?;
+                publisher.set_encoding(descriptor.encoding);
+                if (descriptor.input, descriptor.origin, descriptor.cell_size, descriptor.shape)
+                    != (previous.input, previous.origin, previous.cell_size, previous.shape)
+                {
+                    info!("Resetting occupancy grid with new geometry");
+                    grid = descriptor.grid();
+                }
+                if descriptor.input != previous.input {
+                    (tracks, points) = subscribe(client, descriptor.input).await?;
+                }
+                if descriptor.snapshot_ms != previous.snapshot_ms {
+                    interval = tokio::time::interval(Duration::from_millis(descriptor.snapshot_ms.max(1)));
+                }
+            }
+            received = next(&mut points) => {
+                let Some(received) = received else {
+                    return Err(format!("{:?} subscription closed", descriptor.input).into());
+                };
+                if is_source(descriptor, received.from, id) {
+                    grid.add_pointcloud(&received.content);
+                }
+            }
+            received = next(&mut tracks) => {
+                let Some(received) = received else {
+                    return Err("Tracks subscription closed".into());
+                };
+                if is_source(descriptor, received.from, id) {
+                    grid.add_tracks(received.timestamp, &received.content);
+                }
+            }
+            _ = interval.tick() => {
+                let elapsed = chrono::Duration::from_std(decayed.elapsed()).unwrap_or_default();
+                grid.decay(elapsed, descriptor.half_life());
+                decayed = Instant::now();
+                // Writing the files blocks, so it happens off the async workers,
+                // skipping snapshots while the previous one is still being written
+                let idle = exporting.as_ref().is_none_or(|export| export.is_finished());
+                if !descriptor.export_path.is_empty() && idle {
+                    let (snapshot, path) = (grid.clone(), descriptor.export_path.clone());
+                    exporting = Some(spawn_blocking(move || {
+                        if let Err(e) = export(&snapshot, &path) {
+                            warn!(error=%e, path=%path, "Failed to export occupancy grid");
+                        }
+                    }));
+                }
+                publisher.publish(grid.clone()).await?;
+            }
+        }
+    }
+}
+
+/// Subscriptions to the tracks or the points of an input, never both
+type Inputs = (
+    Option<Subscription<TrackedObjects>>,
+    Option<Subscription<PointCloud>>,
+);
+
+async fn subscribe(client: &Client, input: OccupancyInput) -> Result<Inputs, SubscribeError> {
+    let filter = SubjectFilter::all();
+    let kind = match input {
+        OccupancyInput::Tracks => {
+            return Ok((Some(Subscription::new(client, filter).await?), None))
+        }
+        OccupancyInput::Pointclouds => Tag::Pointcloud,
+        OccupancyInput::Fused => Tag::Fused,
+        OccupancyInput::Filtered => Tag::Filtered,
+    };
+    let points = Subscription::with_kind(client, filter, kind).await?;
+    Ok((None, Some(points)))
+}
+
+/// The next message of a subscription, never if there is none
+async fn next<T: Content>(subscription: &mut Option<Subscription<T>>) -> Option<Received<T>> {
+    match subscription {
+        Some(subscription) => subscription.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+fn is_source(descriptor: &OccupancyDescriptor, from: Option<Id>, id: Id) -> bool {
+    from.is_some_and(|from| {
+        from != id && (descriptor.sources.is_empty() || descriptor.sources.contains(&from))
+    })
+}
+
+fn maintain_config(
+    entry: Entry,
+    descriptor: &mut OccupancyDescriptor,
+    id: Id,
+) -> Result<(), Box<dyn Error>> {
+    let Ok(configuration) = serde_json::from_slice::<Configuration>(&entry.value) else {
+        return Ok(());
+    };
+
+    for device_config in configuration.descriptors {
+        if device_config.id != id {
+            continue;
+        }
+
+        let erased_desc = device_config.device_descriptor.as_any();
+
+        let updated_desc = match erased_desc.downcast_ref::<OccupancyDescriptor>() {
+            Some(occupancy_desc) => occupancy_desc,
+            None => {
+                tracing::error!(
+                    "Failed to downcast: actual type id = {:?}, expected type id = {:?}",
+                    erased_desc.type_id(),
+                    TypeId::of::<Box<OccupancyDescriptor>>()
+                );
+                continue;
+            }
+        };
+
+        if descriptor != updated_desc {
+            info!("Updated occupancy descriptor");
+            *descriptor = updated_desc.clone();
+        }
+    }
+
+    Ok(())
+}
@@ -14,11 +14,14 @@ use mmwave_core::{
     address::ServerAddress,
     config::Configuration,
     devices::DeviceDescriptor,
-    message::{Id, Message, MessageContent, Tag, TagsToSubject},
-    nats::get_store,
+    message::Id,
+    nats::{get_config, get_store},
     point::Point,
     pointcloud::PointCloud,
+    pubsub::Publisher,
+    telemetry::DeviceState,
     transform::Transform,
+    wire::Encoding,
 };
 use serde::{Deserialize, Deserializer, Serialize};
 use std::{
@@ -37,6 +40,7 @@ pub struct PlaybackDescriptor {
     pub file_path: String,   // Path to the file from which data will be read
     pub label_filter: String, // Label filter for playback
     pub transform: Transform, // Transform of this Playback device
+    pub encoding: Encoding,   // Encoding of published messages
 }
 
 #[derive(Deserialize)]
@@ -44,6 +48,8 @@ struct PlaybackDescriptorHelper {
     file_path: String,
     label_filter: String,
     transform: Transform,
+    #[serde(default)]
+    encoding: Encoding,
 }
 
 impl Eq for PlaybackDescriptor {}
@@ -71,6 +77,7 @@ impl<'de> Deserialize<'de> for PlaybackDescriptor {
             file_path: helper.file_path,
             label_filter: helper.label_filter,
             transform: helper.transform,
+            encoding: helper.encoding,
         })
     }
 }
@@ -107,12 +114,17 @@ impl DeviceDescriptor for PlaybackDescriptor {
             ui.text_edit_singleline(&mut self.label_filter);
         });
         self.transform.ui(ui);
+        self.encoding.ui(ui);
     }
 
     fn transform(&self) -> Option<Transform> {
         Some(self.transform.clone())
     }
 
+    fn set_transform(&mut self, transform: Transform) {
+        self.transform = transform;
+    }
+
     fn position(&self) -> Option<Point> {
         Some(self.transform.apply([0.0, 0.0, 0.0].into()).into())
     }
@@ -139,7 +151,7 @@ async fn start_playback(
             return Err(String::from("Lost connection to NATS").into());
         }
 
-        if let Err(e) = run_playback(
+        let result = run_playback(
             &client,
             &store,
             &mut entries,
@@ -147,8 +159,13 @@ async fn start_playback(
             id,
         )
         .await
-        {
+        .map_err(|e| e.to_string());
+        if let Err(e) = result {
             error!(error=%e, "Playback stopped running");
+            let publisher = Publisher::new(client.clone(), id);
+            if let Err(e) = publisher.error(descriptor.title(), e).await {
+                warn!(error=%e, "Failed to report playback error");
+            }
         }
         interval.tick().await;
     }
@@ -175,12 +192,25 @@ async fn run_playback(
     let time_started = Utc::now();
     let mut ptc_time_started = None;
 
+    let mut publisher = Publisher::new(client.clone(), id).with_encoding(descriptor.encoding);
+    publisher
+        .status(DeviceState::Running, descriptor.title(), None)
+        .await?;
+
+    // Sensor to world, resolved through the configured frame tree
+    let configuration = get_config(store).await?;
+    let mut transform = match &configuration {
+        Some(configuration) => configuration.sensor_to_world_or(id, &descriptor.transform),
+        None => descriptor.transform.clone(),
+    };
+    let mut floor = configuration.map(|c| c.floor).unwrap_or_default();
+
     let mut interval = tokio::time::interval(Duration::from_millis(10));
     for pointcloud in json_array {
         if ptc_time_started == None {
             ptc_time_started = Some(pointcloud.time);
         }
-        if (pointcloud.labels.iter().any(|label| {label.contains(&descriptor.label_filter)}) && descriptor.label_filter != "") || (pointcloud.labels.len() == 0 && descriptor.label_filter == "") {
+        if matches_filter(&pointcloud, &descriptor.label_filter) {
             let time_passed = Utc::now() - time_started;
             let ptc_time = pointcloud.time;
             let ptc_time_passed = ptc_time - ptc_time_started.unwrap();
@@ -192,30 +222,15 @@ async fn run_playback(
                 tokio::time::sleep(Duration::from_millis((ptc_time_passed - time_passed).num_milliseconds() as u64)).await;
             }
 
-            let transformed_points: Vec<_> = pointcloud
-                .points
-                .iter()
-                .map(|pt| Point {
-                    x: pt.x * if descriptor.label_filter == "" {-1.0} else {1.0},
-                    // z: pt.z * if descriptor.label_filter == "zed" {-1.0} else {1.0},
-                    ..*pt
-                })
-                .map(|pt| descriptor.transform.apply(pt.into()).into())
-                .collect();
-
-            let transformed_pointcloud = PointCloud {
-                points: transformed_points,
-                ..pointcloud
-            };
-
-            let message = Message {
-                content: MessageContent::PointCloud(transformed_pointcloud),
-                tags: vec![Tag::Pointcloud, Tag::FromId(id)],
-                timestamp: chrono::Utc::now(),
-            };
-            let subject = message.tags.clone().to_subject();
-            let payload = bincode::serialize(&message)?.into();
-            client.publish(subject, payload).await?;
+            let mut pointcloud = pointcloud;
+            if descriptor.label_filter.is_empty() {
+                for pt in pointcloud.points.iter_mut() {
+                    pt.x = -pt.x;
+                }
+            }
+            transform.apply_to_cloud(&mut pointcloud);
+
+            publisher.publish(pointcloud.with_heights(&floor)).await?;
             yield_now().await;
         }
 
@@ -224,17 +239,43 @@ async fn run_playback(
             config = entries.next() => {
                 if let Some(config) = config {
                     let entry = config?;
+                    let configuration = serde_json::from_slice::<Configuration>(&entry.value).ok();
                     if let Err(e) = maintain_config(entry, &mut descriptor, id) {
                         error!(error=?e, "Failed to maintain config");
                     }
+                    publisher.set_encoding(descriptor.encoding);
+                    if let Some(configuration) = configuration {
+                        transform = configuration.sensor_to_world_or(id, &descriptor.transform);
+                        floor = configuration.floor;
+                    }
                 }
             }
         }
     }
 
+    publisher
+        .status(DeviceState::Stopped, descriptor.title(), Some("Reached end of recording".to_owned()))
+        .await?;
     Ok(())
 }
 
+/// Whether a recorded pointcloud passes the label filter. Older recordings carry
+/// string labels per point, newer ones identify the source device per point.
+fn matches_filter(pointcloud: &PointCloud, label_filter: &str) -> bool {
+    if label_filter.is_empty() {
+        return pointcloud.labels.is_empty() && pointcloud.attributes.source.is_empty();
+    }
+    pointcloud
+        .labels
+        .iter()
+        .any(|label| label.contains(label_filter))
+        || pointcloud
+            .attributes
+            .source
+            .iter()
+            .any(|id| id.to_string().contains(label_filter))
+}
+
 fn maintain_config(
     entry: Entry,
     descriptor: &mut PlaybackDescriptor,
@@ -277,6 +318,11 @@ fn maintain_config(
             info!("Updated playback descriptor transform");
             descriptor.transform = updated_desc.transform.clone();
         }
+
+        if descriptor.encoding != updated_desc.encoding {
+            info!("Updated playback descriptor encoding");
+            descriptor.encoding = updated_desc.encoding;
+        }
     }
 
     Ok(())
@@ -0,0 +1,411 @@
+use async_nats::{
+    connection::State,
+    jetstream::{
+        self,
+        kv::{Entry, Store, Watch},
+    },
+    Client,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use egui::Ui;
+use futures::StreamExt;
+use mmwave_core::{
+    address::ServerAddress,
+    config::Configuration,
+    devices::DeviceDescriptor,
+    message::Id,
+    nats::{get_config, get_store},
+    pointcloud::PointCloud,
+    posture::{classify_height, skeletons, PostureMonitor, PostureParams, Skeleton},
+    pubsub::{Publisher, Subscription},
+    subject::SubjectFilter,
+    telemetry::{DeviceState, TrackState, TrackedObjects},
+    wire::Encoding,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    time::Duration,
+};
+use tokio::{select, task::yield_now};
+use tracing::{error, info, instrument, warn};
+
+/// Skeletons and tracks older than this are not matched with each other
+const MATCH_AGE_MS: i64 = 1000;
+/// Objects not seen for this long are forgotten
+const FORGET_AGE_S: i64 = 10;
+
+/// Classifies the posture of tracks and Zed skeletons and detects falls,
+/// publishing an event when a posture changes and a critical alert on a fall.
+/// Tracks near a skeleton take their posture from it, and skeletons near a
+/// track are otherwise left to it.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PostureDescriptor {
+    pub sources: Vec<Id>,      // Trackers and Zeds to watch, all of them if empty
+    pub params: PostureParams, // Thresholds for this deployment
+    pub match_distance: f32,   // Largest horizontal distance from a track to its skeleton
+    pub encoding: Encoding,    // Encoding of published messages
+}
+
+impl Default for PostureDescriptor {
+    fn default() -> Self {
+        Self {
+            sources: Vec::new(),
+            params: PostureParams::default(),
+            match_distance: 0.5,
+            encoding: Encoding::default(),
+        }
+    }
+}
+
+impl Eq for PostureDescriptor {}
+
+impl Display for PostureDescriptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sources: Vec<String> = self.sources.iter().map(|id| id.to_string()).collect();
+        write!(f, "Posture ({})", sources.join(", "))
+    }
+}
+
+#[typetag::serde]
+#[async_trait]
+impl DeviceDescriptor for PostureDescriptor {
+    #[instrument(skip_all, fields(self=%self, id=%id))]
+    async fn init(self: Box<Self>, id: Id, address: ServerAddress) {
+        if let Err(e) = start_posture(*self, id, address).await {
+            error!(error=?e, "Posture closed with error");
+        }
+    }
+
+    fn clone_boxed(&self) -> Box<dyn DeviceDescriptor> {
+        Box::new(self.clone())
+    }
+
+    fn title(&self) -> String {
+        format!("{}", self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.label("Sources:");
+        let mut removed = None;
+        for (i, source) in self.sources.iter_mut().enumerate() {
+            ui.push_id(("source", i), |ui| {
+                ui.horizontal(|ui| {
+                    source.ui(ui);
+                    if ui.button("remove").clicked() {
+                        removed = Some(i);
+                    }
+                });
+            });
+        }
+        if let Some(i) = removed {
+            self.sources.remove(i);
+        }
+        if ui.button("add source").clicked() {
+            self.sources.push(Id::Device(0, 0));
+        }
+
+        let params = &mut self.params;
+        ui.horizontal(|ui| {
+            ui.label("Standing above (m):");
+            ui.add(
+                egui::DragValue::new(&mut params.standing_height)
+                    .speed(0.01)
+                    .clamp_range(0.0..=3.0),
+            );
+            ui.label("Lying below (m):");
+            ui.add(
+                egui::DragValue::new(&mut params.lying_height)
+                    .speed(0.01)
+                    .clamp_range(0.0..=3.0),
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.label("Lying torso angle (deg):");
+            ui.add(egui::DragValue::new(&mut params.lying_angle).clamp_range(0.0..=90.0));
+            ui.label("Sitting thigh angle (deg):");
+            ui.add(egui::DragValue::new(&mut params.sitting_angle).clamp_range(0.0..=90.0));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Fall drop (m):");
+            ui.add(
+                egui::DragValue::new(&mut params.fall_drop)
+                    .speed(0.01)
+                    .clamp_range(0.0..=3.0),
+            );
+            ui.label("Fall speed (m/s):");
+            ui.add(
+                egui::DragValue::new(&mut params.fall_speed)
+                    .speed(0.01)
+                    .clamp_range(0.0..=10.0),
+            );
+            ui.label("Fall window (s):");
+            ui.add(
+                egui::DragValue::new(&mut params.fall_window_s)
+                    .speed(0.01)
+                    .clamp_range(0.1..=10.0),
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.label("Match distance (m):");
+            ui.add(
+                egui::DragValue::new(&mut self.match_distance)
+                    .speed(0.01)
+                    .clamp_range(0.0..=10.0),
+            );
+        });
+        self.encoding.ui(ui);
+    }
+}
+
+#[instrument(skip_all)]
+async fn start_posture(
+    mut descriptor: PostureDescriptor,
+    id: Id,
+    address: ServerAddress,
+) -> Result<(), Box<dyn Error>> {
+    // Connect to the NATS server
+    let client = async_nats::connect(address.address().to_string()).await?;
+    let jetstream = jetstream::new(client.clone());
+
+    // Listen for config updates on a separate task
+    let store = get_store(jetstream).await?;
+    let mut entries = store.watch("config").await?;
+
+    let mut interval = tokio::time::interval(Duration::from_millis(5000));
+    loop {
+        // Verify the client connection state
+        if client.connection_state() == State::Disconnected {
+            return Err(String::from("Lost connection to NATS").into());
+        }
+
+        let result = run_posture(&client, &store, &mut entries, &mut descriptor, id)
+            .await
+            .map_err(|e| e.to_string());
+        if let Err(e) = result {
+            error!(error=%e, "Posture stopped running");
+            let publisher = Publisher::new(client.clone(), id);
+            if let Err(e) = publisher.error(descriptor.title(), e).await {
+                warn!(error=%e, "Failed to report posture error");
+            }
+        }
+        interval.tick().await;
+    }
+}
+
+/// A device a message came from and the source its tracks were observed by,
+/// as a tracker publishes the tracks of each of its sources separately
+type Stream = (Id, Option<Id>);
+
+/// The latest tracks and skeletons of each source, for matching them up
+#[derive(Default)]
+struct Latest {
+    tracks: HashMap<Stream, (DateTime<Utc>, Vec<[f32; 3]>)>,
+    skeletons: HashMap<Id, (DateTime<Utc>, Vec<Skeleton>)>,
+}
+
+impl Latest {
+    fn skeleton_near(
+        &self,
+        time: DateTime<Utc>,
+        position: [f32; 3],
+        max: f32,
+    ) -> Option<&Skeleton> {
+        self.skeletons
+            .values()
+            .filter(|(t, _)| (time - *t).num_milliseconds().abs() <= MATCH_AGE_MS)
+            .flat_map(|(_, skeletons)| skeletons.iter())
+            .filter_map(|skeleton| {
+                Some((
+                    skeleton,
+                    horizontal_distance(skeleton.position()?, position),
+                ))
+            })
+            .filter(|&(_, distance)| distance <= max)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(skeleton, _)| skeleton)
+    }
+
+    fn has_track_near(&self, time: DateTime<Utc>, position: [f32; 3], max: f32) -> bool {
+        self.tracks
+            .values()
+            .filter(|(t, _)| (time - *t).num_milliseconds().abs() <= MATCH_AGE_MS)
+            .flat_map(|(_, tracks)| tracks.iter())
+            .any(|&track| horizontal_distance(track, position) <= max)
+    }
+}
+
+fn horizontal_distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2)).sqrt()
+}
+
+#[instrument(skip_all)]
+async fn run_posture(
+    client: &Client,
+    store: &Store,
+    entries: &mut Watch,
+    descriptor: &mut PostureDescriptor,
+    id: Id,
+) -> Result<(), Box<dyn Error>> {
+    let mut publisher = Publisher::new(client.clone(), id).with_encoding(descriptor.encoding);
+    publisher
+        .status(DeviceState::Running, descriptor.title(), None)
+        .await?;
+
+    let mut tracks = Subscription::<TrackedObjects>::new(client, SubjectFilter::all()).await?;
+    let mut pointclouds = Subscription::<PointCloud>::new(client, SubjectFilter::all()).await?;
+    // One monitor per stream, as track ids are only unique within a stream
+    let mut monitors: HashMap<Stream, PostureMonitor> = HashMap::new();
+    let mut latest = Latest::default();
+    // Heights are measured from the site floor
+    let mut floor = get_config(store)
+        .await?
+        .map(|c| c.floor)
+        .unwrap_or_default();
+
+    loop {
+        yield_now().await;
+        let (time, stream, events) = select! {
+            Some(config) = entries.next() => {
+                let config = config?;
+                let previous = (descriptor.params, floor);
+                if let Ok(configuration) = serde_json::from_slice::<Configuration>(&config.value) {
+                    floor = configuration.floor;
+                }
+                maintain_config(config, descriptor, id)?;
+                publisher.set_encoding(descriptor.encoding);
+                if (descriptor.params, floor) != previous {
+                    info!("Restarting posture monitors with new thresholds or floor");
+                    monitors.clear();
+                }
+                continue;
+            }
+            received = tracks.next() => {
+                let Some(received) = received else {
+                    return Err("Tracks subscription closed".into());
+                };
+                let Some(from) = source(descriptor, received.from, id) else {
+                    continue;
+                };
+                let time = received.timestamp;
+                let params = descriptor.params;
+                let stream = (from, received.content.source);
+                // Fused tracks are named after their tracker
+                let observer = received.content.source.unwrap_or(from);
+                let monitor = monitors
+                    .entry(stream)
+                    .or_insert_with(|| PostureMonitor::new(params, Some(observer)));
+                let mut events = Vec::new();
+                let mut positions = Vec::new();
+                for object in received.content.objects.iter().filter(|o| o.state != TrackState::Tentative) {
+                    let height = floor.height(object.position);
+                    let estimate = latest
+                        .skeleton_near(time, object.position, descriptor.match_distance)
+                        .and_then(|skeleton| skeleton.classify(&params, &floor))
+                        .unwrap_or_else(|| classify_height(height, &params));
+                    events.extend(monitor.update(time, object.id, object.position, height, estimate));
+                    positions.push(object.position);
+                }
+                latest.tracks.insert(stream, (time, positions));
+                (time, stream, events)
+            }
+            received = pointclouds.next() => {
+                let Some(received) = received else {
+                    return Err("Pointcloud subscription closed".into());
+                };
+                let Some(from) = source(descriptor, received.from, id) else {
+                    continue;
+                };
+                // Only Zed clouds have skeletons
+                let found = skeletons(&received.content);
+                if found.is_empty() {
+                    continue;
+                }
+                let time = received.timestamp;
+                let params = descriptor.params;
+                let stream = (from, None);
+                let monitor = monitors
+                    .entry(stream)
+                    .or_insert_with(|| PostureMonitor::new(params, Some(from)));
+                let mut events = Vec::new();
+                for skeleton in found.iter() {
+                    let (Some(position), Some(head), Some(estimate)) = (
+                        skeleton.position(),
+                        skeleton.head_height(&floor),
+                        skeleton.classify(&params, &floor),
+                    ) else {
+                        continue;
+                    };
+                    if latest.has_track_near(time, position, descriptor.match_distance) {
+                        continue;
+                    }
+                    // Compared to the centre of a track, a head moves about twice as far
+                    events.extend(monitor.update(time, skeleton.body as u64, position, head / 2.0, estimate));
+                }
+                latest.skeletons.insert(from, (time, found));
+                (time, stream, events)
+            }
+        };
+
+        if let Some(monitor) = monitors.get_mut(&stream) {
+            monitor.forget_before(time - chrono::Duration::seconds(FORGET_AGE_S));
+        }
+        for mut event in events {
+            event.description = format!("{} (from {})", event.description, stream.0);
+            publisher.publish(event).await?;
+        }
+    }
+}
+
+/// The device a message came from, if it is one of the sources
+fn source(descriptor: &PostureDescriptor, from: Option<Id>, id: Id) -> Option<Id> {
+    from.filter(|from| {
+        *from != id && (descriptor.sources.is_empty() || descriptor.sources.contains(from))
+    })
+}
+
+fn maintain_config(
+    entry: Entry,
+    descriptor: &mut PostureDescriptor,
+    id: Id,
+) -> Result<(), Box<dyn Error>> {
+    let Ok(configuration) = serde_json::from_slice::<Configuration>(&entry.value) else {
+        return Ok(());
+    };
+
+    for device_config in configuration.descriptors {
+        if device_config.id != id {
+            continue;
+        }
+
+        let erased_desc = device_config.device_descriptor.as_any();
+
+        let updated_desc = match erased_desc.downcast_ref::<PostureDescriptor>() {
+            Some(posture_desc) => posture_desc,
+            None => {
+                tracing::error!(
+                    "Failed to downcast: actual type id = {:?}, expected type id = {:?}",
+                    erased_desc.type_id(),
+                    TypeId::of::<Box<PostureDescriptor>>()
+                );
+                continue;
+            }
+        };
+
+        if descriptor != updated_desc {
+            info!("Updated posture descriptor");
+            *descriptor = updated_desc.clone();
+        }
+    }
+
+    Ok(())
+}
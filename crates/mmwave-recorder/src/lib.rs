@@ -13,9 +13,11 @@ use mmwave_core::{
     address::ServerAddress,
     config::Configuration,
     devices::DeviceDescriptor,
-    message::{Id, Message, MessageContent, Tag},
+    message::Id,
     nats::get_store,
     pointcloud::PointCloud,
+    pubsub::Subscription,
+    subject::SubjectFilter,
 };
 use serde::{Deserialize, Deserializer, Serialize};
 use std::{
@@ -177,7 +179,7 @@ async fn run_recording(
 ) -> Result<(), Box<dyn Error>> {
     let file = File::create(&descriptor.file_path)?;
     let mut writer = JsonArrayWriter::new(file);
-    let mut subscription = client.subscribe("Pointcloud.*").await?;
+    let mut subscription = Subscription::<PointCloud>::new(client, SubjectFilter::all()).await?;
 
     loop {
         yield_now().await;
@@ -188,11 +190,8 @@ async fn run_recording(
                     return Ok(());
                 }
             }
-            Some(message) = subscription.next() => {
-                let message: Message = bincode::deserialize(&message.payload)?;
-                if let MessageContent::PointCloud(pointcloud) = message.content {
-                    writer.write_element(&pointcloud)?;
-                }
+            Some(received) = subscription.next() => {
+                writer.write_element(&received.content)?;
             }
         }
     }
@@ -0,0 +1,247 @@
+use async_nats::{
+    connection::State,
+    jetstream::{
+        self,
+        kv::{Entry, Watch},
+    },
+    Client,
+};
+use async_trait::async_trait;
+use egui::Ui;
+use futures::StreamExt;
+use mmwave_core::{
+    address::ServerAddress,
+    config::Configuration,
+    devices::DeviceDescriptor,
+    message::Id,
+    nats::get_store,
+    pubsub::{Publisher, Subscription},
+    subject::SubjectFilter,
+    telemetry::{Clusters, DeviceState},
+    tracking::{Tracker, TrackerParams},
+    wire::Encoding,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    time::Duration,
+};
+use tokio::{select, task::yield_now};
+use tracing::{error, info, instrument, warn};
+
+/// Tracks the clusters published by cluster devices across frames,
+/// publishing tracks with ids that are stable for as long as they are seen.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct TrackerDescriptor {
+    pub sources: Vec<Id>,      // Cluster devices to track, all of them if empty
+    pub params: TrackerParams, // Parameters for the kalman filters and association
+    pub encoding: Encoding,    // Encoding of published messages
+}
+
+impl Eq for TrackerDescriptor {}
+
+impl Display for TrackerDescriptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sources: Vec<String> = self.sources.iter().map(|id| id.to_string()).collect();
+        write!(f, "Tracker ({})", sources.join(", "))
+    }
+}
+
+#[typetag::serde]
+#[async_trait]
+impl DeviceDescriptor for TrackerDescriptor {
+    #[instrument(skip_all, fields(self=%self, id=%id))]
+    async fn init(self: Box<Self>, id: Id, address: ServerAddress) {
+        if let Err(e) = start_tracker(*self, id, address).await {
+            error!(error=?e, "Tracker closed with error");
+        }
+    }
+
+    fn clone_boxed(&self) -> Box<dyn DeviceDescriptor> {
+        Box::new(self.clone())
+    }
+
+    fn title(&self) -> String {
+        format!("{}", self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.label("Sources:");
+        let mut removed = None;
+        for (i, source) in self.sources.iter_mut().enumerate() {
+            ui.push_id(("source", i), |ui| {
+                ui.horizontal(|ui| {
+                    source.ui(ui);
+                    if ui.button("remove").clicked() {
+                        removed = Some(i);
+                    }
+                });
+            });
+        }
+        if let Some(i) = removed {
+            self.sources.remove(i);
+        }
+        if ui.button("add source").clicked() {
+            self.sources.push(Id::Device(0, 0));
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Process noise (m/s^2):");
+            ui.add(
+                egui::DragValue::new(&mut self.params.process_noise)
+                    .speed(0.01)
+                    .clamp_range(0.0..=100.0),
+            );
+            ui.label("Measurement noise (m):");
+            ui.add(
+                egui::DragValue::new(&mut self.params.measurement_noise)
+                    .speed(0.01)
+                    .clamp_range(0.001..=10.0),
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.label("Gate:");
+            ui.add(
+                egui::DragValue::new(&mut self.params.gate)
+                    .speed(0.1)
+                    .clamp_range(0.0..=1000.0),
+            );
+            ui.label("Confirm hits:");
+            ui.add(egui::DragValue::new(&mut self.params.confirm_hits).clamp_range(1..=100));
+            ui.label("Max misses:");
+            ui.add(egui::DragValue::new(&mut self.params.max_misses).clamp_range(0..=1000));
+        });
+        self.encoding.ui(ui);
+    }
+}
+
+#[instrument(skip_all)]
+async fn start_tracker(
+    mut descriptor: TrackerDescriptor,
+    id: Id,
+    address: ServerAddress,
+) -> Result<(), Box<dyn Error>> {
+    // Connect to the NATS server
+    let client = async_nats::connect(address.address().to_string()).await?;
+    let jetstream = jetstream::new(client.clone());
+
+    // Listen for config updates on a separate task
+    let store = get_store(jetstream).await?;
+    let mut entries = store.watch("config").await?;
+
+    let mut interval = tokio::time::interval(Duration::from_millis(5000));
+    loop {
+        // Verify the client connection state
+        if client.connection_state() == State::Disconnected {
+            return Err(String::from("Lost connection to NATS").into());
+        }
+
+        let result = run_tracker(&client, &mut entries, &mut descriptor, id)
+            .await
+            .map_err(|e| e.to_string());
+        if let Err(e) = result {
+            error!(error=%e, "Tracker stopped running");
+            let publisher = Publisher::new(client.clone(), id);
+            if let Err(e) = publisher.error(descriptor.title(), e).await {
+                warn!(error=%e, "Failed to report tracker error");
+            }
+        }
+        interval.tick().await;
+    }
+}
+
+#[instrument(skip_all)]
+async fn run_tracker(
+    client: &Client,
+    entries: &mut Watch,
+    descriptor: &mut TrackerDescriptor,
+    id: Id,
+) -> Result<(), Box<dyn Error>> {
+    let mut publisher = Publisher::new(client.clone(), id).with_encoding(descriptor.encoding);
+    publisher
+        .status(DeviceState::Running, descriptor.title(), None)
+        .await?;
+    let mut subscription = Subscription::<Clusters>::new(client, SubjectFilter::all()).await?;
+    // One tracker per clustered stream
+    let mut trackers: HashMap<(Id, Option<Id>), Tracker> = HashMap::new();
+
+    loop {
+        yield_now().await;
+        select! {
+            Some(config) = entries.next() => {
+                let previous = descriptor.params;
+                maintain_config(config?, descriptor, id)?;
+                publisher.set_encoding(descriptor.encoding);
+                if descriptor.params != previous {
+                    info!("Restarting tracks with new parameters");
+                    trackers.clear();
+                }
+            }
+            received = subscription.next() => {
+                let Some(received) = received else {
+                    return Err("Clusters subscription closed".into());
+                };
+                let Some(from) = received.from.filter(|from| {
+                    descriptor.sources.is_empty() || descriptor.sources.contains(from)
+                }) else {
+                    continue;
+                };
+                let clusters = received.content;
+                let detections: Vec<[f32; 3]> =
+                    clusters.clusters.iter().map(|c| c.centroid).collect();
+                let tracker = trackers
+                    .entry((from, clusters.source))
+                    .or_insert_with(|| Tracker::new(descriptor.params));
+                tracker.update(clusters.time, &detections);
+                publisher
+                    .publish(tracker.tracked_objects(clusters.source))
+                    .await?;
+            }
+        }
+    }
+}
+
+fn maintain_config(
+    entry: Entry,
+    descriptor: &mut TrackerDescriptor,
+    id: Id,
+) -> Result<(), Box<dyn Error>> {
+    let Ok(configuration) = serde_json::from_slice::<Configuration>(&entry.value) else {
+        return Ok(());
+    };
+
+    for device_config in configuration.descriptors {
+        if device_config.id != id {
+            continue;
+        }
+
+        let erased_desc = device_config.device_descriptor.as_any();
+
+        let updated_desc = match erased_desc.downcast_ref::<TrackerDescriptor>() {
+            Some(tracker_desc) => tracker_desc,
+            None => {
+                tracing::error!(
+                    "Failed to downcast: actual type id = {:?}, expected type id = {:?}",
+                    erased_desc.type_id(),
+                    TypeId::of::<Box<TrackerDescriptor>>()
+                );
+                continue;
+            }
+        };
+
+        if descriptor != updated_desc {
+            info!("Updated tracker descriptor");
+            *descriptor = updated_desc.clone();
+        }
+    }
+
+    Ok(())
+}
@@ -15,27 +15,34 @@ use mmwave_core::{
     address::ServerAddress,
     config::Configuration,
     devices::DeviceDescriptor,
-    message::{Id, Message, Tag, TagsToSubject},
-    nats::get_store,
+    ground::Plane,
+    message::Id,
+    nats::{get_config, get_store},
     point::Point,
+    pointcloud::PointCloud,
+    pubsub::Publisher,
+    telemetry::DeviceState,
     transform::Transform,
+    wire::Encoding,
 };
 use serde::{Deserialize, Serialize};
 use std::{any::Any, fmt::Display, time::Duration};
 use tokio::{select, task::yield_now};
-use tracing::{error, info, instrument};
+use tracing::{error, info, instrument, warn};
 use zed::Zed;
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ZedDescriptor {
     pub transform: Transform, // Transform of this Zed device
+    #[serde(default)]
+    pub encoding: Encoding, // Encoding of published messages
 }
 
 impl Eq for ZedDescriptor {}
 
 impl PartialEq for ZedDescriptor {
     fn eq(&self, other: &Self) -> bool {
-        self.transform == other.transform
+        self.transform == other.transform && self.encoding == other.encoding
     }
 }
 
@@ -69,12 +76,17 @@ impl DeviceDescriptor for ZedDescriptor {
 
     fn ui(&mut self, ui: &mut Ui) {
         self.transform.ui(ui);
+        self.encoding.ui(ui);
     }
 
     fn transform(&self) -> Option<Transform> {
         Some(self.transform.clone())
     }
 
+    fn set_transform(&mut self, transform: Transform) {
+        self.transform = transform;
+    }
+
     fn position(&self) -> Option<Point> {
         Some(self.transform.apply([0.0, 0.0, 0.0].into()).into())
     }
@@ -101,16 +113,22 @@ async fn start_zed(
             return Err(String::from("lost connection to nats").into());
         }
 
-        if let Err(e) = run_zed(
+        let result = run_zed(
             &client,
             &store,
             &mut entries,
             descriptor.clone(),
             id,
             address,
-        ).await
-        {
+        )
+        .await
+        .map_err(|e| e.to_string());
+        if let Err(e) = result {
             error!(error=%e, "zed stopped running");
+            let publisher = Publisher::new(client.clone(), id);
+            if let Err(e) = publisher.error(descriptor.title(), e).await {
+                warn!(error=%e, "failed to report zed error");
+            }
         }
         interval.tick().await;
     }
@@ -128,16 +146,36 @@ async fn run_zed(
     // Create a Zed camera instance
     let mut zed = Zed::new();
 
+    let mut publisher = Publisher::new(client.clone(), id).with_encoding(descriptor.encoding);
+    publisher
+        .status(DeviceState::Running, descriptor.title(), None)
+        .await?;
+
+    // Sensor to world, resolved through the configured frame tree
+    let configuration = get_config(store).await?;
+    let mut transform = match &configuration {
+        Some(configuration) => configuration.sensor_to_world_or(id, &descriptor.transform),
+        None => descriptor.transform.clone(),
+    };
+    let mut floor = configuration.map(|c| c.floor).unwrap_or_default();
+
     loop {
         yield_now().await;
         select! {
             Some(config) = entries.next() => {
-                if let Err(()) = maintain_config(config?, &mut descriptor, id.clone()) {
+                let config = config?;
+                let configuration = serde_json::from_slice::<Configuration>(&config.value).ok();
+                if let Err(()) = maintain_config(config, &mut descriptor, id.clone()) {
                     info!("updating zed device transform");
                     // No need to restart the Zed device, just update the transform
                 }
+                publisher.set_encoding(descriptor.encoding);
+                if let Some(configuration) = configuration {
+                    transform = configuration.sensor_to_world_or(id, &descriptor.transform);
+                    floor = configuration.floor;
+                }
             }
-            result = maintain_connection(&mut zed, client, id.clone(), descriptor.transform.clone()) => {
+            result = maintain_connection(&mut zed, &publisher, transform.clone(), &floor) => {
                 match result {
                     Ok(_) => {  },
                     Err(e) => {
@@ -152,31 +190,25 @@ async fn run_zed(
 
 async fn maintain_connection(
     zed: &mut Zed,
-    client: &Client,
-    id: Id,
+    publisher: &Publisher,
     transform: Transform,
+    floor: &Plane,
 ) -> Result<(), Box<dyn std::error::Error>> {
     yield_now().await;
     if let Some(message) = zed.try_read() {
-        let points: Vec<(usize, Point)> = message.bodies.iter().enumerate().flat_map(|(i, body)| {
-                body.keypoints.iter().map(|&pt| (i, transform.apply(pt.into()).into())).collect::<Vec<(usize, Point)>>()}).collect();
-        let labels: Vec<String> = points.clone().iter().map(|(i, p)| format!("zedbody:{}", i)).collect();
-        let points = points.iter().map(|(i, p)| *p).collect();
-        // let labels = Vec::new();
-        let message = Message {
-            content: mmwave_core::message::MessageContent::PointCloud(
-                mmwave_core::pointcloud::PointCloud { 
-                    time: chrono::Utc::now(),
-                    points,
-                    labels
-                }
-            ),
-            tags: vec![Tag::Pointcloud, Tag::FromId(id)],
-            timestamp: chrono::Utc::now(),
-        };
-        let subject = message.tags.clone().to_subject();
-        let payload = bincode::serialize(&message)?.into();
-        client.publish(subject, payload).await?;
+        let mut pointcloud = PointCloud::default();
+        for (i, body) in message.bodies.iter().enumerate() {
+            for (k, &pt) in body.keypoints.iter().enumerate() {
+                pointcloud.points.push(Into::<[f32; 3]>::into(pt).into());
+                pointcloud.attributes.track_id.push(i as u32);
+                pointcloud.attributes.keypoint.push(k as u16);
+            }
+        }
+        let mut pointcloud = pointcloud
+            .with_source(publisher.id())
+            .with_origin(Some(publisher.id()));
+        transform.apply_to_cloud(&mut pointcloud);
+        publisher.publish(pointcloud.with_heights(floor)).await?;
     }
     Ok(())
 }
@@ -209,6 +241,11 @@ fn maintain_config(entry: Entry, descriptor: &mut ZedDescriptor, id: Id) -> Resu
             info!("Updated Zed descriptor transform");
             descriptor.transform = updated_desc.transform.clone();
         }
+
+        if descriptor.encoding != updated_desc.encoding {
+            info!("Updated Zed descriptor encoding");
+            descriptor.encoding = updated_desc.encoding;
+        }
     }
 
     Ok(())
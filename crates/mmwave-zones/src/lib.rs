@@ -0,0 +1,341 @@
+use async_nats::{
+    connection::State,
+    jetstream::{
+        self,
+        kv::{Entry, Watch},
+    },
+    Client,
+};
+use async_trait::async_trait;
+use egui::Ui;
+use futures::StreamExt;
+use mmwave_core::{
+    address::ServerAddress,
+    config::Configuration,
+    devices::DeviceDescriptor,
+    message::{Id, Tag},
+    nats::get_store,
+    pointcloud::PointCloud,
+    pubsub::{Content, Publisher, Received, Subscription},
+    subject::SubjectFilter,
+    telemetry::{DeviceState, Event, TrackedObjects},
+    wire::Encoding,
+    zones::ZoneMonitor,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    time::Duration,
+};
+use tokio::{select, task::yield_now};
+use tracing::{error, info, instrument, warn};
+
+/// What is checked against the zones
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize, Default)]
+pub enum ZoneInput {
+    #[default]
+    Tracks, // Tracks of tracker devices, each an occupant
+    Pointclouds, // Points of ordinary devices, present when there are enough
+    Fused,       // Points of fusion devices, present when there are enough
+    Filtered,    // Points of filter devices, present when there are enough
+}
+
+impl ZoneInput {
+    /// The kind of message the input is published as
+    fn kind(self) -> Tag {
+        match self {
+            ZoneInput::Tracks => Tag::Tracks,
+            ZoneInput::Pointclouds => Tag::Pointcloud,
+            ZoneInput::Fused => Tag::Fused,
+            ZoneInput::Filtered => Tag::Filtered,
+        }
+    }
+}
+
+/// Checks the tracks (or points) of the source devices against the zones of
+/// the configuration, publishing an event whenever something enters, exits or
+/// dwells in a zone and whenever the number of occupants of a zone changes.
+/// Each source is monitored separately.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ZoneDescriptor {
+    pub sources: Vec<Id>,   // Devices to monitor, all of them if empty
+    pub input: ZoneInput,   // What to monitor from them
+    pub min_points: usize,  // Points needed inside a zone for it to be occupied
+    pub encoding: Encoding, // Encoding of published messages
+}
+
+impl Default for ZoneDescriptor {
+    fn default() -> Self {
+        Self {
+            sources: Vec::new(),
+            input: ZoneInput::default(),
+            min_points: 3,
+            encoding: Encoding::default(),
+        }
+    }
+}
+
+impl Eq for ZoneDescriptor {}
+
+impl Display for ZoneDescriptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Zones ({:?})", self.input)
+    }
+}
+
+#[typetag::serde]
+#[async_trait]
+impl DeviceDescriptor for ZoneDescriptor {
+    #[instrument(skip_all, fields(self=%self, id=%id))]
+    async fn init(self: Box<Self>, id: Id, address: ServerAddress) {
+        if let Err(e) = start_zones(*self, id, address).await {
+            error!(error=?e, "Zones closed with error");
+        }
+    }
+
+    fn clone_boxed(&self) -> Box<dyn DeviceDescriptor> {
+        Box::new(self.clone())
+    }
+
+    fn title(&self) -> String {
+        format!("{}", self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.label("Sources:");
+        let mut removed = None;
+        for (i, source) in self.sources.iter_mut().enumerate() {
+            ui.push_id(("source", i), |ui| {
+                ui.horizontal(|ui| {
+                    source.ui(ui);
+                    if ui.button("remove").clicked() {
+                        removed = Some(i);
+                    }
+                });
+            });
+        }
+        if let Some(i) = removed {
+            self.sources.remove(i);
+        }
+        if ui.button("add source").clicked() {
+            self.sources.push(Id::Device(0, 0));
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Input:");
+            egui::ComboBox::from_id_source(ui.make_persistent_id("zones_input"))
+                .selected_text(format!("{:?}", self.input))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.input, ZoneInput::Tracks, "Tracks");
+                    ui.selectable_value(&mut self.input, ZoneInput::Pointclouds, "Pointclouds");
+                    ui.selectable_value(&mut self.input, ZoneInput::Fused, "Fused");
+                    ui.selectable_value(&mut self.input, ZoneInput::Filtered, "Filtered");
+                });
+            ui.label("Min points:");
+            ui.add(egui::DragValue::new(&mut self.min_points).clamp_range(1..=1000));
+        });
+        self.encoding.ui(ui);
+    }
+}
+
+#[instrument(skip_all)]
+async fn start_zones(
+    mut descriptor: ZoneDescriptor,
+    id: Id,
+    address: ServerAddress,
+) -> Result<(), Box<dyn Error>> {
+    // Connect to the NATS server
+    let client = async_nats::connect(address.address().to_string()).await?;
+    let jetstream = jetstream::new(client.clone());
+
+    // Listen for config updates on a separate task
+    let store = get_store(jetstream).await?;
+    let mut entries = store.watch("config").await?;
+
+    // The zones are only in the configuration, so start from the current one
+    let mut configuration = store
+        .get("config")
+        .await?
+        .and_then(|value| serde_json::from_slice::<Configuration>(&value).ok())
+        .unwrap_or_default();
+
+    let mut interval = tokio::time::interval(Duration::from_millis(5000));
+    loop {
+        // Verify the client connection state
+        if client.connection_state() == State::Disconnected {
+            return Err(String::from("Lost connection to NATS").into());
+        }
+
+        let result = run_zones(
+            &client,
+            &mut entries,
+            &mut descriptor,
+            &mut configuration,
+            id,
+        )
+        .await
+        .map_err(|e| e.to_string());
+        if let Err(e) = result {
+            error!(error=%e, "Zones stopped running");
+            let publisher = Publisher::new(client.clone(), id);
+            if let Err(e) = publisher.error(descriptor.title(), e).await {
+                warn!(error=%e, "Failed to report zones error");
+            }
+        }
+        interval.tick().await;
+    }
+}
+
+#[instrument(skip_all)]
+async fn run_zones(
+    client: &Client,
+    entries: &mut Watch,
+    descriptor: &mut ZoneDescriptor,
+    configuration: &mut Configuration,
+    id: Id,
+) -> Result<(), Box<dyn Error>> {
+    let mut publisher = Publisher::new(client.clone(), id).with_encoding(descriptor.encoding);
+    publisher
+        .status(DeviceState::Running, descriptor.title(), None)
+        .await?;
+    if configuration.zones.is_empty() {
+        warn!("No zones are configured");
+    }
+
+    // Only the monitored input is subscribed to, changing it restarts the zones
+    let (mut tracks, mut points) = match descriptor.input {
+        ZoneInput::Tracks => {
+            let tracks = Subscription::<TrackedObjects>::new(client, SubjectFilter::all()).await?;
+            (Some(tracks), None)
+        }
+        _ => {
+            let kind = descriptor.input.kind();
+            let points =
+                Subscription::<PointCloud>::with_kind(client, SubjectFilter::all(), kind).await?;
+            (None, Some(points))
+        }
+    };
+    // Zones in unknown frames are reported, but must not stop config updates
+    let zones = match ZoneMonitor::new(configuration) {
+        Ok(zones) => zones,
+        Err(e) => {
+            publisher.error(descriptor.title(), e.to_string()).await?;
+            ZoneMonitor::default()
+        }
+    };
+    let mut monitors: HashMap<Id, ZoneMonitor> = HashMap::new();
+
+    loop {
+        yield_now().await;
+        let events = select! {
+            Some(config) = entries.next() => {
+                let config = config?;
+                let previous = descriptor.clone();
+                maintain_config(config.clone(), descriptor, id)?;
+                publisher.set_encoding(descriptor.encoding);
+                let Ok(updated) = serde_json::from_slice::<Configuration>(&config.value) else {
+                    continue;
+                };
+                let changed = (&updated.zones, &updated.frames) != (&configuration.zones, &configuration.frames);
+                *configuration = updated;
+                if changed || descriptor.input != previous.input {
+                    info!("Restarting zones with new configuration");
+                    return Ok(());
+                }
+                continue;
+            }
+            received = next(&mut tracks) => {
+                let Some(received) = received else {
+                    return Err("Tracks subscription closed".into());
+                };
+                let Some(from) = source(descriptor, received.from, id) else {
+                    continue;
+                };
+                let monitor = monitors.entry(from).or_insert_with(|| zones.clone());
+                monitor.update_tracks(received.timestamp, &received.content)
+            }
+            received = next(&mut points) => {
+                let Some(received) = received else {
+                    return Err(format!("{:?} subscription closed", descriptor.input).into());
+                };
+                let Some(from) = source(descriptor, received.from, id) else {
+                    continue;
+                };
+                let monitor = monitors.entry(from).or_insert_with(|| zones.clone());
+                update_points(monitor, &received.content, descriptor.min_points)
+            }
+        };
+        for event in events {
+            publisher.publish(event).await?;
+        }
+    }
+}
+
+/// The next message of a subscription, never if there is none
+async fn next<T: Content>(subscription: &mut Option<Subscription<T>>) -> Option<Received<T>> {
+    match subscription {
+        Some(subscription) => subscription.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// The device a message came from, if it is one of the sources
+fn source(descriptor: &ZoneDescriptor, from: Option<Id>, id: Id) -> Option<Id> {
+    from.filter(|from| {
+        *from != id && (descriptor.sources.is_empty() || descriptor.sources.contains(from))
+    })
+}
+
+fn update_points(
+    monitor: &mut ZoneMonitor,
+    pointcloud: &PointCloud,
+    min_points: usize,
+) -> Vec<Event> {
+    let points: Vec<[f32; 3]> = pointcloud.points.iter().map(|p| [p.x, p.y, p.z]).collect();
+    monitor.update_points(pointcloud.time, &points, min_points)
+}
+
+fn maintain_config(
+    entry: Entry,
+    descriptor: &mut ZoneDescriptor,
+    id: Id,
+) -> Result<(), Box<dyn Error>> {
+    let Ok(configuration) = serde_json::from_slice::<Configuration>(&entry.value) else {
+        return Ok(());
+    };
+
+    for device_config in configuration.descriptors {
+        if device_config.id != id {
+            continue;
+        }
+
+        let erased_desc = device_config.device_descriptor.as_any();
+
+        let updated_desc = match erased_desc.downcast_ref::<ZoneDescriptor>() {
+            Some(zone_desc) => zone_desc,
+            None => {
+                tracing::error!(
+                    "Failed to downcast: actual type id = {:?}, expected type id = {:?}",
+                    erased_desc.type_id(),
+                    TypeId::of::<Box<ZoneDescriptor>>()
+                );
+                continue;
+            }
+        };
+
+        if descriptor != updated_desc {
+            info!("Updated zone descriptor");
+            *descriptor = updated_desc.clone();
+        }
+    }
+
+    Ok(())
+}